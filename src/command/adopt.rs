@@ -0,0 +1,407 @@
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    registry::{ImageRef, DEFAULT_IMAGE},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, IMAGE_LABEL},
+    spec::InstanceSpec,
+};
+use bollard::{container::Config, models::ContainerInspectResponse};
+use clap::{ArgAction::SetTrue, Args};
+use console::style;
+use indicatif::HumanBytes;
+
+// Executes the `illa adopt` command to
+// bring a hand-made ILLA Builder container under management
+#[derive(Debug, Args)]
+/// Manage an ILLA Builder container that was not deployed by illa
+pub struct Cmd {
+    /// The existing ILLA Builder container
+    #[clap(value_name = "CONTAINER")]
+    container: String,
+
+    /// Recreate the container without asking, even when some of its state
+    /// cannot be carried over
+    #[clap(short = 'y', long = "yes", action = SetTrue)]
+    yes: bool,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let runtime = runtime::connect().await?;
+        if self.dry_run {
+            let planned = adopt_plan(&runtime, &self.container).await?;
+            if !ui.is_structured() {
+                print_losses(ui, &planned);
+            }
+            return planned.plan.show(ui, "adopt");
+        }
+        // The container being adopted is locked too, so no other run
+        // changes it while it is recreated
+        let report = lock::hold(&runtime, ui, "illa_builder", "adopt", self.wait, async {
+            let adopted = async {
+                let report = adopt_local(&runtime, ui, &self.container, self.yes).await?;
+                state::record(&runtime, ui, "illa_builder", "adopt").await;
+                Ok(report)
+            };
+            match self.container.as_str() {
+                "illa_builder" => adopted.await,
+                source => lock::hold(&runtime, ui, source, "adopt", self.wait, adopted).await,
+            }
+        })
+        .await?;
+        ui.emit(&report)
+    }
+}
+
+// What adopting a container takes: the steps, and the container they
+// start from
+struct AdoptPlan {
+    plan: Plan,
+    spec: InstanceSpec,
+    original: ContainerInspectResponse,
+    running: bool,
+    lost: Vec<String>,
+}
+
+// Labels cannot be added to an existing container, so the container is
+// recreated as `illa_builder` from its own config, pinned to the image it
+// runs. A Builder illa already manages needs no steps.
+async fn adopt_plan(runtime: &dyn ContainerRuntime, container: &str) -> Result<AdoptPlan> {
+    let original = runtime
+        .inspect(container, true)
+        .await
+        .map_err(|e| IllaError::container(container, e, IllaError::Runtime))?;
+    let spec = adoptable_spec(runtime, &original).await?;
+    let running = original
+        .state
+        .as_ref()
+        .and_then(|state| state.running)
+        .unwrap_or(false);
+    let mut plan = Plan::default();
+    if spec.name == "illa_builder" && spec.labels.contains_key(IMAGE_LABEL) {
+        return Ok(AdoptPlan {
+            plan,
+            spec,
+            original,
+            running,
+            lost: vec![],
+        });
+    }
+
+    let requested = ImageRef::parse(&spec.image)?;
+    let mut labels = spec.labels.clone();
+    labels.extend(utils::builder_labels(&requested, None));
+    plan.push(Action::VerifyImage {
+        image: spec.image.clone(),
+        requested,
+        check: Default::default(),
+    });
+    plan.push(Action::RemoveContainer {
+        name: spec.name.clone(),
+        force: true,
+    });
+    let builder_config = Config {
+        image: Some(spec.image.clone()),
+        labels: Some(labels),
+        ..utils::recreate_config(&original)
+    };
+    plan.push(Action::create_container("illa_builder", builder_config));
+    if running {
+        plan.push(Action::StartContainer {
+            name: String::from("illa_builder"),
+        });
+    }
+    Ok(AdoptPlan {
+        plan,
+        lost: losses(&original),
+        spec,
+        original,
+        running,
+    })
+}
+
+// What cannot be carried over has to be confirmed first. If recreating
+// fails, the original container is put back.
+async fn adopt_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    container: &str,
+    yes: bool,
+) -> Result<CommandReport> {
+    ui.println(format!(
+        "{} Trying to adopt the ILLA Builder {container}...",
+        ui::emoji::BUILD
+    ));
+
+    let progress = ui.progress();
+
+    let pb_setup = progress.step();
+    pb_setup.set_message("Reading the container configuration...");
+    let planned = match adopt_plan(runtime, container).await {
+        Ok(planned) => planned,
+        Err(e) => {
+            pb_setup.clear();
+            return Err(e);
+        }
+    };
+    let spec = &planned.spec;
+    if planned.plan.actions.is_empty() {
+        pb_setup.finish(format!(
+            "{} {} is already managed by illa",
+            ui::emoji::SUCCESS,
+            spec.name
+        ));
+        return Ok(CommandReport::success("adopt")
+            .instance("illa_builder")
+            .message(String::from("The ILLA Builder is already managed"))
+            .detail("url", spec.url()));
+    }
+    pb_setup.finish(format!("{} Setup complete", ui::emoji::SUCCESS));
+
+    if !planned.lost.is_empty() && !yes {
+        print_losses(ui, &planned);
+        ui.confirm(&format!("Recreate {} anyway?", spec.name))?;
+    }
+
+    let mut outcome = Outcome::default();
+    if let Err(e) = planned.plan.execute(runtime, &progress, &mut outcome).await {
+        if !outcome.removed.is_empty() {
+            utils::restore_container(runtime, "illa_builder", &planned.original, planned.running)
+                .await;
+        }
+        return Err(e);
+    }
+
+    let url = spec.url();
+    progress.step().finish(if planned.running {
+        format!(
+            "{} {} {}",
+            ui::emoji::SPARKLE,
+            String::from("ILLA Builder adopted, please visit"),
+            style(&url).blue(),
+        )
+    } else {
+        format!(
+            "{} ILLA Builder adopted; it is stopped as it was before",
+            ui::emoji::SUCCESS
+        )
+    });
+
+    Ok(CommandReport::success("adopt")
+        .instance("illa_builder")
+        .detail("adopted", &spec.name)
+        .detail("image", &spec.image)
+        .detail("digest", outcome.pinned.map(|pinned| pinned.digest))
+        .detail("lost", &planned.lost)
+        .detail("url", url))
+}
+
+fn print_losses(ui: &Ui, planned: &AdoptPlan) {
+    if planned.lost.is_empty() {
+        return;
+    }
+    ui.println(format!(
+        "{} Recreating {} as illa_builder loses:",
+        ui::emoji::WARN,
+        planned.spec.name
+    ));
+    for loss in &planned.lost {
+        ui.println(format!("  - {loss}"));
+    }
+}
+
+// What recreating the container `info` does not carry over
+fn losses(info: &ContainerInspectResponse) -> Vec<String> {
+    let mut lost: Vec<_> = utils::extra_networks(info)
+        .into_iter()
+        .map(|network| format!("its connection to the network {network}"))
+        .collect();
+    if let Some(size) = info.size_rw.filter(|size| *size > 0) {
+        lost.push(format!(
+            "{} of files written inside the container rather than to its volumes or \
+             data directory",
+            HumanBytes(size as u64)
+        ));
+    }
+    lost
+}
+
+// The spec of `info`, if it is a Builder container that can be adopted
+// without taking over the name of another Builder
+async fn adoptable_spec(
+    runtime: &dyn ContainerRuntime,
+    info: &ContainerInspectResponse,
+) -> Result<InstanceSpec> {
+    let spec = InstanceSpec::from_inspect(info)?;
+    let builder = ImageRef::parse(DEFAULT_IMAGE)?;
+    let is_builder = ImageRef::parse(&spec.image)
+        .map(|image| image.repository.rsplit('/').next() == builder.repository.rsplit('/').next())
+        .unwrap_or(false);
+    if !is_builder {
+        return Err(IllaError::InvalidArgument(format!(
+            "{} runs {}, which is not an ILLA Builder image",
+            spec.name, spec.image
+        )));
+    }
+    if spec.name != "illa_builder" && runtime.inspect("illa_builder", false).await.is_ok() {
+        return Err(IllaError::InvalidArgument(format!(
+            "illa already manages an ILLA Builder; remove it before adopting {}",
+            spec.name
+        )));
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{
+        fake::{builder_config, FakeRuntime, FAKE_DIGEST},
+        DIGEST_LABEL,
+    };
+    use bollard::{
+        container::NetworkingConfig,
+        models::{EndpointSettings, RestartPolicy, RestartPolicyNameEnum},
+    };
+    use std::collections::HashMap;
+
+    fn runtime_with_container(image: &str) -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_image(image);
+        runtime.add_container("my-illa", builder_config(image, 8080, "/data/illa"), true);
+        runtime
+    }
+
+    #[tokio::test]
+    async fn recreates_as_managed_builder() {
+        let runtime = runtime_with_container("illasoft/illa-builder:1.2.0");
+
+        let report = adopt_local(&runtime, &Ui::default(), "my-illa", true)
+            .await
+            .unwrap();
+
+        assert!(!runtime.has_container("my-illa"));
+        assert!(runtime.is_running("illa_builder"));
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let spec = InstanceSpec::from_inspect(&info).unwrap();
+        assert_eq!(spec.labels[IMAGE_LABEL], "illasoft/illa-builder:1.2.0");
+        assert_eq!(spec.labels[DIGEST_LABEL], FAKE_DIGEST);
+        assert_eq!(spec.env_value("POSTGRES_PASSWORD"), Some("secret"));
+        assert_eq!(
+            (spec.host_port, spec.data_dir.as_str()),
+            (8080, "/data/illa")
+        );
+        assert_eq!(report.details["adopted"], "my-illa");
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("illasoft/illa-builder:1.2.0@{FAKE_DIGEST}").as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn carries_over_what_the_spec_does_not_own() {
+        let runtime = FakeRuntime::new();
+        let image = "illasoft/illa-builder:1.2.0";
+        runtime.add_image(image);
+        let mut config = builder_config(image, 8080, "/data/illa");
+        config.cmd = Some(vec![String::from("--verbose")]);
+        config
+            .env
+            .as_mut()
+            .unwrap()
+            .push(String::from("TZ=Europe/Berlin"));
+        let host_config = config.host_config.as_mut().unwrap();
+        host_config.restart_policy = Some(RestartPolicy {
+            name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+            ..Default::default()
+        });
+        host_config.memory = Some(2 << 30);
+        host_config.network_mode = Some(String::from("illa-net"));
+        config.networking_config = Some(NetworkingConfig {
+            endpoints_config: HashMap::from([
+                (String::from("illa-net"), EndpointSettings::default()),
+                (String::from("monitoring"), EndpointSettings::default()),
+            ]),
+        });
+        runtime.add_container("my-illa", config, true);
+
+        let report = adopt_local(&runtime, &Ui::default(), "my-illa", true)
+            .await
+            .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let config = info.config.unwrap();
+        assert_eq!(config.cmd.unwrap(), ["--verbose"]);
+        assert!(config
+            .env
+            .unwrap()
+            .contains(&String::from("TZ=Europe/Berlin")));
+        let host_config = info.host_config.unwrap();
+        assert_eq!(
+            host_config.restart_policy.unwrap().name,
+            Some(RestartPolicyNameEnum::UNLESS_STOPPED)
+        );
+        assert_eq!(host_config.memory, Some(2 << 30));
+        assert_eq!(host_config.network_mode.as_deref(), Some("illa-net"));
+        let lost = report.details["lost"].as_array().unwrap();
+        assert!(lost[0].as_str().unwrap().contains("monitoring"), "{lost:?}");
+    }
+
+    #[tokio::test]
+    async fn losing_state_needs_confirmation() {
+        let runtime = runtime_with_container("illasoft/illa-builder:1.2.0");
+
+        let result = adopt_local(&runtime, &Ui::default(), "my-illa", false).await;
+
+        assert!(matches!(result, Err(IllaError::Aborted(_))), "{result:?}");
+        assert!(runtime.is_running("my-illa"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("remove")));
+    }
+
+    #[tokio::test]
+    async fn plan_leaves_the_container_alone() {
+        let runtime = runtime_with_container("illasoft/illa-builder:1.2.0");
+
+        let planned = adopt_plan(&runtime, "my-illa").await.unwrap();
+
+        assert_eq!(planned.plan.actions.len(), 4);
+        assert!(!planned.lost.is_empty());
+        assert!(runtime.is_running("my-illa"));
+        assert!(!runtime.has_container("illa_builder"));
+    }
+
+    #[tokio::test]
+    async fn refuses_other_images() {
+        let runtime = runtime_with_container("postgres:15");
+
+        let result = adopt_local(&runtime, &Ui::default(), "my-illa", true).await;
+
+        assert!(matches!(result, Err(IllaError::InvalidArgument(_))));
+        assert!(runtime.is_running("my-illa"));
+    }
+
+    #[tokio::test]
+    async fn failed_start_restores_the_original() {
+        let runtime = runtime_with_container("illasoft/illa-builder:1.2.0");
+        runtime.fail("start", "port is already allocated");
+
+        let result = adopt_local(&runtime, &Ui::default(), "my-illa", true).await;
+
+        assert!(matches!(result, Err(IllaError::ContainerStart(_))));
+        assert!(runtime.has_container("my-illa"));
+        assert!(!runtime.has_container("illa_builder"));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    registry::ImageRef,
+    result::Result,
+    runtime::{self, ContainerRuntime, DIGEST_LABEL},
+    spec::{DesiredSpec, Drift, InstanceSpec},
+};
+use bollard::{container::Config, models::ContainerInspectResponse};
+use clap::{ArgAction::SetTrue, Args};
+use console::style;
+
+// Executes the `illa apply` command to
+// converge an ILLA Builder to its spec
+#[derive(Debug, Args)]
+/// Recreate an ILLA Builder as its spec declares
+pub struct Cmd {
+    #[clap(flatten)]
+    desired: diff::Desired,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let runtime = runtime::connect().await?;
+        let desired = self.desired.load()?;
+        let name = &desired.spec.name;
+        if self.dry_run {
+            let planned = apply_plan(&runtime, &desired).await?;
+            if !planned.drift.is_empty() && !ui.is_structured() {
+                ui.println(format!("{name} differs from its spec in:"));
+                diff::print_drift(ui, &planned.drift);
+            }
+            return planned.plan.show(ui, "apply");
+        }
+        let report = lock::hold(&runtime, ui, name, "apply", self.wait, async {
+            let report = apply_local(&runtime, ui, &desired).await?;
+            state::record(&runtime, ui, name, "apply").await;
+            Ok(report)
+        })
+        .await?;
+        ui.emit(&report)
+    }
+}
+
+// What applying a spec takes: the steps, and the drift and container
+// they start from
+struct ApplyPlan {
+    plan: Plan,
+    drift: Vec<Drift>,
+    original: ContainerInspectResponse,
+    image: String,
+}
+
+// Settings of a container cannot be changed in place, so a drifted
+// container is recreated from the spec. Secrets the spec leaves out are
+// kept from the container, and the data directory is never touched.
+async fn apply_plan(runtime: &dyn ContainerRuntime, desired: &DesiredSpec) -> Result<ApplyPlan> {
+    let name = &desired.spec.name;
+    let (original, drift) = diff::compare(runtime, desired).await?;
+    let mut plan = Plan::default();
+    if drift.is_empty() {
+        return Ok(ApplyPlan {
+            plan,
+            drift,
+            original,
+            image: desired.spec.image.clone(),
+        });
+    }
+    let actual = InstanceSpec::from_inspect(&original)?;
+    let image = match &desired.digest {
+        Some(digest) => ImageRef {
+            digest: Some(digest.clone()),
+            ..ImageRef::parse(&desired.spec.image)?
+        }
+        .to_string(),
+        None => desired.spec.image.clone(),
+    };
+    let image = runtime.info().image_name(&image);
+
+    let mut env = desired.spec.env.clone();
+    let secrets = actual.secret_keys();
+    env.extend(
+        actual
+            .env
+            .iter()
+            .filter(|var| {
+                let key = var.split('=').next().unwrap_or_default();
+                secrets.iter().any(|secret| secret == key) && desired.spec.env_value(key).is_none()
+            })
+            .cloned(),
+    );
+    let mut labels = desired.spec.labels.clone();
+    if let Some(digest) = &desired.digest {
+        labels.insert(DIGEST_LABEL.to_string(), digest.clone());
+    }
+
+    if runtime.inspect_image(&image).await.is_err() {
+        plan.push(Action::PullImage {
+            image: image.clone(),
+        });
+    }
+    plan.push(Action::RemoveContainer {
+        name: name.clone(),
+        force: true,
+    });
+    let builder_config = Config {
+        image: Some(image.clone()),
+        env: Some(env),
+        labels: Some(labels),
+        host_config: Some(desired.spec.host_config(runtime.info())),
+        ..Default::default()
+    };
+    plan.push(Action::create_container(name, builder_config));
+    plan.push(Action::StartContainer { name: name.clone() });
+    Ok(ApplyPlan {
+        plan,
+        drift,
+        original,
+        image,
+    })
+}
+
+async fn apply_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    desired: &DesiredSpec,
+) -> Result<CommandReport> {
+    let name = &desired.spec.name;
+    let ApplyPlan {
+        plan,
+        drift,
+        original,
+        image,
+    } = apply_plan(runtime, desired).await?;
+    if drift.is_empty() {
+        ui.println(format!(
+            "{} {name} already matches its spec",
+            ui::emoji::SUCCESS
+        ));
+        return Ok(CommandReport::success("apply")
+            .instance(name)
+            .message(String::from("Nothing to change"))
+            .detail("changed", Vec::<String>::new()));
+    }
+    let running = original
+        .state
+        .as_ref()
+        .and_then(|state| state.running)
+        .unwrap_or(false);
+
+    ui.println(format!(
+        "{} Applying the spec to {name}, which differs in:",
+        ui::emoji::BUILD
+    ));
+    diff::print_drift(ui, &drift);
+
+    let progress = ui.progress();
+    let mut outcome = Outcome::default();
+    if let Err(e) = plan.execute(runtime, &progress, &mut outcome).await {
+        if outcome.removed.contains(name) {
+            utils::restore_container(runtime, name, &original, running).await;
+        }
+        return Err(e);
+    }
+
+    let url = desired.spec.url();
+    progress.step().finish(format!(
+        "{} {} {}",
+        ui::emoji::SPARKLE,
+        String::from("ILLA Builder matches its spec, please visit"),
+        style(&url).blue(),
+    ));
+    Ok(CommandReport::success("apply")
+        .instance(name)
+        .detail(
+            "changed",
+            drift.iter().map(|d| d.field.clone()).collect::<Vec<_>>(),
+        )
+        .detail("image", image)
+        .detail("url", url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+
+    async fn runtime_with_builder(port: u16) -> (FakeRuntime, DesiredSpec) {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", port, "/data/illa"),
+            true,
+        );
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let spec = InstanceSpec::from_inspect(&info).unwrap();
+        let desired = DesiredSpec {
+            spec: spec.without_secrets(),
+            digest: None,
+        };
+        (runtime, desired)
+    }
+
+    #[tokio::test]
+    async fn recreates_drifted_builder_keeping_secrets() {
+        let (runtime, mut desired) = runtime_with_builder(9090).await;
+        desired.spec.host_port = 8080;
+        desired.spec.limits.memory = Some(2 << 30);
+
+        let report = apply_local(&runtime, &Ui::default(), &desired)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            report.details["changed"],
+            serde_json::json!(["port", "limits.memory"])
+        );
+        assert!(runtime.is_running("illa_builder"));
+        let (_, drift) = diff::compare(&runtime, &desired).await.unwrap();
+        assert!(drift.is_empty(), "{drift:?}");
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let actual = InstanceSpec::from_inspect(&info).unwrap();
+        assert_eq!(actual.env_value("POSTGRES_PASSWORD"), Some("secret"));
+    }
+
+    #[tokio::test]
+    async fn matching_builder_is_left_alone() {
+        let (runtime, desired) = runtime_with_builder(8080).await;
+
+        apply_local(&runtime, &Ui::default(), &desired)
+            .await
+            .unwrap();
+
+        assert!(!runtime
+            .calls()
+            .iter()
+            .any(|call| call.starts_with("remove")));
+    }
+
+    #[tokio::test]
+    async fn plan_leaves_the_builder_alone() {
+        let (runtime, mut desired) = runtime_with_builder(9090).await;
+        desired.spec.host_port = 8080;
+
+        let planned = apply_plan(&runtime, &desired).await.unwrap();
+
+        assert_eq!(planned.drift.len(), 1);
+        assert!(matches!(
+            planned.plan.actions.as_slice(),
+            [
+                Action::RemoveContainer { .. },
+                Action::CreateContainer { .. },
+                Action::StartContainer { .. }
+            ]
+        ));
+        assert!(!runtime
+            .calls()
+            .iter()
+            .any(|call| call.starts_with("remove") || call.starts_with("create")));
+    }
+}
@@ -1,28 +1,25 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::container::{Config, CreateContainerOptions, LogsOptions, StartContainerOptions};
-use bollard::image::CreateImageOptions;
-use bollard::models::{HostConfig, Mount, MountTypeEnum};
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, DIGEST_LABEL, IMAGE_LABEL, MIRROR_LABEL},
+    spec::{self, DesiredSpec, Drift, InstanceSpec, PASSWORD_ENV},
+};
 use bollard::service::PortBinding;
-use bollard::{service::CreateImageInfo, Docker};
+use bollard::{container::Config, models::ContainerInspectResponse};
 use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use console::style;
-use futures_util::{StreamExt, TryStreamExt};
-use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::fmt::format;
-use std::hash::Hash;
-use std::thread;
-use std::time::{Duration, Instant};
-use std::{env, process, string};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-const ILLA_BUILDER_IMAGE: &str = "illasoft/illa-builder";
-const ILLA_BUILDER_VERSION: &str = "latest";
-
 // Executes the `illa deploy` command to
 // deploy your ILLA Builder
-#[derive(Debug, Args)]
+#[derive(Debug, Default, Args)]
 #[clap(group(
     ArgGroup::new("install")
         .required(true)
@@ -49,26 +46,64 @@ pub struct Cmd {
     /// The mount path for the ILLA Builder
     #[clap(short = 'm', long = "mount", value_name = "/TEMP/DIR/ILLA-BUILDER")]
     mount_path: Option<String>,
+
+    /// Load the image from a tarball made by `illa image save` instead of pulling it
+    #[clap(long = "image-archive", value_name = "BUILDER.TAR")]
+    image_archive: Option<PathBuf>,
+
+    /// The manifest `illa image save` wrote for the archive [default:
+    /// BUILDER.TAR.manifest.json]
+    #[clap(
+        long = "archive-manifest",
+        value_name = "FILE",
+        requires = "image_archive"
+    )]
+    archive_manifest: Option<PathBuf>,
+
+    #[clap(flatten)]
+    source: image::ImageSource,
+
+    #[clap(flatten)]
+    check: image::ImageCheck,
+
+    /// Replace an existing ILLA Builder whose settings differ, keeping its data
+    #[clap(long = "recreate", action = SetTrue)]
+    recreate: bool,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
-        let spinner_style = ProgressStyle::with_template("{spinner} {wide_msg}")
-            .unwrap()
-            .tick_strings(&["🔸 ", "🔶 ", "🟠 ", "🟠 ", "🔶 "]);
-
+    pub async fn run(&self, ui: &Ui) -> Result {
         let (self_host, cloud) = (self.self_host, self.cloud);
         match (self_host, cloud) {
             (true, _) => {
-                deploy_self_host(
-                    self.builder_version.as_ref(),
-                    self.port,
-                    self.mount_path.as_ref(),
-                    spinner_style,
-                )
-                .await?
+                let runtime = runtime::connect().await?;
+                if self.dry_run {
+                    let (plan, _) = deploy_plan(&runtime, ui, self).await?;
+                    return plan.show(ui, "deploy");
+                }
+                let report = lock::hold(&runtime, ui, "illa_builder", "deploy", self.wait, async {
+                    // Only a directory illa makes may later be deleted by it
+                    let creates_data_dir = !Path::new(&mount_path(self)).exists();
+                    let report = deploy_self_host(&runtime, ui, self).await?;
+                    if creates_data_dir {
+                        state::record_data_dir_created(&runtime, ui, "illa_builder").await;
+                    }
+                    state::record(&runtime, ui, "illa_builder", "deploy").await;
+                    Ok(report)
+                })
+                .await?;
+                ui.emit(&report)?
             }
-            (_, true) => deploy_cloud(spinner_style).await?,
+            (_, true) => deploy_cloud(ui).await?,
             _ => unreachable!(),
         };
         Ok(())
@@ -76,87 +111,187 @@ impl Cmd {
 }
 
 async fn deploy_self_host(
-    version: Option<&String>,
-    port: u16,
-    mount_path: Option<&String>,
-    progress_style: ProgressStyle,
-) -> Result {
-    println!("{} Running a self-hosted installation...", ui::emoji::BUILD);
-
-    let _docker = Docker::connect_with_local_defaults().unwrap();
-    if (_docker.ping().await).is_err() {
-        println!(
-            "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-            ui::emoji::FAIL,
-            String::from("No running docker found."),
-            ui::emoji::WARN,
-            style("Please check the status of docker with command: docker info").red(),
-            String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-            style("https://docs.docker.com/engine/install/").blue(),
-            String::from("Once Docker is installed, please try running the command again."),
-        );
-        process::exit(1);
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    cmd: &Cmd,
+) -> Result<CommandReport> {
+    ui.println(format!(
+        "{} Running a self-hosted installation...",
+        ui::emoji::BUILD
+    ));
+
+    let (plan, convergence) = deploy_plan(runtime, ui, cmd).await?;
+    let original = match convergence {
+        Convergence::Recreated => Some(utils::inspect_builder(runtime, "illa_builder").await?),
+        _ => None,
+    };
+    let progress = ui.progress();
+    let mut outcome = Outcome::default();
+    if let Err(e) = plan.execute(runtime, &progress, &mut outcome).await {
+        // The replaced Builder is put back if the new one does not start
+        if let (Some(original), true) = (&original, outcome.started.is_empty()) {
+            if !outcome.removed.is_empty() {
+                utils::restore_container(runtime, "illa_builder", original, true).await;
+            }
+        }
+        return Err(e);
     }
 
-    let m = MultiProgress::new();
-    let pb_download = m.add(ProgressBar::new(0));
-    pb_download.set_style(progress_style.clone());
-    let finish_spinner_style = ProgressStyle::with_template("{wide_msg}").unwrap();
+    let url = format!("{}:{}", "http://localhost", cmd.port);
+    let message = match convergence {
+        Convergence::Unchanged => "ILLA Builder is already running, please visit",
+        _ => "ILLA Builder started, please visit",
+    };
+    progress.step().finish(format!(
+        "{} {} {}",
+        ui::emoji::SPARKLE,
+        message,
+        style(&url).blue(),
+    ));
 
-    let default_version = ILLA_BUILDER_VERSION.to_owned();
-    let builder_version = version.unwrap_or(&default_version);
-    let builder_image = ILLA_BUILDER_IMAGE.to_owned() + ":" + builder_version;
+    Ok(CommandReport::success("deploy")
+        .instance("illa_builder")
+        .detail("action", convergence)
+        .detail("image", outcome.image)
+        .detail("digest", outcome.pinned.map(|pinned| pinned.digest))
+        .detail("port", cmd.port)
+        .detail("mount", mount_path(cmd))
+        .detail("url", url))
+}
 
-    let default_mount_path = utils::get_default_mount();
-    let mount_path = mount_path.unwrap_or(&default_mount_path);
+/// What deploying does about the ILLA Builder that is already there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Convergence {
+    /// There was none
+    Created,
+    /// It runs as requested
+    Unchanged,
+    /// It was as requested but stopped
+    Started,
+    /// Its settings differed and `--recreate` replaced it
+    Recreated,
+}
 
-    let download_started = Instant::now();
-    let stream_list = &mut _docker.create_image(
-        Some(CreateImageOptions {
-            from_image: builder_image.clone(),
-            ..Default::default()
-        }),
-        None,
-        None,
-    );
+// The steps of a deploy. Nothing is changed while planning, though the
+// registry may be asked whether the version exists.
+//
+// Deploying again converges on the requested settings: a matching Builder
+// is left running or started, and a differing one is only replaced with
+// `--recreate`.
+async fn deploy_plan(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    cmd: &Cmd,
+) -> Result<(Plan, Convergence)> {
+    let port = cmd.port;
+    let source = &cmd.source;
+    runtime.info().check_port(port)?;
 
-    while let Some(value) = stream_list.next().await {
-        pb_download.set_message(format!("Downloading {}...", builder_image.clone()));
-        pb_download.inc(1);
-        thread::sleep(Duration::from_millis(100));
-        if value.is_err() {
-            pb_download.set_style(finish_spinner_style.clone());
-            pb_download.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Download image error:"),
-                style(value.err().unwrap()).red(),
-            ));
-            process::exit(1);
-        };
+    let requested = source.requested(cmd.builder_version.as_deref())?;
+    if let (Some(version), None) = (&cmd.builder_version, &cmd.image_archive) {
+        versions::validate_version(ui, source, &requested, version).await?;
     }
-    pb_download.set_style(finish_spinner_style.clone());
-    pb_download.finish_with_message(format!(
-        "{} Downloaded in {}",
-        ui::emoji::SUCCESS,
-        HumanDuration(download_started.elapsed())
-    ));
-
-    let pb_deploy = m.add(ProgressBar::new(0));
-    pb_deploy.set_style(progress_style.clone());
 
-    let pg_pwd = Uuid::new_v4();
-    let builder_env = vec![
+    let (requested, builder_image) = match &cmd.image_archive {
+        Some(_) => {
+            let builder_image = runtime.info().image_name(&requested.to_string());
+            (requested, builder_image)
+        }
+        None => {
+            let requested = requested.or_latest();
+            let builder_image = source.pull_reference(runtime, &requested);
+            (requested, builder_image)
+        }
+    };
+    let mount_path = mount_path(cmd);
+    let builder_labels = utils::builder_labels(&requested, source.registry_mirror.as_deref());
+    let mut builder_env = vec![
         "ILLA_SERVER_MODE=release".to_string(),
         "ILLA_DEPLOY_MODE=self-host".to_string(),
-        format!("POSTGRES_PASSWORD={pg_pwd}"),
     ];
-    let mut builder_labels = HashMap::new();
-    builder_labels.insert(
-        "maintainer".to_string(),
-        "opensource@illasoft.com".to_string(),
-    );
-    builder_labels.insert("license".to_string(), "Apache-2.0".to_string());
+    let desired = InstanceSpec {
+        name: String::from("illa_builder"),
+        image: builder_image.clone(),
+        env: builder_env.clone(),
+        host_ip: String::from("0.0.0.0"),
+        host_port: port,
+        data_dir: mount_path.clone(),
+        labels: builder_labels.clone(),
+        limits: Default::default(),
+    };
+
+    let mut plan = Plan::default();
+    let existing = match runtime.inspect("illa_builder", false).await {
+        Ok(info) => Some(info),
+        Err(e) if runtime::is_not_found(&e) => None,
+        Err(e) => return Err(e.into()),
+    };
+    // The database was set up with the password of the existing Builder
+    let mut pg_pwd = Uuid::new_v4().to_string();
+    let convergence = match &existing {
+        None => Convergence::Created,
+        Some(info) => {
+            let actual = InstanceSpec::from_inspect(info)?;
+            let drift = existing_drift(&desired, &actual, info, &cmd.check);
+            let running = info
+                .state
+                .as_ref()
+                .and_then(|state| state.running)
+                .unwrap_or(false);
+            match (drift.is_empty(), running) {
+                (true, true) => return Ok((plan, Convergence::Unchanged)),
+                (true, false) => {
+                    plan.push(Action::StartContainer {
+                        name: String::from("illa_builder"),
+                    });
+                    return Ok((plan, Convergence::Started));
+                }
+                (false, _) if !cmd.recreate => {
+                    let fields: Vec<_> = drift.iter().map(|d| d.field.as_str()).collect();
+                    return Err(IllaError::InvalidArgument(format!(
+                        "illa_builder already exists with other settings ({}); pass --recreate to replace it, keeping its data",
+                        fields.join(", ")
+                    )));
+                }
+                (false, _) => {
+                    if let Some(password) = actual.env_value(PASSWORD_ENV) {
+                        pg_pwd = password.to_string();
+                    }
+                    Convergence::Recreated
+                }
+            }
+        }
+    };
+    builder_env.push(format!("{PASSWORD_ENV}={pg_pwd}"));
+
+    match &cmd.image_archive {
+        Some(archive) => plan.push(Action::LoadImage {
+            archive: archive.clone(),
+            manifest: cmd.archive_manifest.clone(),
+            requested: requested.clone(),
+            expect_digest: cmd.check.expect_digest.clone(),
+        }),
+        None => plan.push(Action::PullImage {
+            image: builder_image.clone(),
+        }),
+    }
+    plan.push(Action::VerifyImage {
+        image: builder_image.clone(),
+        requested: requested.clone(),
+        check: cmd.check.clone(),
+    });
+    plan.push(Action::CreateDirectory {
+        path: mount_path.clone(),
+    });
+    // The new image is in place before the old Builder goes away
+    if convergence == Convergence::Recreated {
+        plan.push(Action::RemoveContainer {
+            name: String::from("illa_builder"),
+            force: true,
+        });
+    }
+
     let mut builder_port_bindings = HashMap::new();
     builder_port_bindings.insert(
         "2022/tcp".to_string(),
@@ -166,78 +301,554 @@ async fn deploy_self_host(
         }]),
     );
 
-    let local_dir = utils::local_bind_init(mount_path);
-    let mounts = vec![Mount {
-        target: Some("/opt/illa/database".to_string()),
-        source: Some(local_dir),
-        typ: Some(MountTypeEnum::BIND),
-        read_only: Some(false),
-        ..Default::default()
-    }];
-
     let builder_config = Config {
         image: Some(builder_image),
         env: Some(builder_env),
         labels: Some(builder_labels),
-        host_config: Some(HostConfig {
-            port_bindings: Some(builder_port_bindings),
-            mounts: Some(mounts),
-            ..Default::default()
-        }),
+        host_config: Some(
+            runtime
+                .info()
+                .builder_host_config(builder_port_bindings, &mount_path),
+        ),
         ..Default::default()
     };
+    plan.push(Action::create_container("illa_builder", builder_config));
+    plan.push(Action::StartContainer {
+        name: String::from("illa_builder"),
+    });
+    Ok((plan, convergence))
+}
+
+// How the existing Builder differs from the requested one. Its image is
+// compared by name and tag; the digest only counts with --expect-digest,
+// as a newer image under the same tag is for `illa update` to fetch.
+fn existing_drift(
+    desired: &InstanceSpec,
+    actual: &InstanceSpec,
+    info: &ContainerInspectResponse,
+    check: &image::ImageCheck,
+) -> Vec<Drift> {
+    let digest = info
+        .config
+        .as_ref()
+        .and_then(|config| config.labels.as_ref())
+        .and_then(|labels| labels.get(DIGEST_LABEL));
+    let mut desired = DesiredSpec {
+        spec: desired.clone(),
+        digest: check.expect_digest.clone().or_else(|| digest.cloned()),
+    };
+    if let Some(digest) = digest {
+        desired
+            .spec
+            .labels
+            .insert(DIGEST_LABEL.to_string(), digest.clone());
+    }
+    // The image brings labels of its own; only the ones illa writes count
+    let mut actual = actual.clone();
+    actual.labels.retain(|key, _| {
+        desired.spec.labels.contains_key(key) || [IMAGE_LABEL, MIRROR_LABEL].contains(&key.as_str())
+    });
+    spec::diff(&desired, &actual, digest.map(String::as_str))
+}
+
+fn mount_path(cmd: &Cmd) -> String {
+    cmd.mount_path
+        .clone()
+        .unwrap_or_else(utils::get_default_mount)
+}
+
+async fn deploy_cloud(ui: &Ui) -> Result {
+    utils::cloud_onboarding(ui, "deploy")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        registry::stub_registry,
+        runtime::{
+            fake::{image_archive, image_config, image_id, FakeRuntime, FAKE_DIGEST},
+            RuntimeInfo, RuntimeKind, IMAGE_LABEL, MIRROR_LABEL,
+        },
+    };
+    use std::{env, fs};
+
+    // A registry that publishes the Builder versions `1.2.0` and `1.2.14`
+    async fn published_versions() -> String {
+        stub_registry::serve(|_| {
+            vec![(
+                "/v2/illasoft/illa-builder/tags/list".to_string(),
+                stub_registry::response(
+                    "200 OK",
+                    "",
+                    r#"{"name":"illasoft/illa-builder","tags":["latest","1.2.0","1.2.14"]}"#,
+                ),
+            )]
+        })
+        .await
+    }
+
+    fn temp_data_dir() -> String {
+        env::temp_dir()
+            .join(format!("illa-test-{}", Uuid::new_v4()))
+            .display()
+            .to_string()
+    }
 
-    let create_builder = &_docker
-        .create_container(
-            Some(CreateContainerOptions {
-                name: "illa_builder",
-            }),
-            builder_config,
+    #[tokio::test]
+    async fn deploys_and_starts_builder() {
+        let runtime = FakeRuntime::new();
+        let data_dir = temp_data_dir();
+
+        deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(runtime.is_running("illa_builder"));
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("illasoft/illa-builder:latest@{FAKE_DIGEST}").as_str())
+        );
+        assert_eq!(utils::builder_data_dir(&info), Some(data_dir.clone()));
+        let bindings = info.host_config.unwrap().port_bindings.unwrap();
+        assert_eq!(
+            bindings["2022/tcp"].as_ref().unwrap()[0]
+                .host_port
+                .as_deref(),
+            Some("8080")
+        );
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn deploying_again_converges() {
+        let runtime = FakeRuntime::new();
+        let data_dir = temp_data_dir();
+        let cmd = Cmd {
+            port: 8080,
+            mount_path: Some(data_dir.clone()),
+            ..Default::default()
+        };
+        let ui = Ui::default();
+        let deploy = || deploy_self_host(&runtime, &ui, &cmd);
+
+        deploy().await.unwrap();
+        let report = deploy().await.unwrap();
+        assert_eq!(report.details["action"], "unchanged");
+        runtime.kill("illa_builder", "SIGTERM").await.unwrap();
+        let report = deploy().await.unwrap();
+        assert_eq!(report.details["action"], "started");
+
+        assert!(runtime.is_running("illa_builder"));
+        let creates = runtime
+            .calls()
+            .iter()
+            .filter(|c| c.starts_with("create"))
+            .count();
+        assert_eq!(creates, 1);
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn labels_of_the_image_are_not_drift() {
+        let runtime = FakeRuntime::new();
+        let data_dir = temp_data_dir();
+        let cmd = Cmd {
+            port: 8080,
+            mount_path: Some(data_dir.clone()),
+            ..Default::default()
+        };
+        deploy_self_host(&runtime, &Ui::default(), &cmd)
+            .await
+            .unwrap();
+        // Container labels include the ones of the image it runs
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let mut config = utils::recreate_config(&info);
+        config.labels.get_or_insert_with(Default::default).insert(
+            String::from("org.opencontainers.image.source"),
+            String::from("https://github.com/illacloud/builder"),
+        );
+        runtime.remove("illa_builder", true).await.unwrap();
+        runtime.add_container("illa_builder", config, true);
+
+        let report = deploy_self_host(&runtime, &Ui::default(), &cmd)
+            .await
+            .unwrap();
+
+        assert_eq!(report.details["action"], "unchanged");
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn changed_settings_need_recreate() {
+        let runtime = FakeRuntime::new();
+        let data_dir = temp_data_dir();
+        let cmd = |port, recreate| Cmd {
+            port,
+            recreate,
+            mount_path: Some(data_dir.clone()),
+            ..Default::default()
+        };
+        deploy_self_host(&runtime, &Ui::default(), &cmd(8080, false))
+            .await
+            .unwrap();
+        let password = || async {
+            let info = runtime.inspect("illa_builder", false).await.unwrap();
+            let spec = InstanceSpec::from_inspect(&info).unwrap();
+            spec.env_value(PASSWORD_ENV).unwrap().to_string()
+        };
+        let before = password().await;
+
+        let refused = deploy_self_host(&runtime, &Ui::default(), &cmd(9090, false)).await;
+        assert!(
+            matches!(refused, Err(IllaError::InvalidArgument(ref m)) if m.contains("port") && m.contains("--recreate")),
+            "{refused:?}"
+        );
+        let report = deploy_self_host(&runtime, &Ui::default(), &cmd(9090, true))
+            .await
+            .unwrap();
+
+        assert_eq!(report.details["action"], "recreated");
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let spec = InstanceSpec::from_inspect(&info).unwrap();
+        assert_eq!(spec.host_port, 9090);
+        assert_eq!(spec.data_dir, data_dir);
+        assert_eq!(password().await, before);
+        assert!(runtime.is_running("illa_builder"));
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn dry_run_changes_nothing() {
+        let runtime = FakeRuntime::new();
+        let data_dir = temp_data_dir();
+
+        let (plan, _) = deploy_plan(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                dry_run: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let steps: Vec<_> = plan.actions.iter().map(ToString::to_string).collect();
+        assert_eq!(steps[0], "pull the image illasoft/illa-builder:latest");
+        assert!(steps[3].contains("0.0.0.0:8080->2022/tcp"), "{steps:?}");
+        assert!(steps[3].contains("POSTGRES_PASSWORD=********"), "{steps:?}");
+        assert!(runtime
+            .calls()
+            .iter()
+            .all(|call| !call.starts_with("pull") && !call.starts_with("create")));
+        assert!(fs::metadata(&data_dir).is_err());
+    }
+
+    #[tokio::test]
+    async fn rootless_privileged_port_is_rejected() {
+        let runtime = FakeRuntime::new().with_info(RuntimeInfo {
+            kind: RuntimeKind::Podman,
+            version: String::from("4.6.0"),
+            rootless: true,
+            selinux: false,
+        });
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 0,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::InvalidArgument(_))));
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn qualifies_image_on_podman() {
+        let runtime = FakeRuntime::new().with_info(RuntimeInfo {
+            kind: RuntimeKind::Podman,
+            version: String::from("4.6.0"),
+            rootless: false,
+            selinux: true,
+        });
+        let data_dir = temp_data_dir();
+        let version = String::from("1.2.14");
+
+        deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                builder_version: Some(version.clone()),
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                source: image::ImageSource {
+                    registry_url: Some(published_versions().await),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("docker.io/illasoft/illa-builder:1.2.14@{FAKE_DIGEST}").as_str())
+        );
+        assert_eq!(
+            info.host_config.unwrap().binds,
+            Some(vec![format!("{data_dir}:/opt/illa/database:Z")])
+        );
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn pull_failure_creates_nothing() {
+        let runtime = FakeRuntime::new();
+        runtime.fail("pull", "manifest unknown");
+        let data_dir = temp_data_dir();
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                ..Default::default()
+            },
         )
         .await;
 
-    let start_builder = &_docker
-        .start_container("illa_builder", None::<StartContainerOptions<String>>)
+        let err = result.unwrap_err();
+        assert!(matches!(err, IllaError::ImagePull(_)));
+        assert!(err.to_string().contains("manifest unknown"));
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("create")));
+    }
+
+    #[tokio::test]
+    async fn create_failure_skips_start() {
+        let runtime = FakeRuntime::new();
+        runtime.fail("create", "name conflict");
+        let data_dir = temp_data_dir();
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                ..Default::default()
+            },
+        )
         .await;
 
-    match (create_builder.is_err(), start_builder.is_err()) {
-        (true, _) => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Create ILLA Builder error:"),
-                style(create_builder.as_ref().err().unwrap()).red(),
-            ));
-            process::exit(1);
-        }
-        (false, true) => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Start ILLA Builder error:"),
-                style(start_builder.as_ref().err().unwrap()).red(),
-            ));
-            process::exit(1);
-        }
-        _ => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::SPARKLE,
-                String::from("ILLA Builder started, please visit"),
-                style(format!("{}:{}", "http://localhost", port)).blue(),
-            ));
-            process::exit(0);
-        }
-    };
+        assert!(matches!(result, Err(IllaError::ContainerCreate(_))));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("start")));
+        std::fs::remove_dir_all(data_dir).ok();
+    }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn start_failure_is_reported() {
+        let runtime = FakeRuntime::new();
+        runtime.fail("start", "port is already allocated");
+        let data_dir = temp_data_dir();
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                ..Default::default()
+            },
+        )
+        .await;
 
-async fn deploy_cloud(progress_style: ProgressStyle) -> Result {
-    println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND);
+        let err = result.unwrap_err();
+        assert!(matches!(err, IllaError::ContainerStart(_)));
+        assert_eq!(err.exit_code(), 7);
+        assert!(!runtime.is_running("illa_builder"));
+        std::fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn reports_pulled_digest() {
+        let runtime = FakeRuntime::new();
+
+        let report = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(temp_data_dir()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report.details["digest"], FAKE_DIGEST);
+    }
+
+    #[tokio::test]
+    async fn stream_error_fails_pull() {
+        let runtime = FakeRuntime::new();
+        runtime.fail(
+            "pull_stream",
+            "manifest for illasoft/illa-builder:9.9.9 not found",
+        );
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(temp_data_dir()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::ImagePull(_))));
+        assert!(!runtime.has_container("illa_builder"));
+    }
+
+    #[tokio::test]
+    async fn deploys_from_archive_without_pulling() {
+        let data_dir = temp_data_dir();
+        let archive = env::temp_dir().join(format!("illa-test-{}.tar", Uuid::new_v4()));
+        let config = image_config("illasoft/illa-builder:1.2.0");
+        let hex = image_id(&config).trim_start_matches("sha256:").to_string();
+        image_archive(
+            &archive,
+            &["illasoft/illa-builder:1.2.0"],
+            &config,
+            &format!("{hex}.json"),
+        );
+        let runtime = FakeRuntime::new();
+
+        let report = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                port: 8080,
+                mount_path: Some(data_dir.clone()),
+                image_archive: Some(archive.clone()),
+                check: image::ImageCheck {
+                    expect_digest: Some(image_id(&config)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(runtime.is_running("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("pull")));
+        assert_eq!(report.details["image"], "illasoft/illa-builder:1.2.0");
+        fs::remove_file(archive).ok();
+    }
+
+    #[tokio::test]
+    async fn pulls_docker_hub_image_through_mirror() {
+        let runtime = FakeRuntime::new();
+        let source = image::ImageSource {
+            registry_mirror: Some(String::from("mirror.corp:5000")),
+            registry_url: Some(published_versions().await),
+            ..Default::default()
+        };
+        let version = String::from("1.2.0");
+
+        deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                builder_version: Some(version.clone()),
+                port: 8080,
+                mount_path: Some(temp_data_dir()),
+                source,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("mirror.corp:5000/illasoft/illa-builder:1.2.0@{FAKE_DIGEST}").as_str())
+        );
+        let labels = info.config.unwrap().labels.unwrap();
+        assert_eq!(labels[IMAGE_LABEL], "illasoft/illa-builder:1.2.0");
+        assert_eq!(labels[MIRROR_LABEL], "mirror.corp:5000");
+    }
+
+    #[tokio::test]
+    async fn tagged_image_rejects_builder_version() {
+        let runtime = FakeRuntime::new();
+        let source = image::ImageSource {
+            image: Some(String::from("registry.corp/illa/builder:1.0.0")),
+            ..Default::default()
+        };
+        let version = String::from("1.2.0");
 
-    Ok(())
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                builder_version: Some(version.clone()),
+                port: 8080,
+                mount_path: Some(temp_data_dir()),
+                source,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::InvalidArgument(_))));
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unpublished_version_pulls_nothing() {
+        let runtime = FakeRuntime::new();
+
+        let result = deploy_self_host(
+            &runtime,
+            &Ui::default(),
+            &Cmd {
+                builder_version: Some(String::from("1.2.41")),
+                port: 8080,
+                mount_path: Some(temp_data_dir()),
+                source: image::ImageSource {
+                    registry_url: Some(published_versions().await),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::InvalidArgument(_))));
+        assert!(runtime.calls().is_empty());
+    }
 }
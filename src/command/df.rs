@@ -0,0 +1,363 @@
+use crate::{
+    command::{ui::Ui, *},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime},
+};
+use clap::Args;
+use console::style;
+use indicatif::HumanBytes;
+use prettytable::{color, Attr};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+use std::{collections::HashMap, fs, path::Path};
+
+// Executes the `illa df` command to
+// report the disk usage of ILLA Builder
+#[derive(Debug, Args)]
+/// Show the disk usage of ILLA Builder
+pub struct Cmd {
+    /// Warn about instances using more than this, e.g. 500M or 10G
+    #[clap(
+        long = "warn-above",
+        value_name = "SIZE",
+        default_value = "10G",
+        value_parser = parse_size
+    )]
+    warn_above: u64,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let runtime = runtime::connect().await?;
+        let usage = disk_usage(&runtime, self.warn_above).await?;
+        if ui.is_structured() {
+            return ui.emit(&usage);
+        }
+
+        let size = |bytes: Option<u64>| match bytes {
+            Some(bytes) => HumanBytes(bytes).to_string(),
+            None => String::from("-"),
+        };
+        // A partly read size is a lower bound
+        let at_least = |bytes: Option<u64>, partial: bool| match (bytes, partial) {
+            (Some(bytes), true) => format!(">= {}", HumanBytes(bytes)),
+            _ => size(bytes),
+        };
+        let mut table = Table::new();
+        table.add_row(Row::new(
+            ["Name", "Data", "Container", "Image", "Logs", "Total"]
+                .iter()
+                .map(|title| Cell::new(title).with_style(Attr::ForegroundColor(color::GREEN)))
+                .collect(),
+        ));
+        for instance in &usage.instances {
+            table.add_row(Row::new(vec![
+                Cell::new(&format!("/{}", instance.name))
+                    .with_style(Attr::ForegroundColor(color::BLUE)),
+                Cell::new(&at_least(instance.data, instance.data_partial)),
+                Cell::new(&size(instance.container)),
+                Cell::new(&size(instance.image)),
+                Cell::new(&size(instance.logs)),
+                Cell::new(&at_least(Some(instance.total), instance.data_partial)),
+            ]));
+        }
+        table.add_row(Row::new(vec![
+            Cell::new("Total").with_style(Attr::Bold),
+            Cell::new(""),
+            Cell::new(""),
+            Cell::new(""),
+            Cell::new(""),
+            Cell::new(&at_least(
+                Some(usage.total),
+                usage.instances.iter().any(|i| i.data_partial),
+            ))
+            .with_style(Attr::Bold),
+        ]));
+        table.printstd();
+
+        for instance in usage.instances.iter().filter(|i| i.over_threshold) {
+            ui.println(format!(
+                "{} {}",
+                ui::emoji::WARN,
+                style(format!(
+                    "{} uses {}, more than {}",
+                    instance.name,
+                    HumanBytes(instance.total),
+                    HumanBytes(usage.warn_above)
+                ))
+                .yellow()
+            ));
+        }
+        if usage.instances.iter().any(|i| i.data.is_none()) {
+            ui.println(format!(
+                "{} Some data directories could not be read; run as their owner to include them",
+                ui::emoji::WARN
+            ));
+        }
+        if usage.instances.iter().any(|i| i.data_partial) {
+            ui.println(format!(
+                "{} Parts of some data directories could not be read, so their sizes are lower \
+                 bounds; run as their owner to include them",
+                ui::emoji::WARN
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The disk usage of every ILLA Builder, as reported by
+/// `illa df --output json|yaml`. Sizes are in bytes; a missing size could
+/// not be read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiskUsage {
+    pub instances: Vec<InstanceUsage>,
+    /// All instances together, counting a shared image once
+    pub total: u64,
+    pub warn_above: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceUsage {
+    pub name: String,
+    /// The bound data directory on the host
+    pub data_dir: Option<String>,
+    pub data: Option<u64>,
+    /// Parts of the data directory could not be read, so `data` is a
+    /// lower bound
+    pub data_partial: bool,
+    /// The container's writable layer
+    pub container: Option<u64>,
+    pub image: Option<u64>,
+    pub logs: Option<u64>,
+    pub total: u64,
+    pub over_threshold: bool,
+}
+
+async fn disk_usage(runtime: &dyn ContainerRuntime, warn_above: u64) -> Result<DiskUsage> {
+    let mut instances = vec![];
+    let mut images = HashMap::new();
+    for builder in list::instance_records(runtime).await? {
+        let info = runtime
+            .inspect(&builder.name, true)
+            .await
+            .map_err(|e| IllaError::container(&builder.name, e, IllaError::Runtime))?;
+        let data_dir = utils::builder_data_dir(&info);
+        let data = data_dir.as_deref().and_then(|dir| dir_size(Path::new(dir)));
+        let (data, data_partial) = match data {
+            Some(size) => (Some(size.bytes), size.partial),
+            None => (None, false),
+        };
+        let container = info.size_rw.map(|size| size.max(0) as u64);
+        let image = match &info.image {
+            Some(id) => runtime
+                .inspect_image(id)
+                .await
+                .ok()
+                .and_then(|image| image.size)
+                .map(|size| size.max(0) as u64),
+            None => None,
+        };
+        if let (Some(id), Some(size)) = (&info.image, image) {
+            images.insert(id.clone(), size);
+        }
+        let logs = info
+            .log_path
+            .as_deref()
+            .filter(|path| !path.is_empty())
+            .and_then(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len());
+
+        let total = [data, container, image, logs].iter().flatten().sum();
+        instances.push(InstanceUsage {
+            name: builder.name,
+            data_dir,
+            data,
+            data_partial,
+            container,
+            image,
+            logs,
+            total,
+            over_threshold: total > warn_above,
+        });
+    }
+
+    let total = instances
+        .iter()
+        .map(|i| i.total - i.image.unwrap_or_default())
+        .sum::<u64>()
+        + images.values().sum::<u64>();
+    Ok(DiskUsage {
+        instances,
+        total,
+        warn_above,
+    })
+}
+
+// The bytes of all files below a directory
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct DirSize {
+    bytes: u64,
+    /// Some entries could not be read and are left out
+    partial: bool,
+}
+
+// The size of `dir`, without following symlinks. Entries that cannot be
+// read are skipped and make the size partial; an unreadable `dir` has no
+// size.
+fn dir_size(dir: &Path) -> Option<DirSize> {
+    let mut size = DirSize::default();
+    for entry in fs::read_dir(dir).ok()? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(_) => {
+                size.partial = true;
+                continue;
+            }
+        };
+        let Ok(metadata) = path.symlink_metadata() else {
+            size.partial = true;
+            continue;
+        };
+        if !metadata.is_dir() {
+            size.bytes += metadata.len();
+            continue;
+        }
+        match dir_size(&path) {
+            Some(inner) => {
+                size.bytes += inner.bytes;
+                size.partial |= inner.partial;
+            }
+            None => size.partial = true,
+        }
+    }
+    Some(size)
+}
+
+// `1024`, `500K`, `500M`, `10G` or `1.5GiB`, in binary units like the sizes
+// the command prints
+fn parse_size(value: &str) -> std::result::Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("{value:?} is not a size like 500M or 10G"))?;
+    let shift = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("{value:?} has an unknown unit; use K, M, G or T")),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime, FAKE_IMAGE_SIZE, FAKE_WRITABLE_SIZE};
+    use std::env;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn sums_data_container_and_image() {
+        let data_dir = env::temp_dir().join(format!("illa-df-{}", Uuid::new_v4()));
+        fs::create_dir_all(data_dir.join("pg")).unwrap();
+        fs::write(data_dir.join("pg").join("base"), vec![0; 1000]).unwrap();
+        fs::write(data_dir.join("PG_VERSION"), "14").unwrap();
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config(
+                "illasoft/illa-builder:1.2.0",
+                8080,
+                &data_dir.display().to_string(),
+            ),
+            true,
+        );
+
+        let usage = disk_usage(&runtime, 4096).await.unwrap();
+
+        let instance = &usage.instances[0];
+        assert_eq!(instance.data, Some(1002));
+        assert!(!instance.data_partial);
+        assert_eq!(instance.container, Some(FAKE_WRITABLE_SIZE as u64));
+        assert_eq!(instance.image, Some(FAKE_IMAGE_SIZE as u64));
+        assert_eq!(instance.total, 1002 + 512 + 3072);
+        assert!(instance.over_threshold);
+        assert_eq!(usage.total, instance.total);
+        fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn missing_data_dir_has_no_size() {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/nonexistent/illa"),
+            false,
+        );
+
+        let usage = disk_usage(&runtime, u64::MAX).await.unwrap();
+
+        assert_eq!(usage.instances[0].data, None);
+        assert_eq!(usage.instances[0].image, None);
+        assert!(!usage.instances[0].over_threshold);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_subdirectory_makes_the_size_partial() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let data_dir = env::temp_dir().join(format!("illa-df-{}", Uuid::new_v4()));
+        let locked = data_dir.join("pg");
+        fs::create_dir_all(&locked).unwrap();
+        fs::write(locked.join("base"), vec![0; 1000]).unwrap();
+        fs::write(data_dir.join("PG_VERSION"), "14").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root reads the directory regardless
+        let readable = fs::read_dir(&locked).is_ok();
+        let size = dir_size(&data_dir).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&data_dir).ok();
+
+        if readable {
+            assert_eq!(
+                size,
+                DirSize {
+                    bytes: 1002,
+                    partial: false
+                }
+            );
+        } else {
+            assert_eq!(
+                size,
+                DirSize {
+                    bytes: 2,
+                    partial: true
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("10G"), Ok(10 << 30));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("10X").is_err());
+    }
+}
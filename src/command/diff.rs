@@ -0,0 +1,173 @@
+use crate::{
+    command::{ui::Ui, *},
+    result::{ExitStatus, IllaError, Result},
+    runtime::{self, ContainerRuntime, DIGEST_LABEL},
+    spec::{self, DesiredSpec, Drift, InstanceSpec},
+    state::StateStore,
+};
+use bollard::models::ContainerInspectResponse;
+use clap::Args;
+use console::style;
+use serde::Serialize;
+use std::{fs, path::PathBuf};
+
+// Executes the `illa diff` command to
+// compare an ILLA Builder with its spec
+#[derive(Debug, Args)]
+/// Show how an ILLA Builder differs from its spec
+pub struct Cmd {
+    #[clap(flatten)]
+    desired: Desired,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result<ExitStatus> {
+        let runtime = runtime::connect().await?;
+        let desired = self.desired.load()?;
+        let (_, drift) = compare(&runtime, &desired).await?;
+        if ui.is_structured() {
+            ui.emit(&DiffReport {
+                instance: desired.spec.name.clone(),
+                source: self.desired.source(),
+                drift: drift.clone(),
+            })?;
+        } else if drift.is_empty() {
+            ui.println(format!(
+                "{} {} matches {}",
+                ui::emoji::SUCCESS,
+                desired.spec.name,
+                self.desired.source()
+            ));
+        } else {
+            print_drift(ui, &drift);
+        }
+        match drift.is_empty() {
+            true => Ok(ExitStatus::Success),
+            false => Ok(ExitStatus::DriftDetected),
+        }
+    }
+}
+
+/// Where the desired spec of an ILLA Builder comes from
+#[derive(Debug, Clone, Args)]
+pub struct Desired {
+    /// The ILLA Builder container [default: the one named in --file, or illa_builder]
+    name: Option<String>,
+
+    /// Use the spec in this YAML or JSON file instead of the state file
+    #[clap(short = 'f', long = "file", value_name = "SPEC")]
+    file: Option<PathBuf>,
+}
+
+impl Desired {
+    pub fn load(&self) -> Result<DesiredSpec> {
+        let Some(file) = &self.file else {
+            let name = self.name.as_deref().unwrap_or("illa_builder");
+            let state = StateStore::open()?.load()?;
+            let instance = state.instances.get(name).ok_or_else(|| {
+                IllaError::InvalidArgument(format!(
+                    "{name} is not in the state file; run `illa state repair` or pass --file"
+                ))
+            })?;
+            return Ok(DesiredSpec {
+                spec: instance.spec.clone(),
+                digest: instance.digest.clone(),
+            });
+        };
+
+        let desired: DesiredSpec = serde_yaml::from_str(&fs::read_to_string(file)?)
+            .map_err(|e| IllaError::InvalidArgument(format!("{}: {e}", file.display())))?;
+        match &self.name {
+            Some(name) if *name != desired.spec.name => Err(IllaError::InvalidArgument(format!(
+                "{} declares {}, not {name}",
+                file.display(),
+                desired.spec.name
+            ))),
+            _ => Ok(desired),
+        }
+    }
+
+    pub fn source(&self) -> String {
+        match &self.file {
+            Some(file) => file.display().to_string(),
+            None => String::from("the state file"),
+        }
+    }
+}
+
+/// The drift of an ILLA Builder, as reported by
+/// `illa diff --output json|yaml`
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffReport {
+    pub instance: String,
+    pub source: String,
+    pub drift: Vec<Drift>,
+}
+
+/// Inspect the container `desired` names and compare it with the spec
+pub async fn compare(
+    runtime: &dyn ContainerRuntime,
+    desired: &DesiredSpec,
+) -> Result<(ContainerInspectResponse, Vec<Drift>)> {
+    let info = utils::inspect_builder(runtime, &desired.spec.name).await?;
+    let actual = InstanceSpec::from_inspect(&info)?;
+    let digest = info
+        .config
+        .as_ref()
+        .and_then(|config| config.labels.as_ref())
+        .and_then(|labels| labels.get(DIGEST_LABEL));
+    let drift = spec::diff(desired, &actual, digest.map(String::as_str));
+    Ok((info, drift))
+}
+
+/// Print each drifted setting as its desired and actual value
+pub fn print_drift(ui: &Ui, drift: &[Drift]) {
+    let value = |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("(unset)"));
+    for d in drift {
+        ui.println(format!("  {}", style(&d.field).bold()));
+        ui.println(format!(
+            "{}",
+            style(format!("-   {}", value(&d.desired))).red()
+        ));
+        ui.println(format!(
+            "{}",
+            style(format!("+   {}", value(&d.actual))).green()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+
+    #[tokio::test]
+    async fn compares_the_running_container() {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 9090, "/data/illa"),
+            true,
+        );
+        let mut desired = DesiredSpec {
+            spec: InstanceSpec::from_inspect(
+                &runtime.inspect("illa_builder", false).await.unwrap(),
+            )
+            .unwrap()
+            .without_secrets(),
+            digest: None,
+        };
+        desired.spec.host_port = 8080;
+
+        let (_, drift) = compare(&runtime, &desired).await.unwrap();
+
+        assert_eq!(
+            drift,
+            [Drift {
+                field: String::from("port"),
+                desired: Some(String::from("0.0.0.0:8080")),
+                actual: Some(String::from("0.0.0.0:9090")),
+            }]
+        );
+    }
+}
@@ -1,10 +1,13 @@
-use crate::{command::*, result::Result};
-use bollard::Docker;
+use crate::{
+    command::{
+        ui::{CommandReport, Ui},
+        *,
+    },
+    result::Result,
+    runtime,
+};
 use clap::Args;
 use console::style;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::thread;
-use std::time::Duration;
 
 // Executes the `illa doctor` command to
 // check the prerequisites of self hosting
@@ -13,53 +16,58 @@ use std::time::Duration;
 pub struct Cmd {}
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
-        let spinner_style = ProgressStyle::with_template("{spinner} {wide_msg}")
-            .unwrap()
-            .tick_strings(&["🔸 ", "🔶 ", "🟠 ", "🟠 ", "🔶 "]);
-
-        println!(
+    pub async fn run(&self, ui: &Ui) -> Result {
+        ui.println(format!(
             "{} Checking the prerequisites of self-host...",
             ui::emoji::LOOKING_GLASS
-        );
-
-        let pb = ProgressBar::new(0);
-        pb.set_style(spinner_style.clone());
+        ));
 
-        for _ in 0..10 {
-            pb.set_message("Checking the version of Docker...");
-            pb.inc(1);
-            thread::sleep(Duration::from_millis(200));
-        }
+        let progress = ui.progress();
+        let pb = progress.step();
+        pb.set_message("Checking the version of the container runtime...");
 
-        let new_spinner_style = ProgressStyle::with_template("{wide_msg}").unwrap();
-        pb.set_style(new_spinner_style);
-        let _docker = Docker::connect_with_local_defaults().unwrap();
-        let error_info = |pb: ProgressBar| {
-            pb.println(format!(
-                "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-                ui::emoji::FAIL,
-                String::from("No running docker found."),
-                ui::emoji::WARN,
-                style("Please check the status of docker with command: docker info").red(),
-                String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-                style("https://docs.docker.com/engine/install/").blue(),
-                String::from("Once Docker is installed, please try running the command again."),
-            ));
-            pb.finish_with_message(format!("illa doctor exited."));
-        };
-        match _docker.version().await {
-            Ok(version) =>  pb.finish_with_message(format!(
-                "{} {}: {}\n{} {}",
-                ui::emoji::SUCCESS,
-                String::from("Docker version"),
-                version.version.unwrap(),
-                ui::emoji::SPARKLE,
-                style("Success! The minimum requirement for deploying ILLA has been satisfied. Self-Host your ILLA Builder by command [illa deploy].").green(),
-            )),
-            Err(e) => error_info(pb),
+        match runtime::connect().await {
+            Ok(runtime) => {
+                let mut notes = vec![];
+                if runtime.info.rootless {
+                    notes.push("rootless");
+                }
+                if runtime.info.selinux {
+                    notes.push("SELinux");
+                }
+                let notes = if notes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", notes.join(", "))
+                };
+                pb.finish(format!(
+                    "{} {} version: {}{}\n{} {}",
+                    ui::emoji::SUCCESS,
+                    runtime.info.kind,
+                    runtime.info.version,
+                    notes,
+                    ui::emoji::SPARKLE,
+                    style("Success! The minimum requirement for deploying ILLA has been satisfied. Self-Host your ILLA Builder by command [illa deploy].").green(),
+                ));
+                let port_warning = runtime.info.check_port(80).err().map(|e| e.to_string());
+                if let Some(warning) = &port_warning {
+                    ui.println(format!("{} {}", ui::emoji::WARN, style(warning).yellow()));
+                }
+                ui.emit(
+                    &CommandReport::success("doctor")
+                        .detail("runtime", runtime.info.kind.to_string())
+                        .detail("version", &runtime.info.version)
+                        .detail("rootless", runtime.info.rootless)
+                        .detail("selinux", runtime.info.selinux)
+                        .detail("warning", port_warning),
+                )?;
+            }
+            Err(e) => {
+                pb.finish("illa doctor exited.");
+                return Err(e);
+            }
         }
-        println!();
+        ui.println("");
         Ok(())
     }
 }
@@ -0,0 +1,809 @@
+use crate::{
+    command::{
+        ui::{CommandReport, Ui},
+        *,
+    },
+    registry::{signature, ImageRef, RegistryClient, DEFAULT_IMAGE},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, RuntimeError},
+};
+use clap::{Args, Subcommand};
+use console::style;
+use futures_util::StreamExt;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+// Config blobs are small; anything larger is a layer and is not hashed
+const MAX_CONFIG_SIZE: u64 = 1024 * 1024;
+
+// Executes the `illa image` command to
+// move ILLA Builder images between hosts
+#[derive(Debug, Args)]
+/// Manage ILLA Builder images for offline installation
+pub struct Cmd {
+    #[clap(subcommand)]
+    cmd: ImageCmds,
+}
+
+#[derive(Debug, Subcommand)]
+enum ImageCmds {
+    Save(SaveCmd),
+}
+
+/// Export the ILLA Builder image to a tarball
+#[derive(Debug, Args)]
+struct SaveCmd {
+    /// The version of ILLA Builder to export [default: latest]
+    #[clap(short = 'V', long = "version", value_name = "X.Y.Z")]
+    version: Option<String>,
+
+    /// The tarball to write
+    #[clap(short = 'o', long = "file", value_name = "BUILDER.TAR")]
+    file: PathBuf,
+
+    #[clap(flatten)]
+    source: ImageSource,
+}
+
+/// Where the Builder image comes from
+#[derive(Debug, Clone, Default, Args)]
+pub struct ImageSource {
+    /// The Builder image as repo/name[:tag|@digest] [default: illasoft/illa-builder]
+    #[clap(long = "image", value_name = "REPO/NAME[:TAG|@DIGEST]")]
+    pub image: Option<String>,
+
+    /// Pull Docker Hub images through this registry mirror
+    #[clap(long = "registry-mirror", value_name = "HOST[:PORT]")]
+    pub registry_mirror: Option<String>,
+
+    /// Read tags from this registry API endpoint instead of the image's
+    /// registry, e.g. http://localhost:5000
+    #[clap(long = "registry-url", value_name = "URL", env = "ILLA_REGISTRY_URL")]
+    pub registry_url: Option<String>,
+}
+
+impl ImageSource {
+    /// The image asked for, with `--builder-version` applied
+    pub fn requested(&self, version: Option<&str>) -> Result<ImageRef> {
+        ImageRef::parse(self.image.as_deref().unwrap_or(DEFAULT_IMAGE))?.with_version(version)
+    }
+
+    /// The reference to pull `image` by on this runtime
+    pub fn pull_reference(&self, runtime: &dyn ContainerRuntime, image: &ImageRef) -> String {
+        runtime
+            .info()
+            .image_name(&image.mirrored(self.registry_mirror.as_deref()).to_string())
+    }
+
+    /// A client for the registry `image` is published in
+    pub fn registry(&self, image: &ImageRef) -> Result<RegistryClient> {
+        RegistryClient::new(image, self.registry_url.as_deref())
+    }
+}
+
+/// How the Builder image is checked before the container is created
+#[derive(Debug, Clone, Default, Args)]
+pub struct ImageCheck {
+    /// Refuse to start unless the image has this digest
+    #[clap(long = "expect-digest", value_name = "SHA256:HEX")]
+    pub expect_digest: Option<String>,
+
+    /// Verify the image against a cosign signature file (base64)
+    #[clap(
+        long = "signature",
+        value_name = "FILE",
+        requires = "signature_payload"
+    )]
+    pub signature: Option<PathBuf>,
+
+    /// The payload the signature covers, from `cosign sign --output-payload`
+    #[clap(
+        long = "signature-payload",
+        value_name = "FILE",
+        requires = "signature"
+    )]
+    pub signature_payload: Option<PathBuf>,
+
+    /// The PEM encoded cosign public key to verify the signature with
+    #[clap(long = "public-key", value_name = "FILE", env = "ILLA_PUBLIC_KEY")]
+    pub public_key: Option<PathBuf>,
+}
+
+/// An image resolved to its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedImage {
+    /// The reference to create the container from, pinned by digest when
+    /// the registry digest is known
+    pub reference: String,
+    /// The registry manifest digest, or the image ID for images that
+    /// never came from a registry
+    pub digest: String,
+}
+
+/// Resolve `image` to its content digest and run the checks asked for.
+/// `reported` is the digest the pull reported, if any.
+pub async fn pin_image(
+    runtime: &dyn ContainerRuntime,
+    requested: &ImageRef,
+    image: &str,
+    reported: Option<String>,
+    check: &ImageCheck,
+) -> Result<PinnedImage> {
+    let inspected = runtime.inspect_image(image).await?;
+    let id = inspected.id.unwrap_or_default();
+    let name = image.split('@').next().unwrap_or(image);
+    let repo_digest = reported.or_else(|| {
+        inspected
+            .repo_digests
+            .iter()
+            .flatten()
+            .filter_map(|d| d.split_once('@'))
+            .find(|(repo, _)| normalize_tag(repo) == normalize_tag(&strip_tag(name)))
+            .map(|(_, digest)| digest.to_string())
+    });
+
+    if let Some(expected) = &check.expect_digest {
+        if Some(expected) != repo_digest.as_ref() && *expected != id {
+            return Err(IllaError::ImageVerify(format!(
+                "{image} has digest {}, expected {expected}",
+                repo_digest.as_deref().unwrap_or(&id)
+            )));
+        }
+    }
+
+    // A key on its own means signed images are expected, which must not
+    // pass unverified
+    if check.public_key.is_some() && check.signature.is_none() {
+        return Err(IllaError::InvalidArgument(String::from(
+            "a public key is set, from --public-key or ILLA_PUBLIC_KEY, but there is no \
+             --signature and --signature-payload to verify the image with",
+        )));
+    }
+    if let (Some(signature), Some(payload)) = (&check.signature, &check.signature_payload) {
+        let key = check.public_key.as_ref().ok_or_else(|| {
+            IllaError::InvalidArgument(String::from(
+                "--signature needs a public key, from --public-key or ILLA_PUBLIC_KEY",
+            ))
+        })?;
+        // Signatures cover the registry manifest, which a loaded archive lacks
+        let digest = repo_digest.as_deref().ok_or_else(|| {
+            IllaError::ImageVerify(format!("{image} has no registry digest to verify"))
+        })?;
+        signature::verify(
+            &std::fs::read_to_string(key)?,
+            &std::fs::read(payload)?,
+            &std::fs::read_to_string(signature)?,
+            requested,
+            digest,
+        )?;
+    }
+
+    Ok(match repo_digest {
+        Some(digest) if !image.contains('@') => PinnedImage {
+            reference: format!("{image}@{digest}"),
+            digest,
+        },
+        Some(digest) => PinnedImage {
+            reference: image.to_string(),
+            digest,
+        },
+        None => PinnedImage {
+            reference: image.to_string(),
+            digest: id,
+        },
+    })
+}
+
+fn strip_tag(name: &str) -> String {
+    match name.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => repo.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        match &self.cmd {
+            ImageCmds::Save(cmd) => {
+                let runtime = runtime::connect().await?;
+                let report =
+                    save_image(&runtime, ui, &cmd.source, cmd.version.as_deref(), &cmd.file)
+                        .await?;
+                ui.emit(&report)
+            }
+        }
+    }
+}
+
+async fn save_image(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    source: &ImageSource,
+    version: Option<&str>,
+    file: &Path,
+) -> Result<CommandReport> {
+    let image = source.pull_reference(runtime, &source.requested(version)?.or_latest());
+    ui.println(format!("{} Exporting {image}...", ui::emoji::BUILD));
+
+    let progress = ui.progress();
+    if let Err(e) = runtime.inspect_image(&image).await {
+        if !runtime::is_not_found(&e) {
+            return Err(IllaError::Runtime(e));
+        }
+        utils::pull_image(runtime, &progress, &image).await?;
+    }
+
+    let step = progress.step();
+    step.set_message(format!("Writing {}...", file.display()));
+    let mut out = File::create(file)?;
+    let mut written = 0;
+    let mut chunks = runtime.export_image(&image);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                step.clear();
+                drop(out);
+                std::fs::remove_file(file).ok();
+                return Err(IllaError::Runtime(e));
+            }
+        };
+        out.write_all(&chunk)?;
+        written += chunk.len() as u64;
+    }
+    out.flush()?;
+
+    let manifest = read_archive(file)?;
+    manifest.verify(&image)?;
+    let bundled = BundledManifest {
+        image: image.clone(),
+        digest: manifest.digest.clone(),
+        archive: file_digest(file)?,
+    };
+    let bundled_path = BundledManifest::path_for(file);
+    std::fs::write(
+        &bundled_path,
+        serde_json::to_vec_pretty(&bundled).map_err(anyhow::Error::from)?,
+    )?;
+    step.finish(format!(
+        "{} {} Saved {image} ({}) to {}",
+        ui::emoji::SUCCESS,
+        style("Success!").green(),
+        HumanBytes(written),
+        file.display()
+    ));
+
+    Ok(CommandReport::success("image save")
+        .detail("image", image)
+        .detail("digest", manifest.digest)
+        .detail("file", file.display().to_string())
+        .detail("manifest", bundled_path.display().to_string())
+        .detail("bytes", written))
+}
+
+/// The manifest `illa image save` writes next to an archive, as
+/// `BUILDER.TAR.manifest.json`. It is kept apart from the archive so that a
+/// tampered archive cannot vouch for itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundledManifest {
+    pub image: String,
+    /// The image ID, `sha256:<hex>`
+    pub digest: String,
+    /// The digest of the archive file itself
+    pub archive: String,
+}
+
+impl BundledManifest {
+    /// Where `illa image save` writes the manifest of `archive`
+    pub fn path_for(archive: &Path) -> PathBuf {
+        let mut name = archive.as_os_str().to_owned();
+        name.push(".manifest.json");
+        PathBuf::from(name)
+    }
+}
+
+// Check the archive holding `image` against what was supplied apart from
+// it: the manifest `illa image save` wrote, found next to the archive
+// unless given, and the digest the user expects
+fn verify_bundled(
+    archive: &Path,
+    contents: &ArchiveManifest,
+    image: &str,
+    manifest: Option<&Path>,
+    expect_digest: Option<&str>,
+) -> Result {
+    let default = BundledManifest::path_for(archive);
+    let manifest = manifest.or_else(|| default.exists().then_some(default.as_path()));
+    if manifest.is_none() && expect_digest.is_none() {
+        return Err(IllaError::ImageVerify(format!(
+            "{} has no manifest to check it against; keep the {} written by `illa image save` next to it, pass --archive-manifest, or pass --expect-digest with the image ID",
+            archive.display(),
+            default.display()
+        )));
+    }
+
+    if let Some(path) = manifest {
+        let bundled: BundledManifest = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| IllaError::ImageVerify(format!("unreadable {}: {e}", path.display())))?;
+        if normalize_tag(&bundled.image) != normalize_tag(image) {
+            return Err(IllaError::ImageVerify(format!(
+                "{} is for {}, not {image}",
+                path.display(),
+                bundled.image
+            )));
+        }
+        if bundled.digest != contents.digest {
+            return Err(IllaError::ImageVerify(format!(
+                "the archive contains image {}, but {} names {}",
+                contents.digest,
+                path.display(),
+                bundled.digest
+            )));
+        }
+        let found = file_digest(archive)?;
+        if found != bundled.archive {
+            return Err(IllaError::ImageVerify(format!(
+                "{} hashes to {found}, but {} names {}",
+                archive.display(),
+                path.display(),
+                bundled.archive
+            )));
+        }
+    }
+    match expect_digest {
+        Some(expected) if expected != contents.digest => Err(IllaError::ImageVerify(format!(
+            "the archive contains image {}, expected {expected}",
+            contents.digest
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn file_digest(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// The image described by the `manifest.json` bundled in a
+/// `docker save` tarball
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveManifest {
+    pub repo_tags: Vec<String>,
+    /// The image ID named by the manifest, `sha256:<hex>`
+    pub digest: String,
+    /// The digest of the config blob actually found in the archive
+    pub config_digest: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ManifestEntry {
+    config: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+}
+
+impl ArchiveManifest {
+    /// Check that the archive carries `image` and that its config blob
+    /// hashes to the image ID the manifest names
+    pub fn verify(&self, image: &str) -> Result {
+        let wanted = normalize_tag(image);
+        if !self.repo_tags.iter().any(|t| normalize_tag(t) == wanted) {
+            return Err(IllaError::ImageVerify(format!(
+                "the archive contains {}, not {image}",
+                if self.repo_tags.is_empty() {
+                    String::from("no tagged image")
+                } else {
+                    self.repo_tags.join(", ")
+                }
+            )));
+        }
+        match &self.config_digest {
+            Some(found) if *found == self.digest => Ok(()),
+            Some(found) => Err(IllaError::ImageVerify(format!(
+                "the manifest names {} but the image config hashes to {found}",
+                self.digest
+            ))),
+            None => Err(IllaError::ImageVerify(format!(
+                "the image config {} is missing from the archive",
+                self.digest
+            ))),
+        }
+    }
+}
+
+/// Read the manifest of a `docker save` tarball, hashing the config blobs
+/// on the way so the manifest can be checked against them
+pub fn read_archive(path: &Path) -> Result<ArchiveManifest> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut manifest = None;
+    let mut digests = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if name == "manifest.json" {
+            let mut content = vec![];
+            entry.read_to_end(&mut content)?;
+            manifest = Some(content);
+        } else if entry.header().entry_type().is_file() && entry.size() <= MAX_CONFIG_SIZE {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut entry, &mut hasher)?;
+            digests.insert(name, format!("sha256:{:x}", hasher.finalize()));
+        }
+    }
+
+    let manifest = manifest.ok_or_else(|| {
+        IllaError::ImageVerify(format!("{} has no manifest.json", path.display()))
+    })?;
+    let entries: Vec<ManifestEntry> = serde_json::from_slice(&manifest)
+        .map_err(|e| IllaError::ImageVerify(format!("unreadable manifest.json: {e}")))?;
+    let entry = match entries.as_slice() {
+        [entry] => entry,
+        _ => {
+            return Err(IllaError::ImageVerify(format!(
+                "{} must contain exactly one image, found {}",
+                path.display(),
+                entries.len()
+            )))
+        }
+    };
+
+    // The config is stored as `<hex>.json` by `docker save` and as
+    // `blobs/sha256/<hex>` in the OCI layout
+    let hex = entry
+        .config
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(".json");
+    Ok(ArchiveManifest {
+        repo_tags: entry.repo_tags.clone().unwrap_or_default(),
+        digest: format!("sha256:{hex}"),
+        config_digest: digests.get(&entry.config).cloned(),
+    })
+}
+
+// Docker Hub images are listed with or without their registry prefix
+pub(crate) fn normalize_tag(tag: &str) -> &str {
+    let tag = tag.strip_prefix("docker.io/").unwrap_or(tag);
+    tag.strip_prefix("library/").unwrap_or(tag)
+}
+
+/// Load the Builder image from a `docker save` tarball instead of pulling
+/// it, returning the loaded image and its digest. Without a tag in
+/// `requested` the archive's own tag is used.
+///
+/// Before the archive is handed to the runtime it is checked against its
+/// own manifest, and against the [`BundledManifest`] at `bundled` or next
+/// to the archive and `expect_digest`, at least one of which must be
+/// there. The loaded image ID is checked against them afterwards.
+pub async fn load_archive(
+    runtime: &dyn ContainerRuntime,
+    progress: &ui::Progress,
+    archive: &Path,
+    requested: &ImageRef,
+    bundled: Option<&Path>,
+    expect_digest: Option<&str>,
+) -> Result<(ImageRef, String)> {
+    let manifest = read_archive(archive)?;
+    let loaded = if requested.tag.is_some() || requested.digest.is_some() {
+        requested.clone()
+    } else {
+        manifest
+            .repo_tags
+            .iter()
+            .filter_map(|t| ImageRef::parse(t).ok())
+            .find(|t| normalize_tag(&t.name()) == normalize_tag(&requested.name()))
+            .ok_or_else(|| {
+                IllaError::ImageVerify(format!(
+                    "{} does not contain {requested}",
+                    archive.display()
+                ))
+            })?
+    };
+    let image = runtime.info().image_name(&loaded.to_string());
+    manifest.verify(&image)?;
+    verify_bundled(archive, &manifest, &image, bundled, expect_digest)?;
+
+    let step = progress.step();
+    step.set_message(format!("Loading {}...", archive.display()));
+    let mut events = runtime.load_image(archive);
+    while let Some(event) = events.next().await {
+        let error = match event {
+            Ok(info) => info
+                .error
+                .map(|message| RuntimeError::DockerResponseServerError {
+                    status_code: 500,
+                    message,
+                }),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = error {
+            step.clear();
+            return Err(IllaError::ImageLoad(e));
+        }
+    }
+
+    let inspected = runtime.inspect_image(&image).await.map_err(|e| {
+        step.clear();
+        IllaError::ImageLoad(e)
+    })?;
+    if inspected.id.as_deref() != Some(manifest.digest.as_str()) {
+        step.clear();
+        return Err(IllaError::ImageVerify(format!(
+            "{image} was loaded as {}, expected {}",
+            inspected.id.unwrap_or_default(),
+            manifest.digest
+        )));
+    }
+    step.finish(format!(
+        "{} Loaded {image} from {}",
+        ui::emoji::SUCCESS,
+        archive.display()
+    ));
+
+    Ok((loaded, manifest.digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{image_archive, image_config, image_id, FakeRuntime, FAKE_DIGEST};
+    use std::{env, fs};
+    use uuid::Uuid;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("illa-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn version(version: &str) -> ImageRef {
+        ImageRef::default().with_version(Some(version)).unwrap()
+    }
+
+    fn progress() -> ui::Progress {
+        Ui::default().progress()
+    }
+
+    #[tokio::test]
+    async fn saved_archive_loads_elsewhere() {
+        let file = temp_file("builder.tar");
+        let source = FakeRuntime::new();
+
+        let report = save_image(
+            &source,
+            &Ui::default(),
+            &ImageSource::default(),
+            Some("1.2.0"),
+            &file,
+        )
+        .await
+        .unwrap();
+        assert!(source.has_image("illasoft/illa-builder:1.2.0"));
+
+        assert!(BundledManifest::path_for(&file).exists());
+        let target = FakeRuntime::new();
+        let (image, digest) = load_archive(
+            &target,
+            &progress(),
+            &file,
+            &ImageRef::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(image.to_string(), "illasoft/illa-builder:1.2.0");
+        assert_eq!(report.details["digest"], digest.as_str());
+        assert!(target.has_image(&image.to_string()));
+        assert!(!target.calls().iter().any(|c| c.starts_with("pull")));
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_config() {
+        let file = temp_file("builder.tar");
+        let config = image_config("illasoft/illa-builder:1.2.0");
+        let claimed = image_id(b"something else");
+        let name = format!("{}.json", claimed.trim_start_matches("sha256:"));
+        image_archive(&file, &["illasoft/illa-builder:1.2.0"], &config, &name);
+        let runtime = FakeRuntime::new();
+
+        let result = load_archive(
+            &runtime,
+            &progress(),
+            &file,
+            &ImageRef::default(),
+            None,
+            Some(&claimed),
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::ImageVerify(_))));
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_consistent_archive_the_manifest_does_not_name() {
+        let file = temp_file("builder.tar");
+        save_image(
+            &FakeRuntime::new(),
+            &Ui::default(),
+            &ImageSource::default(),
+            Some("1.2.0"),
+            &file,
+        )
+        .await
+        .unwrap();
+        // A rebuilt archive is consistent in itself but not with the
+        // manifest saved next to it
+        let config = image_config("evil/illa-builder:1.2.0");
+        let hex = image_id(&config).trim_start_matches("sha256:").to_string();
+        image_archive(
+            &file,
+            &["illasoft/illa-builder:1.2.0"],
+            &config,
+            &format!("{hex}.json"),
+        );
+        let runtime = FakeRuntime::new();
+
+        let result = load_archive(
+            &runtime,
+            &progress(),
+            &file,
+            &ImageRef::default(),
+            None,
+            None,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(IllaError::ImageVerify(ref m)) if m.contains("names")),
+            "{result:?}"
+        );
+
+        fs::remove_file(BundledManifest::path_for(&file)).unwrap();
+        let result = load_archive(
+            &runtime,
+            &progress(),
+            &file,
+            &ImageRef::default(),
+            None,
+            None,
+        )
+        .await;
+        assert!(
+            matches!(result, Err(IllaError::ImageVerify(ref m)) if m.contains("no manifest")),
+            "{result:?}"
+        );
+        assert!(runtime.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_other_version() {
+        let file = temp_file("builder.tar");
+        let config = image_config("illasoft/illa-builder:1.2.0");
+        let hex = image_id(&config).trim_start_matches("sha256:").to_string();
+        image_archive(
+            &file,
+            &["docker.io/illasoft/illa-builder:1.2.0"],
+            &config,
+            &format!("{hex}.json"),
+        );
+        let runtime = FakeRuntime::new();
+        let id = image_id(&config);
+
+        let result = load_archive(
+            &runtime,
+            &progress(),
+            &file,
+            &version("1.3.0"),
+            None,
+            Some(&id),
+        )
+        .await;
+        assert!(matches!(result, Err(IllaError::ImageVerify(_))));
+
+        let (image, _) = load_archive(
+            &runtime,
+            &progress(),
+            &file,
+            &version("1.2.0"),
+            None,
+            Some(&id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(image.to_string(), "illasoft/illa-builder:1.2.0");
+    }
+
+    #[tokio::test]
+    async fn pins_signed_image() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+        use p256::{
+            ecdsa::{signature::Signer, Signature, SigningKey},
+            pkcs8::{EncodePublicKey, LineEnding},
+        };
+
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let payload = serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": "illasoft/illa-builder" },
+                "image": { "docker-manifest-digest": FAKE_DIGEST },
+                "type": "cosign container image signature"
+            }
+        })
+        .to_string();
+        let signature: Signature = key.sign(payload.as_bytes());
+        let (public_key, payload_file, signature_file) = (
+            temp_file("cosign.pub"),
+            temp_file("payload.json"),
+            temp_file("builder.sig"),
+        );
+        fs::write(
+            &public_key,
+            key.verifying_key()
+                .to_public_key_pem(LineEnding::LF)
+                .unwrap(),
+        )
+        .unwrap();
+        fs::write(&payload_file, &payload).unwrap();
+        fs::write(&signature_file, STANDARD.encode(signature.to_der())).unwrap();
+        let check = ImageCheck {
+            expect_digest: Some(FAKE_DIGEST.to_string()),
+            signature: Some(signature_file),
+            signature_payload: Some(payload_file),
+            public_key: Some(public_key),
+        };
+
+        let pinned = pin_image(
+            &runtime,
+            &version("1.2.0"),
+            "illasoft/illa-builder:1.2.0",
+            None,
+            &check,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(pinned.digest, FAKE_DIGEST);
+        assert_eq!(
+            pinned.reference,
+            format!("illasoft/illa-builder:1.2.0@{FAKE_DIGEST}")
+        );
+    }
+
+    #[tokio::test]
+    async fn public_key_without_signature_is_refused() {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        let check = ImageCheck {
+            public_key: Some(temp_file("cosign.pub")),
+            ..Default::default()
+        };
+
+        let result = pin_image(
+            &runtime,
+            &version("1.2.0"),
+            "illasoft/illa-builder:1.2.0",
+            None,
+            &check,
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(IllaError::InvalidArgument(ref m)) if m.contains("--signature")),
+            "{result:?}"
+        );
+    }
+}
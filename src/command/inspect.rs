@@ -0,0 +1,342 @@
+use crate::{
+    command::{ui::Ui, *},
+    registry::ImageRef,
+    result::Result,
+    runtime::{self, ContainerRuntime, ContainerStats, DIGEST_LABEL},
+    spec,
+};
+use bollard::models::ContainerInspectResponse;
+use clap::Args;
+use indicatif::{HumanBytes, HumanDuration};
+use prettytable::{color, Attr};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+// Executes the `illa inspect` command to
+// show the effective configuration of an ILLA Builder
+#[derive(Debug, Args)]
+/// Show the configuration of an ILLA Builder
+pub struct Cmd {
+    /// The ILLA Builder container
+    #[clap(default_value = "illa_builder")]
+    name: String,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let runtime = runtime::connect().await?;
+        let details = inspect_instance(&runtime, &self.name).await?;
+        if ui.is_structured() {
+            return ui.emit(&details);
+        }
+        details.table().printstd();
+        Ok(())
+    }
+}
+
+/// The effective configuration of an ILLA Builder, as reported by
+/// `illa inspect --output json|yaml`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceDetails {
+    pub name: String,
+    pub id: String,
+    pub image: Option<String>,
+    pub digest: Option<String>,
+    pub version: Option<String>,
+    pub state: Option<String>,
+    pub created: Option<String>,
+    pub started: Option<String>,
+    /// Seconds since the container started, while it runs
+    pub uptime: Option<u64>,
+    /// CPU and memory use right now, while it runs
+    pub usage: Option<ContainerStats>,
+    pub ports: Vec<String>,
+    pub bind_address: Option<String>,
+    pub data_dir: Option<String>,
+    /// `NAME=value` pairs with secret values masked
+    pub env: Vec<String>,
+    pub restart_policy: Option<String>,
+    pub limits: ResourceLimits,
+    pub health: Option<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Resource limits of the container; `None` means unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResourceLimits {
+    pub memory: Option<u64>,
+    pub cpus: Option<f64>,
+    pub pids: Option<i64>,
+}
+
+impl InstanceDetails {
+    pub fn from_inspect(info: &ContainerInspectResponse, now: SystemTime) -> Self {
+        let config = info.config.clone().unwrap_or_default();
+        let host_config = info.host_config.clone().unwrap_or_default();
+        let state = info.state.clone().unwrap_or_default();
+        let labels: BTreeMap<_, _> = config.labels.unwrap_or_default().into_iter().collect();
+        let image = config.image.clone();
+        let reference = image.as_deref().and_then(|i| ImageRef::parse(i).ok());
+
+        let bindings: Vec<_> = host_config
+            .port_bindings
+            .iter()
+            .flatten()
+            .flat_map(|(port, bindings)| {
+                bindings
+                    .iter()
+                    .flatten()
+                    .map(move |binding| (port.clone(), binding.clone()))
+            })
+            .collect();
+        let mut ports: Vec<_> = bindings
+            .iter()
+            .map(|(port, binding)| {
+                format!(
+                    "{}:{}->{port}",
+                    binding
+                        .host_ip
+                        .as_deref()
+                        .filter(|ip| !ip.is_empty())
+                        .unwrap_or("0.0.0.0"),
+                    binding.host_port.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        ports.sort();
+        let bind_address = bindings
+            .iter()
+            .find_map(|(_, binding)| binding.host_ip.clone())
+            .filter(|ip| !ip.is_empty());
+
+        let running = state.running.unwrap_or(false);
+        let started = state
+            .started_at
+            .clone()
+            .filter(|started| !started.starts_with("0001-"));
+        let uptime = started
+            .as_deref()
+            .filter(|_| running)
+            .and_then(|started| humantime::parse_rfc3339_weak(started).ok())
+            .and_then(|started| now.duration_since(started).ok())
+            .map(|uptime| uptime.as_secs());
+
+        InstanceDetails {
+            name: info
+                .name
+                .as_deref()
+                .unwrap_or_default()
+                .trim_start_matches('/')
+                .to_string(),
+            id: info.id.clone().unwrap_or_default(),
+            digest: labels
+                .get(DIGEST_LABEL)
+                .cloned()
+                .or_else(|| reference.as_ref().and_then(|r| r.digest.clone())),
+            version: reference.and_then(|r| r.tag),
+            image,
+            state: state.status.map(|status| status.to_string()),
+            created: info.created.clone(),
+            started,
+            uptime,
+            usage: None,
+            ports,
+            bind_address,
+            data_dir: utils::builder_data_dir(info),
+            env: config
+                .env
+                .iter()
+                .flatten()
+                .map(|var| mask_secret(var))
+                .collect(),
+            restart_policy: host_config
+                .restart_policy
+                .and_then(|policy| policy.name)
+                .map(|name| name.to_string())
+                .filter(|name| !name.is_empty()),
+            limits: ResourceLimits {
+                memory: host_config
+                    .memory
+                    .filter(|memory| *memory > 0)
+                    .map(|memory| memory as u64),
+                cpus: host_config
+                    .nano_cpus
+                    .filter(|cpus| *cpus > 0)
+                    .map(|cpus| cpus as f64 / 1e9),
+                pids: host_config.pids_limit.filter(|pids| *pids > 0),
+            },
+            health: state
+                .health
+                .and_then(|health| health.status)
+                .map(|status| status.to_string())
+                .filter(|status| !status.is_empty()),
+            labels,
+        }
+    }
+
+    fn table(&self) -> Table {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let rows = [
+            ("Name", self.name.clone()),
+            ("ID", self.id[..12.min(self.id.len())].to_string()),
+            ("Image", or_dash(self.image.clone())),
+            ("Digest", or_dash(self.digest.clone())),
+            ("Version", or_dash(self.version.clone())),
+            ("State", or_dash(self.state.clone())),
+            ("Health", or_dash(self.health.clone())),
+            ("Created", or_dash(self.created.clone())),
+            (
+                "Uptime",
+                or_dash(
+                    self.uptime
+                        .map(|secs| HumanDuration(Duration::from_secs(secs)).to_string()),
+                ),
+            ),
+            (
+                "CPU",
+                or_dash(
+                    self.usage
+                        .as_ref()
+                        .map(|usage| format!("{:.1}%", usage.cpu_percent)),
+                ),
+            ),
+            (
+                "Memory",
+                or_dash(self.usage.as_ref().map(|usage| match usage.memory_limit {
+                    0 => HumanBytes(usage.memory_usage).to_string(),
+                    limit => format!("{} / {}", HumanBytes(usage.memory_usage), HumanBytes(limit)),
+                })),
+            ),
+            ("Ports", self.ports.join("\n")),
+            ("Bind address", or_dash(self.bind_address.clone())),
+            ("Data", or_dash(self.data_dir.clone())),
+            ("Env", self.env.join("\n")),
+            ("Restart policy", or_dash(self.restart_policy.clone())),
+            (
+                "Memory limit",
+                or_dash(self.limits.memory.map(|m| HumanBytes(m).to_string())),
+            ),
+            (
+                "CPU limit",
+                or_dash(self.limits.cpus.map(|c| c.to_string())),
+            ),
+            (
+                "PIDs limit",
+                or_dash(self.limits.pids.map(|p| p.to_string())),
+            ),
+            (
+                "Labels",
+                self.labels
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        ];
+
+        let mut table = Table::new();
+        for (key, value) in rows {
+            table.add_row(Row::new(vec![
+                Cell::new(key).with_style(Attr::ForegroundColor(color::GREEN)),
+                Cell::new(&value),
+            ]));
+        }
+        table
+    }
+}
+
+async fn inspect_instance(runtime: &dyn ContainerRuntime, name: &str) -> Result<InstanceDetails> {
+    let info = utils::inspect_builder(runtime, name).await?;
+    let mut details = InstanceDetails::from_inspect(&info, SystemTime::now());
+    // The usage is only a nicety, a runtime without stats does not fail
+    if info.state.as_ref().and_then(|state| state.running) == Some(true) {
+        details.usage = runtime.stats(name).await.ok();
+    }
+    Ok(details)
+}
+
+fn mask_secret(var: &str) -> String {
+    match var.split_once('=') {
+        Some((key, _)) if spec::is_secret(key) => format!("{key}=********"),
+        _ => var.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::IllaError;
+    use crate::runtime::fake::{builder_config, FakeRuntime, FAKE_DIGEST, FAKE_MEMORY_USAGE};
+    use bollard::models::{ContainerState, HostConfig, RestartPolicy, RestartPolicyNameEnum};
+
+    #[tokio::test]
+    async fn shows_the_effective_config() {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config(
+                &format!("illasoft/illa-builder:1.2.0@{FAKE_DIGEST}"),
+                8080,
+                "/data/illa",
+            ),
+            true,
+        );
+
+        let details = inspect_instance(&runtime, "illa_builder").await.unwrap();
+
+        assert_eq!(details.version.as_deref(), Some("1.2.0"));
+        assert_eq!(details.digest.as_deref(), Some(FAKE_DIGEST));
+        assert_eq!(details.ports, ["0.0.0.0:8080->2022/tcp"]);
+        assert_eq!(details.data_dir.as_deref(), Some("/data/illa"));
+        assert_eq!(details.env[2], "POSTGRES_PASSWORD=********");
+        assert_eq!(details.env[0], "ILLA_SERVER_MODE=release");
+        assert_eq!(
+            details.usage.map(|usage| usage.memory_usage),
+            Some(FAKE_MEMORY_USAGE)
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_instance_is_not_found() {
+        let runtime = FakeRuntime::new();
+
+        let result = inspect_instance(&runtime, "illa_builder").await;
+
+        assert!(matches!(result, Err(IllaError::ContainerNotFound(_))));
+    }
+
+    #[test]
+    fn uptime_limits_and_policy() {
+        let info = ContainerInspectResponse {
+            state: Some(ContainerState {
+                running: Some(true),
+                started_at: Some(String::from("2023-05-01T10:00:00.123456789Z")),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                memory: Some(2 << 30),
+                nano_cpus: Some(1_500_000_000),
+                pids_limit: Some(0),
+                restart_policy: Some(RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                    maximum_retry_count: None,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let now = humantime::parse_rfc3339("2023-05-01T11:30:00.123456789Z").unwrap();
+
+        let details = InstanceDetails::from_inspect(&info, now);
+
+        assert_eq!(details.uptime, Some(5400));
+        assert_eq!(details.limits.memory, Some(2 << 30));
+        assert_eq!(details.limits.cpus, Some(1.5));
+        assert_eq!(details.limits.pids, None);
+        assert_eq!(details.restart_policy.as_deref(), Some("unless-stopped"));
+    }
+}
@@ -0,0 +1,62 @@
+//! Ctrl-C handling. Outside a plan Ctrl-C ends the run at once. A running
+//! plan is told instead, so it can stop and roll back the step it was at;
+//! a second Ctrl-C ends the run regardless.
+
+use crate::{command::lock, result::IllaError};
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+use tokio::sync::watch;
+
+// The plans running now
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+
+fn pressed() -> &'static watch::Sender<bool> {
+    static PRESSED: OnceLock<watch::Sender<bool>> = OnceLock::new();
+    PRESSED.get_or_init(|| watch::channel(false).0)
+}
+
+/// Handle Ctrl-C for the rest of the run
+pub fn install() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if GUARDS.load(Ordering::SeqCst) == 0 || is_interrupted() {
+                // Exiting skips the code that would release the leases
+                lock::release_held().await;
+                process::exit(IllaError::Interrupted(String::new()).exit_code());
+            }
+            pressed().send_replace(true);
+        }
+    });
+}
+
+/// Marks a running plan until dropped
+#[derive(Debug)]
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        GUARDS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Have Ctrl-C interrupt the caller rather than end the run, for as long
+/// as the guard lives
+pub fn guard() -> Guard {
+    GUARDS.fetch_add(1, Ordering::SeqCst);
+    Guard(())
+}
+
+/// Whether Ctrl-C was pressed
+pub fn is_interrupted() -> bool {
+    *pressed().borrow()
+}
+
+/// Ctrl-C as seen by a running plan: turns true once it is pressed
+pub fn subscribe() -> watch::Receiver<bool> {
+    pressed().subscribe()
+}
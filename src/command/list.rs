@@ -1,14 +1,14 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::{
-    container::{ListContainersOptions, StatsOptions},
-    Docker,
+use crate::{
+    command::{ui::Ui, *},
+    registry::ImageRef,
+    result::Result,
+    runtime::{self, ContainerRuntime, BUILDER_DATA_DIR},
 };
+use bollard::models::ContainerSummary;
 use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use prettytable::{color, Attr};
 use prettytable::{Cell, Row, Table};
-use std::collections::HashMap;
-use std::process;
+use serde::Serialize;
 
 // Executes the `illa list` command to
 // get ILLA Builder info
@@ -34,20 +34,75 @@ pub struct Cmd {
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
+    pub async fn run(&self, ui: &Ui) -> Result {
         let (all, self_host, cloud) = (self.all, self.self_host, self.cloud);
         match (all, self_host, cloud) {
-            (true, _, _) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
-            (_, true, _) => list_local().await?,
-            (_, _, true) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
+            (true, _, _) => utils::cloud_onboarding(ui, "list")?,
+            (_, true, _) => list_local(ui).await?,
+            (_, _, true) => utils::cloud_onboarding(ui, "list")?,
             _ => unreachable!(),
         };
         Ok(())
     }
 }
 
-async fn list_local() -> Result {
-    let _docker = Docker::connect_with_local_defaults().unwrap();
+/// A managed ILLA Builder as reported by `illa list --output json|yaml`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceRecord {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub state: String,
+    pub ports: Vec<String>,
+    pub mount: Option<String>,
+    pub version: Option<String>,
+}
+
+impl InstanceRecord {
+    fn from_summary(builder: &ContainerSummary) -> Self {
+        let image = builder.image.clone().unwrap_or_default();
+        let ports = builder
+            .ports
+            .iter()
+            .flatten()
+            .map(|p| match p.public_port {
+                Some(public) => format!(
+                    "{}:{}->{}/tcp",
+                    p.ip.as_deref().unwrap_or("0.0.0.0"),
+                    public,
+                    p.private_port
+                ),
+                None => format!("{}/tcp", p.private_port),
+            })
+            .collect();
+        let mount = builder
+            .mounts
+            .iter()
+            .flatten()
+            .find(|m| m.destination.as_deref() == Some(BUILDER_DATA_DIR))
+            .and_then(|m| m.source.clone());
+        let version = ImageRef::parse(&image).ok().and_then(|image| image.tag);
+
+        InstanceRecord {
+            id: builder.id.clone().unwrap_or_default(),
+            name: builder
+                .names
+                .iter()
+                .flatten()
+                .next()
+                .map(|n| n.trim_start_matches('/').to_string())
+                .unwrap_or_default(),
+            image,
+            state: builder.state.clone().unwrap_or_default(),
+            ports,
+            mount,
+            version,
+        }
+    }
+}
+
+async fn list_local(ui: &Ui) -> Result {
+    let runtime = runtime::connect().await;
 
     let mut table = Table::new();
 
@@ -57,31 +112,87 @@ async fn list_local() -> Result {
         Cell::new("Image").with_style(Attr::ForegroundColor(color::GREEN)),
         Cell::new("State").with_style(Attr::ForegroundColor(color::GREEN)),
     ]));
-    if (_docker.ping().await).is_err() {
-        table.printstd();
-        process::exit(1);
-    }
+    let runtime = match runtime {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            if !ui.is_structured() {
+                table.printstd();
+            }
+            return Err(e);
+        }
+    };
 
-    let mut ls_containers_filters = HashMap::new();
-    ls_containers_filters.insert("name".to_string(), vec!["illa_builder".to_string()]);
-    let builders = &_docker
-        .list_containers(Some(ListContainersOptions::<String> {
-            all: true,
-            filters: ls_containers_filters,
-            ..Default::default()
-        }))
-        .await
-        .unwrap();
-    for builder in builders {
+    let records = instance_records(&runtime).await?;
+    if ui.is_structured() {
+        return ui.emit(&records);
+    }
+    for record in records {
         table.add_row(Row::new(vec![
-            Cell::new(&builder.id.as_ref().unwrap().as_str()[0..12])
+            Cell::new(&record.id[..12.min(record.id.len())])
                 .with_style(Attr::ForegroundColor(color::BLUE)),
-            Cell::new(builder.names.as_ref().unwrap()[0].as_str()),
-            Cell::new(builder.image.as_ref().unwrap().as_str()),
-            Cell::new(builder.state.as_ref().unwrap().as_str()),
+            Cell::new(&format!("/{}", record.name)),
+            Cell::new(&record.image),
+            Cell::new(&record.state),
         ]));
     }
     table.printstd();
 
     Ok(())
 }
+
+pub(crate) async fn instance_records(
+    runtime: &dyn ContainerRuntime,
+) -> Result<Vec<InstanceRecord>> {
+    let builders = runtime.list("illa_builder").await?;
+    Ok(builders
+        .iter()
+        .filter(|builder| !utils::is_lease(builder))
+        .map(InstanceRecord::from_summary)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime, FAKE_DIGEST};
+
+    #[tokio::test]
+    async fn records_describe_the_builder() {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.14", 8080, "/data/illa"),
+            true,
+        );
+
+        let records = instance_records(&runtime).await.unwrap();
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.name, "illa_builder");
+        assert_eq!(record.state, "running");
+        assert_eq!(record.ports, vec!["0.0.0.0:8080->2022/tcp".to_string()]);
+        assert_eq!(record.mount.as_deref(), Some("/data/illa"));
+        assert_eq!(record.version.as_deref(), Some("1.2.14"));
+    }
+
+    #[test]
+    fn version_ignores_registry_port() {
+        let record = InstanceRecord::from_summary(&ContainerSummary {
+            image: Some(String::from("registry.local:5000/illa-builder")),
+            ..Default::default()
+        });
+
+        assert_eq!(record.version, None);
+    }
+
+    #[test]
+    fn version_ignores_pinned_digest() {
+        let record = InstanceRecord::from_summary(&ContainerSummary {
+            image: Some(format!("illasoft/illa-builder:1.2.0@{FAKE_DIGEST}")),
+            ..Default::default()
+        });
+
+        assert_eq!(record.version.as_deref(), Some("1.2.0"));
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{
+    command::{ui::Ui, *},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, LEASE_LABEL},
+};
+use bollard::container::Config;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{self, Command},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+// How often `--wait` checks whether the instance is free again
+const WAIT_POLL: Duration = Duration::from_millis(500);
+
+/// The CLI run holding the lock on an instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub host: String,
+    pub command: String,
+    /// RFC 3339 time the lock was taken
+    pub since: String,
+}
+
+impl Holder {
+    fn current(command: &str) -> Self {
+        Holder {
+            pid: process::id(),
+            host: hostname(),
+            command: command.to_string(),
+            since: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        }
+    }
+
+    fn labels(&self, name: &str) -> HashMap<String, String> {
+        HashMap::from([
+            (LEASE_LABEL.to_string(), name.to_string()),
+            (format!("{LEASE_LABEL}.pid"), self.pid.to_string()),
+            (format!("{LEASE_LABEL}.host"), self.host.clone()),
+            (format!("{LEASE_LABEL}.command"), self.command.clone()),
+            (format!("{LEASE_LABEL}.since"), self.since.clone()),
+        ])
+    }
+
+    fn from_labels(labels: &HashMap<String, String>) -> Option<Self> {
+        let label = |key: &str| labels.get(&format!("{LEASE_LABEL}.{key}")).cloned();
+        Some(Holder {
+            pid: label("pid")?.parse().ok()?,
+            host: label("host")?,
+            command: label("command")?,
+            since: label("since")?,
+        })
+    }
+
+    // A run on this host is gone when its process is. One on another host
+    // cannot be checked, and a long pull may hold the lock for any time, so
+    // its lease never expires by itself.
+    fn is_stale(&self) -> bool {
+        self.host == hostname() && process_alive(self.pid) == Some(false)
+    }
+
+    // How to free the instance if the run on another host is gone
+    fn hint(&self, name: &str) -> String {
+        match self.host == hostname() {
+            true => String::new(),
+            false => format!(
+                "; if that run is gone, remove its lease with `docker rm {}`",
+                lease_name(name)
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Holder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PID {} on {} (`illa {}`) since {}",
+            self.pid, self.host, self.command, self.since
+        )
+    }
+}
+
+/// The lock files of the instances, one per instance name
+#[derive(Debug, Clone)]
+pub struct Locks {
+    dir: PathBuf,
+}
+
+impl Locks {
+    /// The lock files under the user's config directory, e.g.
+    /// `~/.config/illa/locks`
+    pub fn open() -> Result<Self> {
+        let dir = dirs::config_dir().ok_or_else(|| {
+            IllaError::State(String::from("no config directory for the current user"))
+        })?;
+        Ok(Locks::at(dir.join("illa").join("locks")))
+    }
+
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Locks { dir: dir.into() }
+    }
+
+    /// Take the lock on the instance `name` for `command`. A busy instance
+    /// fails the command, or with `wait` is waited for.
+    pub async fn acquire(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        ui: &Ui,
+        name: &str,
+        command: &str,
+        wait: bool,
+    ) -> Result<InstanceLock> {
+        let mut waiting = false;
+        loop {
+            let holder = match self.try_acquire(runtime, name, command).await? {
+                Ok(lock) => return Ok(lock),
+                Err(holder) => holder,
+            };
+            let hint = holder
+                .as_ref()
+                .map(|holder| holder.hint(name))
+                .unwrap_or_default();
+            let holder = holder
+                .map(|holder| holder.to_string())
+                .unwrap_or_else(|| String::from("another illa run"));
+            if !wait {
+                return Err(IllaError::Locked(format!(
+                    "{name} is being modified by {holder}; pass --wait to wait for it{hint}"
+                )));
+            }
+            if !waiting {
+                ui.println(format!(
+                    "{} Waiting for {holder} to finish with {name}{hint}...",
+                    ui::emoji::LOOKING_GLASS
+                ));
+                waiting = true;
+            }
+            tokio::time::sleep(WAIT_POLL).await;
+        }
+    }
+
+    // The lock, or who holds it if they could be told
+    async fn try_acquire(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        name: &str,
+        command: &str,
+    ) -> Result<std::result::Result<InstanceLock, Option<Holder>>> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(self.dir.join(format!("{name}.lock")))?;
+        if file.try_lock_exclusive().is_err() {
+            // The holder may not have written itself yet
+            let mut contents = String::new();
+            file.read_to_string(&mut contents)?;
+            return Ok(Err(serde_json::from_str(&contents).ok()));
+        }
+
+        let holder = Holder::current(command);
+        file.set_len(0)?;
+        file.write_all(&serde_json::to_vec(&holder).map_err(anyhow::Error::from)?)?;
+        match lease(runtime, name, &holder).await? {
+            Ok(lease) => {
+                held().lock().unwrap().extend(lease.clone());
+                Ok(Ok(InstanceLock { file, lease }))
+            }
+            Err(other) => {
+                file.set_len(0)?;
+                Ok(Err(Some(other)))
+            }
+        }
+    }
+}
+
+/// The lock on an instance, held until [`InstanceLock::release`]. The
+/// local lock goes with the process, but a lease is only removed by
+/// releasing it or once it goes stale.
+#[derive(Debug)]
+pub struct InstanceLock {
+    file: File,
+    lease: Option<String>,
+}
+
+impl InstanceLock {
+    pub async fn release(self, runtime: &dyn ContainerRuntime) {
+        if let Some(lease) = &self.lease {
+            runtime.remove(lease, true).await.ok();
+            held().lock().unwrap().retain(|held| held != lease);
+        }
+        self.file.set_len(0).ok();
+    }
+}
+
+// The leases this run holds, for an exit on Ctrl-C to release
+fn held() -> &'static Mutex<Vec<String>> {
+    static HELD: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    HELD.get_or_init(Default::default)
+}
+
+/// Remove the leases this run still holds, before it exits without
+/// unwinding. The local locks go with the process.
+pub async fn release_held() {
+    let leases = std::mem::take(&mut *held().lock().unwrap());
+    if leases.is_empty() {
+        return;
+    }
+    if let Ok(runtime) = runtime::connect().await {
+        for lease in leases {
+            runtime.remove(&lease, true).await.ok();
+        }
+    }
+}
+
+/// Run `f` while holding the lock on the instance `name`. An earlier
+/// interrupted command on the instance is reported first, and `f` being
+/// interrupted is recorded in the state file for the next run to report.
+pub async fn hold<T>(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    name: &str,
+    command: &str,
+    wait: bool,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let lock = Locks::open()?
+        .acquire(runtime, ui, name, command, wait)
+        .await?;
+    state::report_interrupted(ui, name);
+    let result = f.await;
+    match &result {
+        Ok(_) => state::clear_interrupted(ui, name),
+        Err(IllaError::Interrupted(during)) => state::mark_interrupted(ui, name, command, during),
+        Err(_) => {}
+    }
+    lock.release(runtime).await;
+    result
+}
+
+/// Run `f` while holding the locks on all the instances `names`, taken
+/// one by one in order. The ones taken are released if a later one cannot
+/// be.
+pub async fn hold_all<T>(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    names: &[String],
+    command: &str,
+    wait: bool,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let locks = Locks::open()?;
+    let mut held = vec![];
+    for name in names {
+        match locks.acquire(runtime, ui, name, command, wait).await {
+            Ok(lock) => held.push(lock),
+            Err(e) => {
+                for lock in held {
+                    lock.release(runtime).await;
+                }
+                return Err(e);
+            }
+        }
+    }
+    let result = f.await;
+    for lock in held {
+        lock.release(runtime).await;
+    }
+    result
+}
+
+/// The name of the container leasing the instance `name`
+pub fn lease_name(name: &str) -> String {
+    format!("{name}.lease")
+}
+
+// The file lock only keeps out runs on this host, so the instance is also
+// leased on the daemon. Labels cannot be added to a container, so the lease
+// is a container of its own that is never started; creating it fails while
+// another run holds it. A new instance has nothing to lease yet.
+async fn lease(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+    holder: &Holder,
+) -> Result<std::result::Result<Option<String>, Holder>> {
+    let info = match runtime.inspect(name, false).await {
+        Ok(info) => info,
+        Err(e) if runtime::is_not_found(&e) => return Ok(Ok(None)),
+        Err(e) => return Err(e.into()),
+    };
+    let lease = lease_name(name);
+    let config = Config {
+        image: info.image.clone(),
+        labels: Some(holder.labels(name)),
+        ..Default::default()
+    };
+    loop {
+        match runtime.create(&lease, config.clone()).await {
+            Ok(()) => return Ok(Ok(Some(lease))),
+            Err(e) if runtime::is_conflict(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let other = match runtime.inspect(&lease, false).await {
+            Ok(other) => other,
+            Err(e) if runtime::is_not_found(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let holder = other
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|labels| Holder::from_labels(&labels));
+        match holder {
+            Some(holder) if !holder.is_stale() => return Ok(Err(holder)),
+            // By the ID inspected, so a lease another run took over in the
+            // meantime is not removed too
+            _ => match runtime
+                .remove(other.id.as_deref().unwrap_or(&lease), true)
+                .await
+            {
+                Err(e) if !runtime::is_not_found(&e) => return Err(e.into()),
+                _ => {}
+            },
+        }
+    }
+}
+
+// Whether the process `pid` runs, if that can be told
+#[cfg(unix)]
+fn process_alive(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 only checks the process exists; EPERM means it does but
+    // belongs to another user
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        Some(libc::EPERM) => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| {
+            let output = Command::new("hostname").output().ok()?;
+            String::from_utf8(output.stdout).ok()
+        })
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| String::from("unknown host"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+    use std::env;
+    use uuid::Uuid;
+
+    fn runtime_with_builder() -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            true,
+        );
+        runtime
+    }
+
+    fn temp_locks() -> Locks {
+        Locks::at(env::temp_dir().join(format!("illa-locks-{}", Uuid::new_v4())))
+    }
+
+    #[tokio::test]
+    async fn second_run_is_told_who_holds_the_lock() {
+        let runtime = runtime_with_builder();
+        let locks = temp_locks();
+        let ui = Ui::default();
+
+        let lock = locks
+            .acquire(&runtime, &ui, "illa_builder", "update", false)
+            .await
+            .unwrap();
+        assert!(runtime.has_container("illa_builder.lease"));
+        let busy = locks
+            .acquire(&runtime, &ui, "illa_builder", "remove", false)
+            .await;
+
+        let message = busy.unwrap_err().to_string();
+        assert!(
+            message.contains(&format!("PID {}", process::id())),
+            "{message}"
+        );
+        assert!(message.contains("`illa update`"), "{message}");
+        lock.release(&runtime).await;
+        assert!(!runtime.has_container("illa_builder.lease"));
+        locks
+            .acquire(&runtime, &ui, "illa_builder", "remove", false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn lease_from_another_host_holds_until_removed() {
+        let runtime = runtime_with_builder();
+        let ui = Ui::default();
+        // Taken long ago, as by a slow pull or a run that crashed
+        let since = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        let remote = Config {
+            image: Some(String::from("illasoft/illa-builder:1.2.0")),
+            labels: Some(
+                Holder {
+                    pid: 1,
+                    host: String::from("ci-runner"),
+                    command: String::from("deploy"),
+                    since: humantime::format_rfc3339_seconds(since).to_string(),
+                }
+                .labels("illa_builder"),
+            ),
+            ..Default::default()
+        };
+
+        runtime.add_container("illa_builder.lease", remote, false);
+        let busy = temp_locks()
+            .acquire(&runtime, &ui, "illa_builder", "update", false)
+            .await;
+        assert!(
+            matches!(busy, Err(IllaError::Locked(ref m))
+                if m.contains("ci-runner") && m.contains("docker rm illa_builder.lease")),
+            "{busy:?}"
+        );
+
+        runtime.remove("illa_builder.lease", true).await.unwrap();
+        temp_locks()
+            .acquire(&runtime, &ui, "illa_builder", "update", false)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn lease_of_a_dead_local_run_is_stale() {
+        let holder = |pid| Holder {
+            pid,
+            host: hostname(),
+            command: String::from("update"),
+            since: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        };
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+
+        assert!(!holder(process::id()).is_stale());
+        assert!(holder(dead).is_stale());
+    }
+
+    #[tokio::test]
+    async fn wait_takes_the_lock_once_released() {
+        let runtime = runtime_with_builder();
+        let locks = temp_locks();
+        let ui = Ui::default();
+        let lock = locks
+            .acquire(&runtime, &ui, "illa_builder", "update", false)
+            .await
+            .unwrap();
+
+        let release = async {
+            tokio::time::sleep(WAIT_POLL).await;
+            lock.release(&runtime).await;
+        };
+        let (_, waited) = tokio::join!(
+            release,
+            locks.acquire(&runtime, &ui, "illa_builder", "restart", true)
+        );
+
+        waited.unwrap();
+        assert!(runtime.has_container("illa_builder.lease"));
+    }
+}
@@ -1,10 +1,23 @@
 #![allow(unused)]
+pub mod adopt;
+pub mod apply;
 pub mod deploy;
+pub mod df;
+pub mod diff;
 pub mod doctor;
+pub mod image;
+pub mod inspect;
+pub mod interrupt;
 pub mod list;
+pub mod lock;
+pub mod plan;
+pub mod prune;
 pub mod remove;
 pub mod restart;
+pub mod start;
+pub mod state;
 pub mod stop;
 pub mod ui;
 pub mod update;
 pub mod utils;
+pub mod versions;
@@ -0,0 +1,581 @@
+use crate::{
+    command::{
+        image::{ImageCheck, PinnedImage},
+        prune::PruneSummary,
+        ui::{CommandReport, Progress, Ui},
+        *,
+    },
+    registry::ImageRef,
+    result::{IllaError, Result},
+    runtime::{ContainerRuntime, BUILDER_DATA_DIR, DIGEST_LABEL, IMAGE_LABEL},
+    spec,
+    state::Shutdown,
+};
+use bollard::container::Config;
+use indicatif::{HumanBytes, HumanDuration};
+use serde::Serialize;
+use std::{fmt, path::PathBuf, time::Instant};
+use tokio::sync::watch;
+
+/// One step of a command that changes an ILLA Builder
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    PullImage {
+        image: String,
+    },
+    /// Load an image from a tarball made by `illa image save`
+    LoadImage {
+        archive: PathBuf,
+        /// The manifest `illa image save` wrote, if not next to the archive
+        manifest: Option<PathBuf>,
+        #[serde(skip)]
+        requested: ImageRef,
+        #[serde(skip)]
+        expect_digest: Option<String>,
+    },
+    /// Resolve the image to its digest and run the requested checks. The
+    /// containers created afterwards are pinned to that digest.
+    VerifyImage {
+        image: String,
+        #[serde(skip)]
+        requested: ImageRef,
+        #[serde(skip)]
+        check: ImageCheck,
+    },
+    CreateDirectory {
+        path: String,
+    },
+    /// Write the directory to a tarball before it is deleted
+    BackupDirectory {
+        path: String,
+        archive: PathBuf,
+    },
+    DeleteDirectory {
+        path: String,
+    },
+    RemoveContainer {
+        name: String,
+        force: bool,
+    },
+    CreateContainer {
+        name: String,
+        image: String,
+        /// `NAME=value` pairs with secret values masked
+        env: Vec<String>,
+        ports: Vec<String>,
+        data_dir: Option<String>,
+        #[serde(skip)]
+        config: Box<Config<String>>,
+    },
+    StartContainer {
+        name: String,
+    },
+    /// Ask the container to exit and kill it if it does not in time, as
+    /// [`stop::StopArgs`] say
+    StopContainer {
+        name: String,
+        #[serde(flatten)]
+        stop: stop::StopArgs,
+    },
+    /// Stop the container as [`stop::StopArgs`] say, then start it again
+    RestartContainer {
+        name: String,
+        #[serde(flatten)]
+        stop: stop::StopArgs,
+    },
+    /// Remove the Builder images no container uses, keeping the `keep`
+    /// newest of them
+    PruneImages {
+        keep: usize,
+        /// The images found to remove when the plan was made, if looked up
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        images: Vec<String>,
+    },
+}
+
+impl Action {
+    // Pulls and loads stream for as long as the image takes to arrive,
+    // and are cancelled on Ctrl-C
+    fn is_streaming(&self) -> bool {
+        matches!(self, Action::PullImage { .. } | Action::LoadImage { .. })
+    }
+
+    /// Create the container `name` with `config`
+    pub fn create_container(name: &str, config: Config<String>) -> Self {
+        let host_config = config.host_config.clone().unwrap_or_default();
+        let mut ports: Vec<_> = host_config
+            .port_bindings
+            .iter()
+            .flatten()
+            .flat_map(|(port, bindings)| {
+                bindings.iter().flatten().map(move |binding| {
+                    format!(
+                        "{}:{}->{port}",
+                        binding.host_ip.as_deref().unwrap_or("0.0.0.0"),
+                        binding.host_port.as_deref().unwrap_or_default()
+                    )
+                })
+            })
+            .collect();
+        ports.sort();
+        let mounted = host_config
+            .mounts
+            .iter()
+            .flatten()
+            .find(|m| m.target.as_deref() == Some(BUILDER_DATA_DIR))
+            .and_then(|m| m.source.clone());
+        let bound = host_config.binds.iter().flatten().find_map(|bind| {
+            let (source, target) = bind.split_once(':')?;
+            target
+                .starts_with(BUILDER_DATA_DIR)
+                .then(|| source.to_string())
+        });
+
+        Action::CreateContainer {
+            name: name.to_string(),
+            image: config.image.clone().unwrap_or_default(),
+            env: config
+                .env
+                .iter()
+                .flatten()
+                .map(|var| match var.split_once('=') {
+                    Some((key, _)) if spec::is_secret(key) => format!("{key}=********"),
+                    _ => var.clone(),
+                })
+                .collect(),
+            ports,
+            data_dir: mounted.or(bound),
+            config: Box::new(config),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::PullImage { image } => write!(f, "pull the image {image}"),
+            Action::LoadImage {
+                archive, manifest, ..
+            } => {
+                write!(f, "load the image from {}", archive.display())?;
+                match manifest {
+                    Some(manifest) => write!(f, ", checked against {}", manifest.display()),
+                    None => Ok(()),
+                }
+            }
+            Action::VerifyImage { image, check, .. } => {
+                write!(f, "resolve the digest of {image}")?;
+                if check.expect_digest.is_some() || check.signature.is_some() {
+                    write!(f, " and verify it")?;
+                }
+                Ok(())
+            }
+            Action::CreateDirectory { path } => write!(f, "create the directory {path}"),
+            Action::BackupDirectory { path, archive } => {
+                write!(f, "back up the directory {path} to {}", archive.display())
+            }
+            Action::DeleteDirectory { path } => {
+                write!(f, "delete the directory {path} and everything in it")
+            }
+            Action::RemoveContainer { name, force } => {
+                write!(f, "remove the container {name}")?;
+                if *force {
+                    write!(f, ", killing it if it runs")?;
+                }
+                Ok(())
+            }
+            Action::CreateContainer {
+                name,
+                image,
+                env,
+                ports,
+                data_dir,
+                ..
+            } => {
+                write!(f, "create the container {name} from {image}")?;
+                if !ports.is_empty() {
+                    write!(f, ", publishing {}", ports.join(", "))?;
+                }
+                if let Some(data_dir) = data_dir {
+                    write!(f, ", with data in {data_dir}")?;
+                }
+                if !env.is_empty() {
+                    write!(f, ", with env {}", env.join(" "))?;
+                }
+                Ok(())
+            }
+            Action::StartContainer { name } => write!(f, "start the container {name}"),
+            Action::StopContainer { name, stop } if stop.timeout.is_zero() => {
+                write!(f, "kill the container {name} if it runs")
+            }
+            Action::StopContainer { name, stop } => write!(
+                f,
+                "stop the container {name} if it runs, killing it if it does not exit within {} \
+                 of {}",
+                humantime::format_duration(stop.timeout),
+                stop.signal.as_deref().unwrap_or("its stop signal")
+            ),
+            Action::RestartContainer { name, stop } if stop.timeout.is_zero() => {
+                write!(f, "kill the container {name} and start it again")
+            }
+            Action::RestartContainer { name, stop } => write!(
+                f,
+                "restart the container {name}, killing it if it does not exit within {} of {}",
+                humantime::format_duration(stop.timeout),
+                stop.signal.as_deref().unwrap_or("its stop signal")
+            ),
+            Action::PruneImages { keep, images } if images.is_empty() => write!(
+                f,
+                "remove the unused ILLA Builder images but the {keep} newest"
+            ),
+            Action::PruneImages { images, .. } => {
+                write!(f, "remove the unused images {}", images.join(", "))
+            }
+        }
+    }
+}
+
+/// The ordered steps of a command that changes an ILLA Builder. The same
+/// plan is printed by `--dry-run` and carried out otherwise.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+}
+
+/// What carrying out a plan produced, for the command's report
+#[derive(Debug, Clone, Default)]
+pub struct Outcome {
+    /// The image pulled or loaded
+    pub image: Option<String>,
+    pub pulled: Option<String>,
+    pub loaded: Option<ImageRef>,
+    pub pinned: Option<PinnedImage>,
+    pub pruned: Option<PruneSummary>,
+    pub backup: Option<PathBuf>,
+    /// The containers removed so far, to put back if a later step fails
+    pub removed: Vec<String>,
+    pub created: Vec<String>,
+    pub started: Vec<String>,
+    /// How a restarted container was stopped
+    pub stopped: Option<Shutdown>,
+}
+
+impl Plan {
+    pub fn push(&mut self, action: Action) {
+        self.actions.push(action);
+    }
+
+    /// Print what the plan would do, without doing it
+    pub fn show(&self, ui: &Ui, command: &str) -> Result {
+        if ui.is_structured() {
+            return ui.emit(
+                &CommandReport::success(command)
+                    .message("Dry run, nothing was changed")
+                    .detail("dry_run", true)
+                    .detail("plan", &self.actions),
+            );
+        }
+        if self.actions.is_empty() {
+            ui.println(format!(
+                "{} Dry run, `illa {command}` would change nothing",
+                ui::emoji::SUCCESS
+            ));
+            return Ok(());
+        }
+        ui.println(format!(
+            "{} Dry run, `illa {command}` would:",
+            ui::emoji::LOOKING_GLASS
+        ));
+        self.print(ui);
+        Ok(())
+    }
+
+    /// Print the numbered steps
+    pub fn print(&self, ui: &Ui) {
+        for (i, action) in self.actions.iter().enumerate() {
+            ui.println(format!("  {}. {action}", i + 1));
+        }
+    }
+
+    /// Carry out the plan step by step, stopping at the first failure.
+    /// `outcome` tells how far it got.
+    ///
+    /// Ctrl-C cancels a pull or load in flight; other steps are one call
+    /// to the daemon and finish first. The plan then stops before its next
+    /// step, and the containers it created but did not start are removed
+    /// again.
+    pub async fn execute(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        progress: &Progress,
+        outcome: &mut Outcome,
+    ) -> Result {
+        let _guard = interrupt::guard();
+        self.execute_until(runtime, progress, outcome, interrupt::subscribe())
+            .await
+    }
+
+    // Carry out the plan until `interrupt` turns true
+    async fn execute_until(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        progress: &Progress,
+        outcome: &mut Outcome,
+        mut interrupt: watch::Receiver<bool>,
+    ) -> Result {
+        for action in &self.actions {
+            let pressed = *interrupt.borrow();
+            let done = match (pressed, action.is_streaming()) {
+                (true, _) => None,
+                (false, true) => tokio::select! {
+                    done = execute(runtime, progress, action, outcome) => Some(done),
+                    _ = interrupt.wait_for(|pressed| *pressed) => None,
+                },
+                (false, false) => Some(execute(runtime, progress, action, outcome).await),
+            };
+            if let Some(done) = done {
+                done?;
+                continue;
+            }
+            roll_back(runtime, progress, outcome).await;
+            return Err(IllaError::Interrupted(format!(
+                "stopped before it could {action}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Containers created but never started would block the next run with a
+// name conflict
+async fn roll_back(runtime: &dyn ContainerRuntime, progress: &Progress, outcome: &mut Outcome) {
+    let unstarted: Vec<_> = outcome
+        .created
+        .iter()
+        .filter(|name| !outcome.started.contains(name))
+        .cloned()
+        .collect();
+    for name in unstarted {
+        let pb_rollback = progress.step();
+        pb_rollback.set_message(format!("Removing the unstarted {name}..."));
+        match runtime.remove(&name, true).await {
+            Ok(()) => {
+                outcome.created.retain(|created| *created != name);
+                pb_rollback.finish(format!(
+                    "{} Removed the unstarted {name}",
+                    ui::emoji::SUCCESS
+                ));
+            }
+            Err(e) => pb_rollback.finish(format!(
+                "{} Could not remove the unstarted {name}: {e}",
+                ui::emoji::WARN
+            )),
+        }
+    }
+}
+
+async fn execute(
+    runtime: &dyn ContainerRuntime,
+    progress: &Progress,
+    action: &Action,
+    outcome: &mut Outcome,
+) -> Result {
+    match action {
+        Action::PullImage { image } => {
+            let download_started = Instant::now();
+            let pulled = utils::pull_image(runtime, progress, image).await?;
+            progress.step().finish(format!(
+                "{} Downloaded in {} ({})",
+                ui::emoji::SUCCESS,
+                HumanDuration(download_started.elapsed()),
+                pulled.describe()
+            ));
+            outcome.image = Some(image.clone());
+            outcome.pulled = pulled.digest;
+        }
+        Action::LoadImage {
+            archive,
+            manifest,
+            requested,
+            expect_digest,
+        } => {
+            let (loaded, _) = image::load_archive(
+                runtime,
+                progress,
+                archive,
+                requested,
+                manifest.as_deref(),
+                expect_digest.as_deref(),
+            )
+            .await?;
+            outcome.image = Some(runtime.info().image_name(&loaded.to_string()));
+            outcome.loaded = Some(loaded);
+        }
+        Action::VerifyImage {
+            image,
+            requested,
+            check,
+        } => {
+            let image = outcome.image.clone().unwrap_or_else(|| image.clone());
+            let requested = outcome.loaded.as_ref().unwrap_or(requested);
+            let pinned =
+                image::pin_image(runtime, requested, &image, outcome.pulled.take(), check).await?;
+            outcome.pinned = Some(pinned);
+        }
+        Action::CreateDirectory { path } => {
+            utils::local_bind_init(path);
+        }
+        Action::BackupDirectory { path, archive } => {
+            let pb_backup = progress.step();
+            pb_backup.set_message(format!("Backing up {path}..."));
+            let size = match utils::backup_dir(path, archive) {
+                Ok(size) => size,
+                Err(e) => {
+                    pb_backup.clear();
+                    return Err(e);
+                }
+            };
+            outcome.backup = Some(archive.clone());
+            pb_backup.finish(format!(
+                "{} Backed up {path} to {} ({})",
+                ui::emoji::SUCCESS,
+                archive.display(),
+                HumanBytes(size)
+            ));
+        }
+        Action::DeleteDirectory { path } => utils::local_bind_delete(path.clone())?,
+        Action::RemoveContainer { name, force } => {
+            let pb_rm = progress.step();
+            pb_rm.set_message(format!("Removing {name}..."));
+            if let Err(e) = runtime.remove(name, *force).await {
+                pb_rm.clear();
+                return Err(IllaError::container(name, e, IllaError::ContainerRemove));
+            }
+            outcome.removed.push(name.clone());
+            pb_rm.finish(format!("{} Removed {name}", ui::emoji::SUCCESS));
+        }
+        Action::CreateContainer { name, config, .. } => {
+            let mut config = config.as_ref().clone();
+            let labels = config.labels.get_or_insert_with(Default::default);
+            if let Some(loaded) = &outcome.loaded {
+                labels.insert(IMAGE_LABEL.to_string(), loaded.to_string());
+            }
+            if let Some(pinned) = &outcome.pinned {
+                labels.insert(DIGEST_LABEL.to_string(), pinned.digest.clone());
+                config.image = Some(pinned.reference.clone());
+            }
+            runtime
+                .create(name, config)
+                .await
+                .map_err(IllaError::ContainerCreate)?;
+            outcome.created.push(name.clone());
+        }
+        Action::StartContainer { name } => {
+            runtime
+                .start(name)
+                .await
+                .map_err(|e| IllaError::container(name, e, IllaError::ContainerStart))?;
+            outcome.started.push(name.clone());
+        }
+        // The commands report how the Builder stopped themselves
+        Action::StopContainer { name, stop } => {
+            let pb_stop = progress.step();
+            let stopped = stop::stop_gracefully(runtime, &pb_stop, name, "stop", stop).await;
+            pb_stop.clear();
+            outcome.stopped =
+                stopped.map_err(|e| IllaError::container(name, e, IllaError::ContainerStop))?;
+        }
+        Action::RestartContainer { name, stop } => {
+            let restart_error = |e| IllaError::container(name, e, IllaError::ContainerRestart);
+            let pb_stop = progress.step();
+            let stopped = stop::stop_gracefully(runtime, &pb_stop, name, "restart", stop).await;
+            pb_stop.clear();
+            outcome.stopped = stopped.map_err(restart_error)?;
+            // The Builder is down by now, unless its restart policy brought
+            // it back meanwhile; then it is stopped again the daemon's way
+            runtime
+                .restart(name, stop.timeout.as_secs() as isize)
+                .await
+                .map_err(restart_error)?;
+            outcome.started.push(name.clone());
+        }
+        Action::PruneImages { keep, .. } => {
+            // The change itself succeeded; a failed cleanup only warrants a warning
+            match prune::prune_images(runtime, progress, *keep).await {
+                Ok(pruned) => outcome.pruned = Some(pruned),
+                Err(e) => progress.step().finish(format!(
+                    "{} Could not remove the old images: {e}",
+                    ui::emoji::WARN
+                )),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+
+    #[tokio::test]
+    async fn roll_back_removes_unstarted_containers() {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        let config = builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa");
+        let plan = Plan {
+            actions: vec![Action::create_container("illa_builder", config)],
+        };
+        let progress = Ui::default().progress();
+        let mut outcome = Outcome::default();
+        plan.execute(&runtime, &progress, &mut outcome)
+            .await
+            .unwrap();
+        runtime.add_container(
+            "illa_builder_old",
+            builder_config("illasoft/illa-builder:1.2.0", 9090, "/data/old"),
+            false,
+        );
+        outcome.created.push(String::from("illa_builder_old"));
+        outcome.started.push(String::from("illa_builder_old"));
+
+        roll_back(&runtime, &progress, &mut outcome).await;
+
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(runtime.has_container("illa_builder_old"));
+        assert_eq!(outcome.created, ["illa_builder_old"]);
+    }
+
+    #[tokio::test]
+    async fn interrupted_create_finishes_and_is_rolled_back() {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        let config = builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa");
+        let plan = Plan {
+            actions: vec![
+                Action::create_container("illa_builder", config),
+                Action::StartContainer {
+                    name: String::from("illa_builder"),
+                },
+            ],
+        };
+        let (press, interrupt) = watch::channel(false);
+        runtime.on("create", move || {
+            press.send_replace(true);
+        });
+        let mut outcome = Outcome::default();
+
+        let result = plan
+            .execute_until(&runtime, &Ui::default().progress(), &mut outcome, interrupt)
+            .await;
+
+        assert!(
+            matches!(result, Err(IllaError::Interrupted(ref m)) if m.contains("start")),
+            "{result:?}"
+        );
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(outcome.created.is_empty());
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("start")));
+    }
+}
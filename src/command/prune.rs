@@ -0,0 +1,311 @@
+use crate::{
+    command::{
+        image::normalize_tag,
+        plan::{Action, Plan},
+        ui::{CommandReport, Progress, Ui},
+        *,
+    },
+    registry::{ImageRef, DEFAULT_IMAGE},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, RuntimeError, IMAGE_LABEL, MIRROR_LABEL},
+};
+use bollard::models::ImageSummary;
+use clap::{ArgAction::SetTrue, Args};
+use indicatif::HumanBytes;
+use std::collections::HashSet;
+
+/// How many unused Builder images are kept for rolling back by default
+pub const DEFAULT_KEEP: usize = 1;
+
+// Executes the `illa prune` command to
+// remove superseded ILLA Builder images
+#[derive(Debug, Args)]
+/// Remove ILLA Builder images no instance uses anymore
+pub struct Cmd {
+    /// How many of the newest unused images to keep for rolling back
+    #[clap(long = "keep", value_name = "N", default_value_t = DEFAULT_KEEP)]
+    keep: usize,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for other illa runs changing an ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let runtime = runtime::connect().await?;
+        if self.dry_run {
+            return prune_plan(&runtime, self.keep).await?.show(ui, "prune");
+        }
+        // An update in progress still needs the image it replaces
+        let names: Vec<_> = list::instance_records(&runtime)
+            .await?
+            .into_iter()
+            .map(|record| record.name)
+            .collect();
+        let pruned = lock::hold_all(&runtime, ui, &names, "prune", self.wait, async {
+            prune_images(&runtime, &ui.progress(), self.keep).await
+        })
+        .await?;
+        ui.emit(
+            &CommandReport::success("prune")
+                .message(pruned.describe())
+                .detail("removed", &pruned.removed)
+                .detail("reclaimed_bytes", pruned.reclaimed),
+        )
+    }
+}
+
+/// The Builder images a prune removed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PruneSummary {
+    pub removed: Vec<String>,
+    pub reclaimed: u64,
+}
+
+impl PruneSummary {
+    pub fn describe(&self) -> String {
+        match self.removed.len() {
+            0 => String::from("No unused ILLA Builder images"),
+            n => format!(
+                "Removed {n} unused ILLA Builder image{}, reclaimed {}",
+                if n == 1 { "" } else { "s" },
+                HumanBytes(self.reclaimed)
+            ),
+        }
+    }
+}
+
+/// Remove the Builder images no managed container uses, keeping the
+/// `keep` newest of them. Images of other repositories are never touched,
+/// and an image the runtime reports as in use is skipped.
+pub async fn prune_images(
+    runtime: &dyn ContainerRuntime,
+    progress: &Progress,
+    keep: usize,
+) -> Result<PruneSummary> {
+    let pb_prune = progress.step();
+    pb_prune.set_message("Looking for unused ILLA Builder images...");
+    let pruned = prune(runtime, keep).await;
+    match &pruned {
+        Ok(pruned) => pb_prune.finish(format!("{} {}", ui::emoji::SUCCESS, pruned.describe())),
+        Err(_) => pb_prune.clear(),
+    }
+    pruned
+}
+
+// An unused Builder image and the names it is removed by
+struct Candidate {
+    image: ImageSummary,
+    targets: Vec<String>,
+}
+
+// The unused Builder images beyond the `keep` newest
+async fn candidates(runtime: &dyn ContainerRuntime, keep: usize) -> Result<Vec<Candidate>> {
+    // A lease of a running `illa update` counts too, as it still holds
+    // the image being replaced
+    let builders = runtime.list("illa_builder").await?;
+    let in_use: HashSet<_> = builders.iter().filter_map(|b| b.image_id.clone()).collect();
+    // Only the Builders illa deployed name repositories; a container that
+    // merely has `illa_builder` in its name may run anything
+    let mut repositories: HashSet<String> = HashSet::from([repository(DEFAULT_IMAGE)]);
+    for builder in &builders {
+        let labels = builder.labels.clone().unwrap_or_default();
+        let Some(image) = labels
+            .get(IMAGE_LABEL)
+            .and_then(|i| ImageRef::parse(i).ok())
+        else {
+            continue;
+        };
+        let mirrored = image.mirrored(labels.get(MIRROR_LABEL).map(String::as_str));
+        repositories.insert(repository(&image.name()));
+        repositories.insert(repository(&mirrored.name()));
+        if let Some(image) = &builder.image {
+            repositories.insert(repository(image));
+        }
+    }
+
+    let mut unused: Vec<ImageSummary> = runtime
+        .list_images()
+        .await?
+        .into_iter()
+        .filter(|image| !in_use.contains(&image.id))
+        .filter(|image| {
+            image
+                .repo_tags
+                .iter()
+                .chain(&image.repo_digests)
+                .any(|name| repositories.contains(&repository(name)))
+        })
+        .collect();
+    unused.sort_by_key(|image| std::cmp::Reverse(image.created));
+
+    Ok(unused
+        .into_iter()
+        .skip(keep)
+        .map(|image| {
+            // Untag the Builder tags one by one; an untagged image goes by ID
+            let tags: Vec<_> = image
+                .repo_tags
+                .iter()
+                .filter(|tag| repositories.contains(&repository(tag)))
+                .cloned()
+                .collect();
+            let targets = if tags.is_empty() {
+                vec![image.id.clone()]
+            } else {
+                tags
+            };
+            Candidate { image, targets }
+        })
+        .collect())
+}
+
+// The prune `illa prune --dry-run` shows, naming the images it would
+// remove
+async fn prune_plan(runtime: &dyn ContainerRuntime, keep: usize) -> Result<Plan> {
+    let images: Vec<_> = candidates(runtime, keep)
+        .await?
+        .into_iter()
+        .flat_map(|candidate| candidate.targets)
+        .collect();
+    let mut plan = Plan::default();
+    if !images.is_empty() {
+        plan.push(Action::PruneImages { keep, images });
+    }
+    Ok(plan)
+}
+
+async fn prune(runtime: &dyn ContainerRuntime, keep: usize) -> Result<PruneSummary> {
+    let mut pruned = PruneSummary::default();
+    for Candidate { image, targets } in candidates(runtime, keep).await? {
+        let mut removed = true;
+        for target in &targets {
+            match runtime.remove_image(target).await {
+                Ok(()) => {}
+                Err(e) if in_use_conflict(&e) => removed = false,
+                Err(e) => return Err(IllaError::Runtime(e)),
+            }
+        }
+        if removed && targets.len() >= image.repo_tags.len() {
+            pruned.reclaimed += image.size.max(0) as u64;
+        }
+        if removed {
+            pruned.removed.extend(targets);
+        }
+    }
+    Ok(pruned)
+}
+
+// The repository of `name:tag` or `name@digest`, named the same way
+// whether a Docker Hub image is listed with its registry or not
+fn repository(reference: &str) -> String {
+    let name = ImageRef::parse(reference)
+        .map(|image| image.name())
+        .unwrap_or_else(|_| reference.to_string());
+    normalize_tag(&name).to_string()
+}
+
+fn in_use_conflict(e: &RuntimeError) -> bool {
+    matches!(
+        e,
+        RuntimeError::DockerResponseServerError {
+            status_code: 409,
+            ..
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime, FAKE_DIGEST};
+    use bollard::container::Config;
+
+    fn runtime_with_releases() -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        for tag in ["1.0.0", "1.1.0", "1.2.0"] {
+            runtime.add_image(&format!("illasoft/illa-builder:{tag}"));
+        }
+        runtime.add_image("postgres:15");
+        runtime.add_container(
+            "illa_builder",
+            builder_config(
+                &format!("illasoft/illa-builder:1.2.0@{FAKE_DIGEST}"),
+                8080,
+                "/data/illa",
+            ),
+            true,
+        );
+        runtime
+    }
+
+    #[tokio::test]
+    async fn keeps_used_and_newest_unused_images() {
+        let runtime = runtime_with_releases();
+
+        let pruned = prune_images(&runtime, &Ui::default().progress(), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(pruned.removed, ["illasoft/illa-builder:1.0.0"]);
+        assert_eq!(pruned.reclaimed, 3072);
+        assert!(runtime.has_image("illasoft/illa-builder:1.1.0"));
+        assert!(runtime.has_image("illasoft/illa-builder:1.2.0"));
+        assert!(runtime.has_image("postgres:15"));
+    }
+
+    #[tokio::test]
+    async fn unlabelled_lookalike_does_not_widen_the_prune() {
+        let runtime = runtime_with_releases();
+        for tag in ["1", "2"] {
+            runtime.add_image(&format!("acme/tools:{tag}"));
+        }
+        runtime.add_container(
+            "illa_builder_backup_tools",
+            Config {
+                image: Some(String::from("acme/tools:2")),
+                ..Default::default()
+            },
+            true,
+        );
+
+        let pruned = prune_images(&runtime, &Ui::default().progress(), 0)
+            .await
+            .unwrap();
+
+        assert!(runtime.has_image("acme/tools:1"));
+        assert!(!pruned.removed.iter().any(|image| image.starts_with("acme")));
+    }
+
+    #[tokio::test]
+    async fn plan_names_the_images_without_removing_them() {
+        let runtime = runtime_with_releases();
+
+        let plan = prune_plan(&runtime, 1).await.unwrap();
+
+        assert!(
+            matches!(plan.actions.as_slice(), [Action::PruneImages { images, .. }] if images == &["illasoft/illa-builder:1.0.0"]),
+            "{plan:?}"
+        );
+        assert!(runtime.has_image("illasoft/illa-builder:1.0.0"));
+    }
+
+    #[tokio::test]
+    async fn keep_zero_removes_every_unused_image() {
+        let runtime = runtime_with_releases();
+
+        let pruned = prune_images(&runtime, &Ui::default().progress(), 0)
+            .await
+            .unwrap();
+
+        assert_eq!(pruned.removed.len(), 2);
+        assert!(runtime.has_image("illasoft/illa-builder:1.2.0"));
+        assert!(runtime.is_running("illa_builder"));
+    }
+}
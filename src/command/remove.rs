@@ -1,14 +1,20 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::container::{InspectContainerOptions, RemoveContainerOptions};
-use bollard::Docker;
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime},
+    state::{State, StateStore},
+};
 use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use console::style;
-use std::process;
+use std::path::PathBuf;
 
 // Executes the `illa remove` command to
 // remove one or more ILLA Builder
-#[derive(Debug, Args)]
+#[derive(Debug, Default, Args)]
 #[clap(group(
     ArgGroup::new("remove")
         .required(true)
@@ -31,82 +37,302 @@ pub struct Cmd {
     /// Remove the persistent data of ILLA Builder
     #[clap(short = 'd', long = "data", action = SetTrue)]
     data: bool,
+
+    /// Archive the persistent data to a tarball before deleting it
+    #[clap(long = "backup", action = SetTrue, requires = "data")]
+    backup: bool,
+
+    /// Where to write the backup [default: ~/.local/share/illa/backups]
+    #[clap(long = "backup-dir", value_name = "DIR", requires = "backup")]
+    backup_dir: Option<PathBuf>,
+
+    /// Delete the data without asking for confirmation
+    #[clap(short = 'y', long = "yes", action = SetTrue)]
+    yes: bool,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
+    pub async fn run(&self, ui: &Ui) -> Result {
         let (self_host, cloud) = (self.self_host, self.cloud);
         match (self_host, cloud) {
-            (true, _) => remove_local(self.force, self.data).await?,
-            (_, true) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
+            (true, _) => {
+                let runtime = runtime::connect().await?;
+                if self.dry_run {
+                    return remove_plan(&runtime, self, &managed_data_dirs())
+                        .await?
+                        .show(ui, "remove");
+                }
+                let outcome =
+                    lock::hold(&runtime, ui, "illa_builder", "remove", self.wait, async {
+                        let plan = remove_plan(&runtime, self, &managed_data_dirs()).await?;
+                        if self.data && !self.yes {
+                            ui.println(format!("{} `illa remove` will:", ui::emoji::WARN));
+                            plan.print(ui);
+                            ui.confirm("Delete the ILLA Builder data for good?")?;
+                        }
+                        let outcome = remove_local(&runtime, ui, &plan).await?;
+                        state::forget(ui, "illa_builder");
+                        Ok(outcome)
+                    })
+                    .await?;
+                ui.emit(
+                    &CommandReport::success("remove")
+                        .instance("illa_builder")
+                        .detail("data_removed", self.data)
+                        .detail("backup", outcome.backup),
+                )?
+            }
+            (_, true) => utils::cloud_onboarding(ui, "remove")?,
             _ => unreachable!(),
         };
         Ok(())
     }
 }
 
-async fn remove_local(is_force: bool, data: bool) -> Result {
-    println!("{} Trying to remove the ILLA Builder...", ui::emoji::BUILD);
-
-    let _docker = Docker::connect_with_local_defaults().unwrap();
-    if (_docker.ping().await).is_err() {
-        println!(
-            "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-            ui::emoji::FAIL,
-            String::from("No running docker found."),
-            ui::emoji::WARN,
-            style("Please check the status of docker with command: docker info").red(),
-            String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-            style("https://docs.docker.com/engine/install/").blue(),
-            String::from("Once Docker is installed, please try running the command again."),
+async fn remove_local(runtime: &dyn ContainerRuntime, ui: &Ui, plan: &Plan) -> Result<Outcome> {
+    ui.println(format!(
+        "{} Trying to remove the ILLA Builder...",
+        ui::emoji::BUILD
+    ));
+
+    let mut outcome = Outcome::default();
+    plan.execute(runtime, &ui.progress(), &mut outcome).await?;
+
+    ui.println(format!(
+        "{} {}",
+        ui::emoji::SUCCESS,
+        style("Successfully remove the ILLA Builder.").green(),
+    ));
+
+    Ok(outcome)
+}
+
+// The data is deleted only once the container is gone, so a failed
+// removal leaves a working Builder behind
+async fn remove_plan(
+    runtime: &dyn ContainerRuntime,
+    cmd: &Cmd,
+    managed: &[String],
+) -> Result<Plan> {
+    let mut plan = Plan::default();
+    let builder_info = utils::inspect_builder(runtime, "illa_builder").await?;
+    plan.push(Action::RemoveContainer {
+        name: String::from("illa_builder"),
+        force: cmd.force,
+    });
+    if cmd.data {
+        let path = utils::builder_data_dir(&builder_info).ok_or_else(|| {
+            IllaError::InstanceConfig(String::from(
+                "illa_builder: no host directory is bound to the Builder data directory",
+            ))
+        })?;
+        utils::check_deletable(&path, managed)?;
+        if cmd.backup {
+            let dir = cmd
+                .backup_dir
+                .clone()
+                .unwrap_or_else(utils::default_backup_dir);
+            plan.push(Action::BackupDirectory {
+                path: path.clone(),
+                archive: utils::backup_archive(&dir, "illa_builder"),
+            });
+        }
+        plan.push(Action::DeleteDirectory { path });
+    }
+    Ok(plan)
+}
+
+fn managed_data_dirs() -> Vec<String> {
+    let state = StateStore::open().and_then(|store| store.load()).ok();
+    managed_dirs(state.as_ref(), &utils::get_default_mount())
+}
+
+// The default data root, and the data directories `illa deploy` created
+// elsewhere, as the state file records. Any other directory is the user's.
+fn managed_dirs(state: Option<&State>, default_mount: &str) -> Vec<String> {
+    let created = state
+        .into_iter()
+        .flat_map(|state| state.instances.values())
+        .filter(|instance| instance.data_dir_created)
+        .map(|instance| instance.spec.data_dir.clone());
+    std::iter::once(default_mount.to_string())
+        .chain(created)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+    use std::{env, fs, slice};
+    use uuid::Uuid;
+
+    fn runtime_with_builder(data_dir: &str, running: bool) -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:latest", 8080, data_dir),
+            running,
         );
-        process::exit(1);
+        runtime
     }
 
-    if data {
-        let inspect_options = Some(InspectContainerOptions { size: false });
-        let builder_detail = &_docker
-            .inspect_container("illa_builder", inspect_options)
-            .await;
-        if builder_detail.is_err() {
-            println!(
-                "{} {}\n",
-                ui::emoji::FAIL,
-                String::from("No ILLA Builder found."),
-            );
-            process::exit(1);
+    fn temp_data_dir() -> String {
+        let dir = env::temp_dir().join(format!("illa-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.display().to_string()
+    }
+
+    fn data_cmd(force: bool) -> Cmd {
+        Cmd {
+            self_host: true,
+            force,
+            data: true,
+            ..Default::default()
         }
-        let builder_info = builder_detail.as_ref().unwrap();
-        let builder_mount_cp = &builder_info
-            .host_config
-            .as_ref()
-            .unwrap()
-            .mounts
-            .clone()
+    }
+
+    async fn remove(runtime: &FakeRuntime, cmd: &Cmd, managed: &[String]) -> Result<Outcome> {
+        let plan = remove_plan(runtime, cmd, managed).await?;
+        remove_local(runtime, &Ui::default(), &plan).await
+    }
+
+    #[tokio::test]
+    async fn removes_builder_and_keeps_data() {
+        let data_dir = temp_data_dir();
+        let runtime = runtime_with_builder(&data_dir, false);
+
+        remove(&runtime, &Cmd::default(), &[]).await.unwrap();
+
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(fs::metadata(&data_dir).is_ok());
+        fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn removes_data_directory() {
+        let data_dir = temp_data_dir();
+        let runtime = runtime_with_builder(&data_dir, true);
+
+        remove(&runtime, &data_cmd(true), slice::from_ref(&data_dir))
+            .await
             .unwrap();
-        utils::local_bind_delete(builder_mount_cp[0].source.clone().unwrap());
+
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(fs::metadata(&data_dir).is_err());
     }
 
-    let options = Some(RemoveContainerOptions {
-        force: is_force,
-        ..Default::default()
-    });
-    let stop_builder = _docker.remove_container("illa_builder", options).await;
-    if stop_builder.is_err() {
-        println!(
-            "{} {} {}",
-            ui::emoji::FAIL,
-            String::from("Try to remove ILLA Builder error:"),
-            style(stop_builder.err().unwrap()).red(),
+    #[tokio::test]
+    async fn failed_removal_keeps_data() {
+        let data_dir = temp_data_dir();
+        let runtime = runtime_with_builder(&data_dir, true);
+
+        let result = remove(&runtime, &data_cmd(false), slice::from_ref(&data_dir)).await;
+
+        assert!(matches!(result, Err(IllaError::ContainerRemove(_))));
+        assert!(fs::metadata(&data_dir).is_ok());
+        fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn backs_up_data_before_deleting() {
+        let data_dir = temp_data_dir();
+        fs::write(format!("{data_dir}/pg_version"), "14").unwrap();
+        let backup_dir = env::temp_dir().join(format!("illa-backup-{}", Uuid::new_v4()));
+        let runtime = runtime_with_builder(&data_dir, false);
+        let cmd = Cmd {
+            backup: true,
+            backup_dir: Some(backup_dir.clone()),
+            ..data_cmd(false)
+        };
+
+        let outcome = remove(&runtime, &cmd, slice::from_ref(&data_dir))
+            .await
+            .unwrap();
+
+        let archive = outcome.backup.unwrap();
+        assert!(archive.starts_with(&backup_dir));
+        let mut tarball = tar::Archive::new(fs::File::open(&archive).unwrap());
+        let names: Vec<_> = tarball
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(
+            names.iter().any(|name| name.ends_with("pg_version")),
+            "{names:?}"
         );
-        process::exit(1);
+        assert!(fs::metadata(&data_dir).is_err());
+        fs::remove_dir_all(backup_dir).ok();
     }
 
-    println!(
-        "{} {}",
-        ui::emoji::SUCCESS,
-        style("Successfully remove the ILLA Builder.").green(),
-    );
+    #[tokio::test]
+    async fn refuses_to_delete_unmanaged_data() {
+        let home = dirs::home_dir().unwrap().display().to_string();
+        let data_dir = temp_data_dir();
+
+        for (path, managed) in [
+            ("/", vec![String::from("/")]),
+            (home.as_str(), vec![home.clone()]),
+            (data_dir.as_str(), vec![utils::get_default_mount()]),
+        ] {
+            let runtime = runtime_with_builder(path, false);
 
-    Ok(())
+            let result = remove(&runtime, &data_cmd(false), &managed).await;
+
+            assert!(
+                matches!(result, Err(IllaError::InvalidArgument(ref m)) if m.contains("refusing")),
+                "{path}: {result:?}"
+            );
+            assert!(runtime.has_container("illa_builder"));
+        }
+        assert!(fs::metadata(&data_dir).is_ok());
+        fs::remove_dir_all(data_dir).ok();
+    }
+
+    #[tokio::test]
+    async fn deletes_the_default_data_dir_without_a_state_entry() {
+        // The Builder was deployed before the state file, to the default root
+        let root = temp_data_dir();
+        let data_dir = format!("{root}/.illa-builder");
+        fs::create_dir_all(&data_dir).unwrap();
+        let runtime = runtime_with_builder(&data_dir, false);
+
+        remove(&runtime, &data_cmd(false), &managed_dirs(None, &data_dir))
+            .await
+            .unwrap();
+
+        assert!(fs::metadata(&data_dir).is_err());
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[tokio::test]
+    async fn missing_builder_fails() {
+        let runtime = FakeRuntime::new();
+
+        let result = remove(&runtime, &Cmd::default(), &[]).await;
+
+        assert!(matches!(result, Err(IllaError::ContainerNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn running_builder_needs_force() {
+        let data_dir = temp_data_dir();
+        let runtime = runtime_with_builder(&data_dir, true);
+
+        let result = remove(&runtime, &Cmd::default(), &[]).await;
+
+        assert!(matches!(result, Err(IllaError::ContainerRemove(_))));
+        assert!(runtime.has_container("illa_builder"));
+        fs::remove_dir_all(data_dir).ok();
+    }
 }
@@ -1,9 +1,15 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::{container::RestartContainerOptions, Docker};
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    result::Result,
+    runtime::{self, ContainerRuntime},
+    state::Shutdown,
+};
 use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use console::style;
-use std::process;
 
 // Executes the `illa restart` command to
 // restart one or more ILLA Builder
@@ -22,54 +28,123 @@ pub struct Cmd {
     /// Restart ILLA Builder on ILLA Cloud
     #[clap(short = 'C', long = "cloud", action = SetTrue)]
     cloud: bool,
+
+    #[clap(flatten)]
+    stop: stop::StopArgs,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
+    pub async fn run(&self, ui: &Ui) -> Result {
         let (self_host, cloud) = (self.self_host, self.cloud);
         match (self_host, cloud) {
-            (true, _) => restart_local().await?,
-            (_, true) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
+            (true, _) => {
+                let runtime = runtime::connect().await?;
+                if self.dry_run {
+                    return restart_plan(&self.stop).show(ui, "restart");
+                }
+                let shutdown =
+                    lock::hold(&runtime, ui, "illa_builder", "restart", self.wait, async {
+                        let shutdown = restart_local(&runtime, ui, &self.stop).await?;
+                        if let Some(shutdown) = &shutdown {
+                            state::record_stop(&runtime, ui, "illa_builder", shutdown.clone())
+                                .await;
+                        }
+                        Ok(shutdown)
+                    })
+                    .await?;
+                ui.emit(
+                    &CommandReport::success("restart")
+                        .instance("illa_builder")
+                        .detail("shutdown", shutdown),
+                )?
+            }
+            (_, true) => utils::cloud_onboarding(ui, "restart")?,
             _ => unreachable!(),
         };
         Ok(())
     }
 }
 
-async fn restart_local() -> Result {
-    println!("{} Trying to restart the ILLA Builder...", ui::emoji::BUILD);
+async fn restart_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    stop: &stop::StopArgs,
+) -> Result<Option<Shutdown>> {
+    ui.println(format!(
+        "{} Trying to restart the ILLA Builder...",
+        ui::emoji::BUILD
+    ));
 
-    let _docker = Docker::connect_with_local_defaults().unwrap();
-    if (_docker.ping().await).is_err() {
-        println!(
-            "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-            ui::emoji::FAIL,
-            String::from("No running docker found."),
-            ui::emoji::WARN,
-            style("Please check the status of docker with command: docker info").red(),
-            String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-            style("https://docs.docker.com/engine/install/").blue(),
-            String::from("Once Docker is installed, please try running the command again."),
-        );
-        process::exit(1);
-    }
-    let options = Some(RestartContainerOptions { t: 30 });
-    let stop_builder = _docker.restart_container("illa_builder", options).await;
-    if stop_builder.is_err() {
-        println!(
-            "{} {} {}",
-            ui::emoji::FAIL,
-            String::from("Try to restart ILLA Builder error:"),
-            style(stop_builder.err().unwrap()).red(),
-        );
-        process::exit(1);
-    }
+    let mut outcome = Outcome::default();
+    restart_plan(stop)
+        .execute(runtime, &ui.progress(), &mut outcome)
+        .await?;
 
-    println!(
+    if outcome
+        .stopped
+        .as_ref()
+        .is_some_and(|stopped| stopped.killed)
+    {
+        ui.println(format!(
+            "{} The ILLA Builder did not exit by itself and was killed.",
+            ui::emoji::WARN
+        ));
+    }
+    ui.println(format!(
         "{} {}",
         ui::emoji::SUCCESS,
         style("Successfully restart the ILLA Builder.").green(),
-    );
+    ));
+
+    Ok(outcome.stopped)
+}
 
-    Ok(())
+fn restart_plan(stop: &stop::StopArgs) -> Plan {
+    Plan {
+        actions: vec![Action::RestartContainer {
+            name: String::from("illa_builder"),
+            stop: stop.clone(),
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn stuck_builder_is_killed_and_started_again() {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            true,
+        );
+        runtime.ignore_signals("illa_builder");
+        let stop = stop::StopArgs {
+            timeout: Duration::from_secs(1),
+            signal: None,
+        };
+
+        let shutdown = restart_local(&runtime, &Ui::default(), &stop)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(shutdown.killed);
+        assert_eq!(shutdown.command, "restart");
+        assert!(runtime.is_running("illa_builder"));
+    }
 }
@@ -0,0 +1,508 @@
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
+    },
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, RuntimeError},
+    spec::InstanceSpec,
+};
+use bollard::models::HealthStatusEnum;
+use clap::{ArgAction::SetTrue, ArgGroup, Args};
+use console::style;
+use futures_util::StreamExt;
+use std::{
+    env,
+    io::ErrorKind,
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+// How often a starting Builder is checked for readiness
+const READY_POLL: Duration = Duration::from_millis(500);
+
+// How long one readiness probe waits for the Builder to answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// How many of its last log lines a Builder that is not ready is shown with
+const LOG_TAIL: usize = 10;
+
+// Executes the `illa start` command to
+// start one or more stopped ILLA Builder
+#[derive(Debug, Default, Args)]
+#[clap(group(
+    ArgGroup::new("start")
+        .required(true)
+        .args(&["self_host", "cloud", "all"]),
+))]
+/// Start one or more stopped ILLA Builder
+pub struct Cmd {
+    /// Start Self-hosted ILLA Builder
+    #[clap(short = 'S', long = "self", action = SetTrue)]
+    self_host: bool,
+
+    /// Start ILLA Builder on ILLA Cloud
+    #[clap(short = 'C', long = "cloud", action = SetTrue)]
+    cloud: bool,
+
+    /// Start every Self-hosted ILLA Builder, e.g. after host maintenance
+    #[clap(short = 'A', long = "all", action = SetTrue)]
+    all: bool,
+
+    /// How long to wait for the ILLA Builder to answer requests, or 0s
+    /// to not wait
+    #[clap(
+        long = "ready-timeout",
+        value_name = "DURATION",
+        default_value = "2m",
+        value_parser = humantime::parse_duration
+    )]
+    ready_timeout: Duration,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        if self.cloud {
+            return utils::cloud_onboarding(ui, "start");
+        }
+        let runtime = runtime::connect().await?;
+        let names = match self.all {
+            true => managed_instances(&runtime).await?,
+            false => vec![String::from("illa_builder")],
+        };
+        if names.is_empty() {
+            return Err(IllaError::ContainerNotFound(String::from(
+                "no ILLA Builder to start",
+            )));
+        }
+        if self.dry_run {
+            return start_plan(&runtime, &names).await?.show(ui, "start");
+        }
+
+        // One instance failing does not keep the others down
+        let (mut started, mut running, mut failed) = (vec![], vec![], None);
+        for name in &names {
+            let result = lock::hold(&runtime, ui, name, "start", self.wait, async {
+                start_local(&runtime, ui, name, self.ready_timeout).await
+            })
+            .await;
+            match result {
+                Ok(true) => started.push(name.clone()),
+                Ok(false) => running.push(name.clone()),
+                Err(e) if names.len() > 1 => {
+                    ui.println(format!("{} {name}: {e}", ui::emoji::WARN));
+                    failed.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e);
+        }
+
+        let mut report = CommandReport::success("start");
+        if let [name] = names.as_slice() {
+            report = report.instance(name);
+        }
+        ui.emit(
+            &report
+                .detail("started", started)
+                .detail("already_running", running),
+        )
+    }
+}
+
+// The names of the Builders illa manages
+async fn managed_instances(runtime: &dyn ContainerRuntime) -> Result<Vec<String>> {
+    Ok(list::instance_records(runtime)
+        .await?
+        .into_iter()
+        .map(|record| record.name)
+        .collect())
+}
+
+// The Builders among `names` that are stopped and would be started
+async fn start_plan(runtime: &dyn ContainerRuntime, names: &[String]) -> Result<Plan> {
+    let mut plan = Plan::default();
+    for name in names {
+        let running = utils::inspect_builder(runtime, name)
+            .await?
+            .state
+            .and_then(|state| state.running)
+            .unwrap_or(false);
+        if !running {
+            plan.push(Action::StartContainer { name: name.clone() });
+        }
+    }
+    Ok(plan)
+}
+
+// Start the Builder `name` unless it runs already, which is reported as
+// `false`, and wait for it to answer requests
+async fn start_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    name: &str,
+    ready_timeout: Duration,
+) -> Result<bool> {
+    let plan = start_plan(runtime, &[name.to_string()]).await?;
+    if plan.actions.is_empty() {
+        ui.println(format!("{} {name} is already running", ui::emoji::SUCCESS));
+        return Ok(false);
+    }
+    let spec = InstanceSpec::from_inspect(&utils::inspect_builder(runtime, name).await?)?;
+
+    // Something else may have taken the port while the Builder was stopped.
+    // A remote daemon's ports cannot be checked from here; its own start
+    // error tells instead.
+    if daemon_host().is_none() {
+        if let Err(e) = TcpListener::bind((spec.host_ip.as_str(), spec.host_port)) {
+            if e.kind() == ErrorKind::AddrInUse {
+                return Err(port_in_use(&spec));
+            }
+        }
+    }
+
+    let progress = ui.progress();
+    let step = progress.step();
+    step.set_message(format!("{} Starting {name}...", ui::emoji::BUILD));
+    plan.execute(runtime, &progress, &mut Outcome::default())
+        .await
+        .map_err(|e| match e {
+            IllaError::ContainerStart(e) if is_port_conflict(&e) => port_in_use(&spec),
+            e => e,
+        })?;
+
+    if !ready_timeout.is_zero() {
+        step.set_message(format!(
+            "{} Waiting for {name} to be ready...",
+            ui::emoji::LOOKING_GLASS
+        ));
+        let address = ready_address(&spec);
+        wait_ready(runtime, name, address.as_deref(), ready_timeout).await?;
+    }
+
+    step.finish(format!(
+        "{} {} {}",
+        ui::emoji::SPARKLE,
+        style(format!("{name} is up, please visit")).green(),
+        style(spec.url()).blue(),
+    ));
+    Ok(true)
+}
+
+// Wait until the Builder `name` runs, is healthy if it has a healthcheck,
+// and answers HTTP on `address` when it can be reached from here
+async fn wait_ready(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+    address: Option<&str>,
+    timeout: Duration,
+) -> Result {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let state = utils::inspect_builder(runtime, name)
+            .await?
+            .state
+            .unwrap_or_default();
+        if !state.running.unwrap_or(false) {
+            return Err(IllaError::NotReady(format!(
+                "{name} stopped right after starting, with exit code {}; {}",
+                state.exit_code.unwrap_or_default(),
+                last_logs(runtime, name).await
+            )));
+        }
+        let healthy = state
+            .health
+            .and_then(|health| health.status)
+            .is_none_or(|status| status == HealthStatusEnum::HEALTHY);
+        if healthy && address.is_none() {
+            return Ok(());
+        }
+        if let Some(address) = address.filter(|_| healthy) {
+            if answers_http(address).await {
+                return Ok(());
+            }
+        }
+        if Instant::now() >= deadline {
+            return Err(IllaError::NotReady(format!(
+                "{name} runs but did not answer on {} within {}; {}",
+                address.unwrap_or("its healthcheck"),
+                humantime::format_duration(timeout),
+                last_logs(runtime, name).await
+            )));
+        }
+        tokio::time::sleep(READY_POLL).await;
+    }
+}
+
+// The last lines the Builder logged, to tell why it is not ready
+async fn last_logs(runtime: &dyn ContainerRuntime, name: &str) -> String {
+    let lines: Vec<String> = runtime
+        .logs(name, LOG_TAIL)
+        .filter_map(|line| async { line.ok() })
+        .collect()
+        .await;
+    match lines.concat().trim_end() {
+        "" => format!("see `docker logs {name}`"),
+        logged => format!("its last log lines:\n{logged}"),
+    }
+}
+
+// Whether an HTTP server answers on `address`. Docker's userland proxy
+// accepts connections on a published port as soon as the container starts,
+// so an accepted connection alone does not mean the Builder listens.
+async fn answers_http(address: &str) -> bool {
+    let probe = async {
+        let mut stream = TcpStream::connect(address).await?;
+        stream
+            .write_all(format!("GET / HTTP/1.0\r\nHost: {address}\r\n\r\n").as_bytes())
+            .await?;
+        let mut status = [0; 5];
+        stream.read_exact(&mut status).await?;
+        std::io::Result::Ok(&status == b"HTTP/")
+    };
+    matches!(
+        tokio::time::timeout(PROBE_TIMEOUT, probe).await,
+        Ok(Ok(true))
+    )
+}
+
+// Where the Builder's published port can be reached from here: the address
+// it is bound to, or for all addresses the daemon's host. A port bound to
+// the loopback of a remote daemon cannot be reached at all.
+fn ready_address(spec: &InstanceSpec) -> Option<String> {
+    let host = match spec.host_ip.as_str() {
+        "" | "0.0.0.0" | "::" => daemon_host().unwrap_or_else(|| String::from("127.0.0.1")),
+        ip if daemon_host().is_some() && is_loopback(ip) => return None,
+        ip => ip.to_string(),
+    };
+    Some(match host.contains(':') {
+        true => format!("[{host}]:{}", spec.host_port),
+        false => format!("{host}:{}", spec.host_port),
+    })
+}
+
+fn is_loopback(ip: &str) -> bool {
+    ip.parse::<std::net::IpAddr>()
+        .map(|ip| ip.is_loopback())
+        .unwrap_or(ip == "localhost")
+}
+
+fn port_in_use(spec: &InstanceSpec) -> IllaError {
+    IllaError::PortInUse(format!(
+        "port {} of {} is taken by another program; free it, or move the Builder \
+         with `illa deploy -S --recreate -p <PORT>`",
+        spec.host_port, spec.name
+    ))
+}
+
+// How Docker and Podman fail to publish a port that is taken
+fn is_port_conflict(e: &RuntimeError) -> bool {
+    let message = e.to_string();
+    ["port is already allocated", "address already in use"]
+        .iter()
+        .any(|conflict| message.contains(conflict))
+}
+
+// The host of a daemon reached over the network, whose ports are not ours
+fn daemon_host() -> Option<String> {
+    ["DOCKER_HOST", "CONTAINER_HOST"].iter().find_map(|var| {
+        let host = env::var(var).ok()?;
+        let host = host
+            .strip_prefix("tcp://")
+            .or_else(|| host.strip_prefix("ssh://"))?;
+        let host = host.rsplit('@').next()?;
+        let host = host.split(['/', ':']).next()?;
+        Some(host.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{
+        fake::{builder_config, FakeRuntime},
+        LEASE_LABEL,
+    };
+    use bollard::container::Config;
+    use std::collections::HashMap;
+
+    fn runtime_with_builder(port: u16, running: bool) -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", port, "/data/illa"),
+            running,
+        );
+        runtime
+    }
+
+    #[tokio::test]
+    async fn starts_a_stopped_builder() {
+        let runtime = runtime_with_builder(0, false);
+        let ui = Ui::default();
+
+        let started = start_local(&runtime, &ui, "illa_builder", Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(started);
+        assert!(runtime.is_running("illa_builder"));
+
+        let started = start_local(&runtime, &ui, "illa_builder", Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(!started);
+    }
+
+    #[tokio::test]
+    async fn taken_port_is_reported() {
+        let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let runtime = runtime_with_builder(port, false);
+
+        let result = start_local(&runtime, &Ui::default(), "illa_builder", Duration::ZERO).await;
+
+        assert!(
+            matches!(result, Err(IllaError::PortInUse(ref m)) if m.contains(&port.to_string())),
+            "{result:?}"
+        );
+        assert!(!runtime.is_running("illa_builder"));
+    }
+
+    #[tokio::test]
+    async fn ready_once_the_port_answers_http() {
+        let runtime = runtime_with_builder(0, true);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        // Accepting without answering is what a proxy in front of a Builder
+        // that does not listen yet does
+        let silent = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+            listener
+        });
+        let result = wait_ready(&runtime, "illa_builder", Some(&address), Duration::ZERO).await;
+        assert!(matches!(result, Err(IllaError::NotReady(_))), "{result:?}");
+
+        let listener = silent.await.unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"GET ");
+            stream
+                .write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        wait_ready(
+            &runtime,
+            "illa_builder",
+            Some(&address),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn probes_the_address_the_port_is_bound_to() {
+        let runtime = runtime_with_builder(8080, true);
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let mut spec = InstanceSpec::from_inspect(&info).unwrap();
+        spec.host_ip = String::from("192.168.1.10");
+        assert_eq!(ready_address(&spec).as_deref(), Some("192.168.1.10:8080"));
+        spec.host_ip = String::from("::1");
+        assert_eq!(ready_address(&spec).as_deref(), Some("[::1]:8080"));
+    }
+
+    #[tokio::test]
+    async fn builder_that_exits_is_shown_with_its_logs() {
+        let runtime = runtime_with_builder(0, false);
+        runtime.add_logs(
+            "illa_builder",
+            &[
+                "starting ILLA Builder",
+                "FATAL: could not open the database",
+            ],
+        );
+
+        let result = wait_ready(&runtime, "illa_builder", None, Duration::from_secs(5)).await;
+
+        assert!(
+            matches!(result, Err(IllaError::NotReady(ref m))
+                if m.ends_with("could not open the database")),
+            "{result:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn all_starts_every_managed_instance() {
+        let runtime = runtime_with_builder(0, false);
+        runtime.add_container(
+            "illa_builder_staging",
+            builder_config("illasoft/illa-builder:1.2.0", 0, "/data/staging"),
+            false,
+        );
+        runtime.add_container(
+            "illa_builder.lease",
+            Config {
+                image: Some(String::from("illasoft/illa-builder:1.2.0")),
+                labels: Some(HashMap::from([(
+                    LEASE_LABEL.to_string(),
+                    String::from("illa_builder"),
+                )])),
+                ..Default::default()
+            },
+            false,
+        );
+
+        let names = managed_instances(&runtime).await.unwrap();
+
+        assert_eq!(names.len(), 2, "{names:?}");
+        for name in &names {
+            start_local(&runtime, &Ui::default(), name, Duration::ZERO)
+                .await
+                .unwrap();
+        }
+        assert!(runtime.is_running("illa_builder"));
+        assert!(runtime.is_running("illa_builder_staging"));
+    }
+
+    #[tokio::test]
+    async fn plan_starts_only_stopped_builders() {
+        let runtime = runtime_with_builder(0, true);
+        runtime.add_container(
+            "illa_builder_staging",
+            builder_config("illasoft/illa-builder:1.2.0", 0, "/data/staging"),
+            false,
+        );
+
+        let plan = start_plan(&runtime, &managed_instances(&runtime).await.unwrap())
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(plan.actions.as_slice(), [Action::StartContainer { name }] if name == "illa_builder_staging"),
+            "{plan:?}"
+        );
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("start")));
+    }
+}
@@ -0,0 +1,554 @@
+use crate::{
+    command::{lock::Locks, ui::Ui, *},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime, DIGEST_LABEL},
+    spec::InstanceSpec,
+    state::{InstanceState, Interruption, Shutdown, StateStore},
+};
+use bollard::models::ContainerStateStatusEnum;
+use clap::{Args, Subcommand};
+use console::style;
+use prettytable::{color, Attr};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+use std::time::SystemTime;
+
+// Executes the `illa state` command to
+// inspect and repair the local record of managed instances
+#[derive(Debug, Args)]
+/// Show or repair the local record of managed ILLA Builders
+pub struct Cmd {
+    #[clap(subcommand)]
+    cmd: StateCmds,
+}
+
+#[derive(Debug, Subcommand)]
+enum StateCmds {
+    Show(ShowCmd),
+    Repair(RepairCmd),
+}
+
+/// Show the recorded instances, their digests and deploy history
+#[derive(Debug, Args)]
+struct ShowCmd {}
+
+/// Reconcile the state file with the containers the runtime has
+#[derive(Debug, Args)]
+struct RepairCmd {}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        let store = StateStore::open()?;
+        match &self.cmd {
+            StateCmds::Show(_) => show(ui, &store),
+            StateCmds::Repair(_) => {
+                let runtime = runtime::connect().await?;
+                let repairs = repair(&runtime, ui, &store, &Locks::open()?).await?;
+                if ui.is_structured() {
+                    return ui.emit(&repairs);
+                }
+                for repair in &repairs {
+                    ui.println(format!(
+                        "{} {} {}: {}",
+                        ui::emoji::SUCCESS,
+                        style(&repair.action).bold(),
+                        repair.instance,
+                        repair.reason
+                    ));
+                }
+                if repairs.is_empty() {
+                    ui.println(format!(
+                        "{} The state file matches the runtime",
+                        ui::emoji::SUCCESS
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn show(ui: &Ui, store: &StateStore) -> Result {
+    let state = store.load()?;
+    if ui.is_structured() {
+        return ui.emit(&state);
+    }
+
+    let mut table = Table::new();
+    table.add_row(Row::new(
+        [
+            "Name",
+            "Image",
+            "Digest",
+            "Last change",
+            "Changes",
+            "Last stop",
+        ]
+        .iter()
+        .map(|title| Cell::new(title).with_style(Attr::ForegroundColor(color::GREEN)))
+        .collect(),
+    ));
+    for (name, instance) in &state.instances {
+        let digest = instance.digest.as_deref().unwrap_or("-");
+        let last = instance
+            .history
+            .last()
+            .map(|last| format!("{} ({})", last.at, last.command))
+            .unwrap_or_else(|| String::from("-"));
+        let stop = instance
+            .last_stop
+            .as_ref()
+            .map(|stop| {
+                let how = if stop.killed { "killed" } else { "clean" };
+                format!("{} ({how})", stop.at)
+            })
+            .unwrap_or_else(|| String::from("-"));
+        table.add_row(Row::new(vec![
+            Cell::new(&format!("/{name}")).with_style(Attr::ForegroundColor(color::BLUE)),
+            Cell::new(&instance.spec.image),
+            Cell::new(&digest[..19.min(digest.len())]),
+            Cell::new(&last),
+            Cell::new(&instance.history.len().to_string()),
+            Cell::new(&stop),
+        ]));
+    }
+    table.printstd();
+    for (name, interruption) in &state.interrupted {
+        ui.println(format!(
+            "{} `illa {}` on {name} was interrupted at {}: {}",
+            ui::emoji::WARN,
+            interruption.command,
+            interruption.at,
+            interruption.during
+        ));
+    }
+    ui.println(format!("State file: {}", store.path().display()));
+    Ok(())
+}
+
+/// A change `illa state repair` made to bring the state file in line with
+/// the runtime
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Repair {
+    pub instance: String,
+    /// `added`, `updated`, `removed`, `skipped`, `cleaned` or `cleared`
+    pub action: String,
+    pub reason: String,
+}
+
+impl Repair {
+    fn new(instance: &str, action: &str, reason: impl Into<String>) -> Self {
+        Repair {
+            instance: instance.to_string(),
+            action: action.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+async fn repair(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    store: &StateStore,
+    locks: &Locks,
+) -> Result<Vec<Repair>> {
+    // Read the daemon before taking the state file lock, so it is not held
+    // across network round trips
+    let mut found = vec![];
+    let mut repairs = vec![];
+    let mut busy = vec![];
+
+    // An interrupted command may have left a container it created but
+    // could not start, which would block the next run. One whose instance
+    // is locked may be a run creating it right now, so it is left alone.
+    let interrupted = store.load().map(|state| state.interrupted);
+    for (name, interruption) in interrupted.iter().flatten() {
+        let unstarted = match runtime.inspect(name, false).await {
+            Ok(info) => {
+                info.state.and_then(|state| state.status) == Some(ContainerStateStatusEnum::CREATED)
+            }
+            Err(_) => false,
+        };
+        if !unstarted {
+            continue;
+        }
+        let lock = match locks.acquire(runtime, ui, name, "repair", false).await {
+            Ok(lock) => lock,
+            Err(IllaError::Locked(_)) => {
+                busy.push(name.clone());
+                repairs.push(Repair::new(
+                    name,
+                    "skipped",
+                    "another illa run holds its lock; run `illa state repair` again once it finishes",
+                ));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let removed = runtime
+            .remove(name, true)
+            .await
+            .map_err(|e| IllaError::container(name, e, IllaError::ContainerRemove));
+        lock.release(runtime).await;
+        removed?;
+        repairs.push(Repair::new(
+            name,
+            "cleaned",
+            format!(
+                "removed the container the interrupted `illa {}` left unstarted",
+                interruption.command
+            ),
+        ));
+    }
+
+    for builder in list::instance_records(runtime).await? {
+        if busy.contains(&builder.name) {
+            continue;
+        }
+        let info = utils::inspect_builder(runtime, &builder.name).await?;
+        match InstanceSpec::from_inspect(&info) {
+            Ok(spec) => found.push((spec, digest_of(&info))),
+            Err(e) => repairs.push(Repair::new(&builder.name, "skipped", e.to_string())),
+        }
+    }
+
+    store.rebuild(|state| {
+        for (spec, digest) in &found {
+            let seen = InstanceState::new(spec, digest.clone());
+            match state.instances.get_mut(&spec.name) {
+                None => {
+                    state.instances.insert(spec.name.clone(), seen);
+                    repairs.push(Repair::new(
+                        &spec.name,
+                        "added",
+                        "the container was not recorded",
+                    ));
+                }
+                Some(recorded)
+                    if (&recorded.spec, &recorded.digest) != (&seen.spec, &seen.digest) =>
+                {
+                    recorded.spec = seen.spec;
+                    recorded.secrets = seen.secrets;
+                    recorded.digest = seen.digest;
+                    repairs.push(Repair::new(
+                        &spec.name,
+                        "updated",
+                        "the container was changed outside illa",
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+        let skipped: Vec<_> = repairs
+            .iter()
+            .filter(|r| r.action == "skipped")
+            .map(|r| r.instance.clone())
+            .collect();
+        let gone: Vec<_> = state
+            .instances
+            .keys()
+            .filter(|name| !found.iter().any(|(spec, _)| &spec.name == *name))
+            .filter(|name| !skipped.contains(name))
+            .cloned()
+            .collect();
+        for name in gone {
+            state.instances.remove(&name);
+            repairs.push(Repair::new(
+                &name,
+                "removed",
+                "the container no longer exists",
+            ));
+        }
+        let interrupted = std::mem::take(&mut state.interrupted);
+        for (name, interruption) in interrupted {
+            if busy.contains(&name) {
+                state.interrupted.insert(name, interruption);
+                continue;
+            }
+            repairs.push(Repair::new(
+                &name,
+                "cleared",
+                format!(
+                    "the interrupted `illa {}` was cleaned up",
+                    interruption.command
+                ),
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(repairs)
+}
+
+fn digest_of(info: &bollard::models::ContainerInspectResponse) -> Option<String> {
+    info.config
+        .as_ref()?
+        .labels
+        .as_ref()?
+        .get(DIGEST_LABEL)
+        .cloned()
+}
+
+/// Record in the state file that `command` left the instance `name` as it
+/// is now. The container stays the source of truth, so a failure only
+/// warrants a warning.
+pub async fn record(runtime: &dyn ContainerRuntime, ui: &Ui, name: &str, command: &str) {
+    let recorded = async {
+        let info = utils::inspect_builder(runtime, name).await?;
+        let spec = InstanceSpec::from_inspect(&info)?;
+        StateStore::open()?.update(|state| {
+            state.record(&spec, digest_of(&info), command, SystemTime::now());
+            Ok(())
+        })
+    };
+    if let Err(e) = recorded.await {
+        warn(ui, name, e);
+    }
+}
+
+/// Record how `name` was stopped, adding the instance if the state file
+/// does not know it yet
+pub async fn record_stop(runtime: &dyn ContainerRuntime, ui: &Ui, name: &str, shutdown: Shutdown) {
+    let recorded = async {
+        let info = utils::inspect_builder(runtime, name).await?;
+        let spec = InstanceSpec::from_inspect(&info)?;
+        StateStore::open()?.update(|state| {
+            state
+                .instances
+                .entry(name.to_string())
+                .or_insert_with(|| InstanceState::new(&spec, digest_of(&info)))
+                .last_stop = Some(shutdown);
+            Ok(())
+        })
+    };
+    if let Err(e) = recorded.await {
+        warn(ui, name, e);
+    }
+}
+
+/// Record that deploying `name` created its data directory, adding the
+/// instance if the state file does not know it yet
+pub async fn record_data_dir_created(runtime: &dyn ContainerRuntime, ui: &Ui, name: &str) {
+    let recorded = async {
+        let info = utils::inspect_builder(runtime, name).await?;
+        let spec = InstanceSpec::from_inspect(&info)?;
+        StateStore::open()?.update(|state| {
+            state
+                .instances
+                .entry(name.to_string())
+                .or_insert_with(|| InstanceState::new(&spec, digest_of(&info)))
+                .data_dir_created = true;
+            Ok(())
+        })
+    };
+    if let Err(e) = recorded.await {
+        warn(ui, name, e);
+    }
+}
+
+/// Drop the removed instance `name` from the state file
+pub fn forget(ui: &Ui, name: &str) {
+    let forgotten = StateStore::open().and_then(|store| {
+        store.update(|state| {
+            state.instances.remove(name);
+            Ok(())
+        })
+    });
+    if let Err(e) = forgotten {
+        warn(ui, name, e);
+    }
+}
+
+/// Tell about an earlier command on `name` that was interrupted, and how
+/// to go on from there
+pub fn report_interrupted(ui: &Ui, name: &str) {
+    let Ok(state) = StateStore::open().and_then(|store| store.load()) else {
+        return;
+    };
+    if let Some(interruption) = state.interrupted.get(name) {
+        ui.println(format!(
+            "{} `illa {}` on {name} was interrupted at {}: {}. Run it again to resume, or run `illa state repair` to clean up.",
+            ui::emoji::WARN,
+            interruption.command,
+            interruption.at,
+            interruption.during
+        ));
+    }
+}
+
+/// Record that `command` on `name` was interrupted `during` a step
+pub fn mark_interrupted(ui: &Ui, name: &str, command: &str, during: &str) {
+    let marked = StateStore::open().and_then(|store| {
+        store.update(|state| {
+            let interruption = Interruption::new(command, during, SystemTime::now());
+            state.interrupted.insert(name.to_string(), interruption);
+            Ok(())
+        })
+    });
+    if let Err(e) = marked {
+        warn(ui, name, e);
+    }
+}
+
+/// Forget an interrupted command on `name` once a later one succeeded
+pub fn clear_interrupted(ui: &Ui, name: &str) {
+    let cleared = StateStore::open().and_then(|store| {
+        if !store.load()?.interrupted.contains_key(name) {
+            return Ok(());
+        }
+        store.update(|state| {
+            state.interrupted.remove(name);
+            Ok(())
+        })
+    });
+    if let Err(e) = cleared {
+        warn(ui, name, e);
+    }
+}
+
+fn warn(ui: &Ui, name: &str, e: IllaError) {
+    ui.println(format!(
+        "{} Could not update {name} in the state file: {e}",
+        ui::emoji::WARN
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+    use std::{collections::HashMap, env};
+    use uuid::Uuid;
+
+    fn temp_store() -> StateStore {
+        StateStore::at(
+            env::temp_dir()
+                .join(format!("illa-state-{}", Uuid::new_v4()))
+                .join("state.json"),
+        )
+    }
+
+    fn temp_locks() -> Locks {
+        Locks::at(env::temp_dir().join(format!("illa-locks-{}", Uuid::new_v4())))
+    }
+
+    // A container created but never started, as an interrupted deploy
+    // leaves behind
+    async fn interrupted_deploy(runtime: &FakeRuntime, store: &StateStore) {
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime
+            .create(
+                "illa_builder",
+                builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            )
+            .await
+            .unwrap();
+        store
+            .update(|state| {
+                let interruption = Interruption::new(
+                    "deploy",
+                    "stopped before it could start the container illa_builder",
+                    SystemTime::now(),
+                );
+                state
+                    .interrupted
+                    .insert(String::from("illa_builder"), interruption);
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn repair_reconciles_with_the_runtime() {
+        let store = temp_store();
+        let runtime = FakeRuntime::new();
+        let mut config = builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa");
+        config.labels = Some(HashMap::from([(
+            DIGEST_LABEL.to_string(),
+            String::from("sha256:new"),
+        )]));
+        runtime.add_container("illa_builder", config, true);
+        let stale = InstanceSpec {
+            name: String::from("illa_builder_old"),
+            ..InstanceSpec::from_inspect(&runtime.inspect("illa_builder", false).await.unwrap())
+                .unwrap()
+        };
+        store
+            .update(|state| {
+                state.record(&stale, None, "deploy", SystemTime::now());
+                Ok(())
+            })
+            .unwrap();
+
+        let repairs = repair(&runtime, &Ui::default(), &store, &temp_locks())
+            .await
+            .unwrap();
+
+        let actions: Vec<_> = repairs
+            .iter()
+            .map(|r| (r.instance.as_str(), r.action.as_str()))
+            .collect();
+        assert_eq!(
+            actions,
+            [("illa_builder", "added"), ("illa_builder_old", "removed")]
+        );
+        let state = store.load().unwrap();
+        assert_eq!(
+            state.instances["illa_builder"].digest.as_deref(),
+            Some("sha256:new")
+        );
+        assert!(repair(&runtime, &Ui::default(), &store, &temp_locks())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn repair_cleans_up_after_an_interrupted_deploy() {
+        let store = temp_store();
+        let runtime = FakeRuntime::new();
+        interrupted_deploy(&runtime, &store).await;
+
+        let repairs = repair(&runtime, &Ui::default(), &store, &temp_locks())
+            .await
+            .unwrap();
+
+        let actions: Vec<_> = repairs
+            .iter()
+            .map(|r| (r.instance.as_str(), r.action.as_str()))
+            .collect();
+        assert_eq!(
+            actions,
+            [("illa_builder", "cleaned"), ("illa_builder", "cleared")]
+        );
+        assert!(!runtime.has_container("illa_builder"));
+        assert!(store.load().unwrap().interrupted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn repair_leaves_a_locked_instance_alone() {
+        let store = temp_store();
+        let runtime = FakeRuntime::new();
+        let locks = temp_locks();
+        let ui = Ui::default();
+        interrupted_deploy(&runtime, &store).await;
+        let lock = locks
+            .acquire(&runtime, &ui, "illa_builder", "deploy", false)
+            .await
+            .unwrap();
+
+        let repairs = repair(&runtime, &ui, &store, &locks).await.unwrap();
+
+        let actions: Vec<_> = repairs
+            .iter()
+            .map(|r| (r.instance.as_str(), r.action.as_str()))
+            .collect();
+        assert_eq!(actions, [("illa_builder", "skipped")]);
+        assert!(runtime.has_container("illa_builder"));
+        assert!(store
+            .load()
+            .unwrap()
+            .interrupted
+            .contains_key("illa_builder"));
+        lock.release(&runtime).await;
+    }
+}
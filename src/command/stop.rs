@@ -1,9 +1,25 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::{container::StopContainerOptions, Docker};
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Step, Ui},
+        *,
+    },
+    result::Result,
+    runtime::{self, ContainerRuntime, RuntimeError},
+    state::Shutdown,
+};
 use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use console::style;
-use std::process;
+use serde::{Serialize, Serializer};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+// How often a stopping Builder is checked for having exited
+const STOP_POLL: Duration = Duration::from_millis(500);
+
+// How long a killed Builder is given to be reaped by the daemon, which
+// does not happen when it is stuck in the kernel or the runtime hangs
+const KILL_GRACE: Duration = Duration::from_secs(30);
 
 // Executes the `illa stop` command to
 // stop one or more ILLA Builder
@@ -22,55 +38,338 @@ pub struct Cmd {
     /// Stop ILLA Builder on ILLA Cloud
     #[clap(short = 'C', long = "cloud", action = SetTrue)]
     cloud: bool,
+
+    #[clap(flatten)]
+    stop: StopArgs,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue)]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
+}
+
+/// How the ILLA Builder is asked to exit before it is killed
+#[derive(Debug, Clone, Args, Serialize)]
+pub struct StopArgs {
+    /// How long to wait for the ILLA Builder to exit before killing it,
+    /// e.g. 2m; 0s kills it at once
+    #[clap(
+        long = "timeout",
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(serialize_with = "human_duration")]
+    pub timeout: Duration,
+
+    /// The signal asking the ILLA Builder to exit, e.g. SIGINT; by default
+    /// the one its image declares, or SIGTERM
+    #[clap(long = "signal", value_name = "SIGNAL", value_parser = parse_signal)]
+    pub signal: Option<String>,
+}
+
+impl Default for StopArgs {
+    fn default() -> Self {
+        StopArgs {
+            timeout: Duration::from_secs(30),
+            signal: None,
+        }
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
+    pub async fn run(&self, ui: &Ui) -> Result {
         let (self_host, cloud) = (self.self_host, self.cloud);
         match (self_host, cloud) {
-            (true, _) => stop_local().await?,
-            (_, true) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
+            (true, _) => {
+                let runtime = runtime::connect().await?;
+                if self.dry_run {
+                    return stop_plan(&self.stop).show(ui, "stop");
+                }
+                let shutdown = lock::hold(&runtime, ui, "illa_builder", "stop", self.wait, async {
+                    let shutdown = stop_local(&runtime, ui, &self.stop).await?;
+                    if let Some(shutdown) = &shutdown {
+                        state::record_stop(&runtime, ui, "illa_builder", shutdown.clone()).await;
+                    }
+                    Ok(shutdown)
+                })
+                .await?;
+                ui.emit(
+                    &CommandReport::success("stop")
+                        .instance("illa_builder")
+                        .detail("shutdown", shutdown),
+                )?
+            }
+            (_, true) => utils::cloud_onboarding(ui, "stop")?,
             _ => unreachable!(),
         };
         Ok(())
     }
 }
 
-async fn stop_local() -> Result {
-    println!("{} Trying to stop the ILLA Builder...", ui::emoji::BUILD);
+async fn stop_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    args: &StopArgs,
+) -> Result<Option<Shutdown>> {
+    ui.println(format!(
+        "{} Trying to stop the ILLA Builder...",
+        ui::emoji::BUILD
+    ));
 
-    let _docker = Docker::connect_with_local_defaults().unwrap();
-    if (_docker.ping().await).is_err() {
-        println!(
-            "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-            ui::emoji::FAIL,
-            String::from("No running docker found."),
+    let progress = ui.progress();
+    let mut outcome = Outcome::default();
+    stop_plan(args)
+        .execute(runtime, &progress, &mut outcome)
+        .await?;
+
+    let step = progress.step();
+    match &outcome.stopped {
+        None => step.finish(format!(
+            "{} The ILLA Builder is not running.",
+            ui::emoji::SUCCESS
+        )),
+        Some(shutdown) if shutdown.killed => step.finish(format!(
+            "{} {}",
             ui::emoji::WARN,
-            style("Please check the status of docker with command: docker info").red(),
-            String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-            style("https://docs.docker.com/engine/install/").blue(),
-            String::from("Once Docker is installed, please try running the command again."),
-        );
-        process::exit(1);
+            style("Killed the ILLA Builder, it did not exit by itself.").yellow(),
+        )),
+        Some(_) => step.finish(format!(
+            "{} {}",
+            ui::emoji::SUCCESS,
+            style("Successfully stop the ILLA Builder.").green(),
+        )),
+    }
+
+    Ok(outcome.stopped)
+}
+
+fn stop_plan(args: &StopArgs) -> Plan {
+    Plan {
+        actions: vec![Action::StopContainer {
+            name: String::from("illa_builder"),
+            stop: args.clone(),
+        }],
+    }
+}
+
+/// Ask the container `name` to exit with the stop signal and kill it once
+/// the timeout passes. A container that is not running is left alone, and
+/// one that does not exit even when killed fails the stop.
+pub(crate) async fn stop_gracefully(
+    runtime: &dyn ContainerRuntime,
+    step: &Step,
+    name: &str,
+    command: &str,
+    args: &StopArgs,
+) -> std::result::Result<Option<Shutdown>, RuntimeError> {
+    let info = runtime.inspect(name, false).await?;
+    if !info
+        .state
+        .as_ref()
+        .and_then(|state| state.running)
+        .unwrap_or(false)
+    {
+        return Ok(None);
+    }
+    let signal = match args.timeout.is_zero() {
+        true => String::from("SIGKILL"),
+        false => args
+            .signal
+            .clone()
+            .or_else(|| info.config.and_then(|config| config.stop_signal))
+            .unwrap_or_else(|| String::from("SIGTERM")),
+    };
+    let shutdown = |killed, exit_code| Shutdown {
+        command: command.to_string(),
+        at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        signal: signal.clone(),
+        killed,
+        exit_code,
+    };
+
+    if !is_kill(&signal) {
+        // The container may exit on its own between the checks, which the
+        // daemon reports as a conflict
+        send(runtime, name, &signal).await?;
+        let started = Instant::now();
+        loop {
+            let state = runtime
+                .inspect(name, false)
+                .await?
+                .state
+                .unwrap_or_default();
+            if !state.running.unwrap_or(false) {
+                return Ok(Some(shutdown(false, state.exit_code)));
+            }
+            if started.elapsed() >= args.timeout {
+                break;
+            }
+            step.set_message(format!(
+                "{} Waiting for {name} to exit after {signal} ({}s of {})...",
+                ui::emoji::LOOKING_GLASS,
+                started.elapsed().as_secs(),
+                humantime::format_duration(args.timeout)
+            ));
+            tokio::time::sleep(STOP_POLL).await;
+        }
+        step.set_message(format!(
+            "{} {name} did not exit within {}, killing it...",
+            ui::emoji::WARN,
+            humantime::format_duration(args.timeout)
+        ));
     }
 
-    let options = Some(StopContainerOptions { t: 30 });
-    let stop_builder = _docker.stop_container("illa_builder", options).await;
-    if stop_builder.is_err() {
-        println!(
-            "{} {} {}",
-            ui::emoji::FAIL,
-            String::from("Try to stop ILLA Builder error:"),
-            style(stop_builder.err().unwrap()).red(),
+    // Stopping without a timeout kills the container at once, and the
+    // daemon then waits for it to be reaped, which one stuck in the kernel
+    // or under a hung runtime never is
+    match tokio::time::timeout(KILL_GRACE, runtime.stop(name, 0)).await {
+        Ok(stopped) => stopped?,
+        Err(_) => {
+            return Err(RuntimeError::DockerResponseServerError {
+                status_code: 500,
+                message: format!(
+                    "{name} was killed but still runs after {}; check the container \
+                     runtime on the daemon's host",
+                    humantime::format_duration(KILL_GRACE)
+                ),
+            })
+        }
+    }
+    let state = runtime
+        .inspect(name, false)
+        .await?
+        .state
+        .unwrap_or_default();
+    Ok(Some(shutdown(true, state.exit_code)))
+}
+
+async fn send(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+    signal: &str,
+) -> std::result::Result<(), RuntimeError> {
+    match runtime.kill(name, signal).await {
+        Err(e) if runtime::is_conflict(&e) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_kill(signal: &str) -> bool {
+    signal == "SIGKILL" || signal == "9"
+}
+
+// Accept `TERM`, `sigterm` or `15` for SIGTERM, like `docker stop --signal`
+fn parse_signal(signal: &str) -> std::result::Result<String, String> {
+    let signal = signal.trim().to_uppercase();
+    if signal.is_empty()
+        || !signal
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-')
+    {
+        return Err(format!(
+            "`{signal}` is not a signal, try e.g. SIGTERM or 15"
+        ));
+    }
+    if signal.chars().all(|c| c.is_ascii_digit()) || signal.starts_with("SIG") {
+        return Ok(signal);
+    }
+    Ok(format!("SIG{signal}"))
+}
+
+fn human_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(&humantime::format_duration(*duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::fake::{builder_config, FakeRuntime};
+
+    fn runtime_with_builder() -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            true,
         );
-        process::exit(1);
+        runtime
     }
 
-    println!(
-        "{} {}",
-        ui::emoji::SUCCESS,
-        style("Successfully stop the ILLA Builder.").green(),
-    );
+    async fn stop(runtime: &FakeRuntime, args: &StopArgs) -> Option<Shutdown> {
+        let step = Ui::default().progress().step();
+        stop_gracefully(runtime, &step, "illa_builder", "stop", args)
+            .await
+            .unwrap()
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn builder_exits_on_its_stop_signal() {
+        let runtime = runtime_with_builder();
+
+        let shutdown = stop(&runtime, &StopArgs::default()).await.unwrap();
+
+        assert_eq!(shutdown.signal, "SIGTERM");
+        assert!(!shutdown.killed);
+        assert_eq!(shutdown.exit_code, Some(0));
+        assert!(!runtime.is_running("illa_builder"));
+        assert!(stop(&runtime, &StopArgs::default()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn builder_ignoring_the_signal_is_killed_after_the_timeout() {
+        let runtime = runtime_with_builder();
+        runtime.ignore_signals("illa_builder");
+        let args = StopArgs {
+            timeout: Duration::from_secs(1),
+            signal: Some(String::from("SIGINT")),
+        };
+
+        let shutdown = stop(&runtime, &args).await.unwrap();
+
+        assert_eq!(shutdown.signal, "SIGINT");
+        assert!(shutdown.killed);
+        assert_eq!(shutdown.exit_code, Some(137));
+        let calls: Vec<_> = runtime
+            .calls()
+            .into_iter()
+            .filter(|call| call.starts_with("kill") || call.starts_with("stop"))
+            .collect();
+        assert_eq!(calls, ["kill illa_builder", "stop illa_builder"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn builder_surviving_the_kill_fails_the_stop() {
+        let runtime = runtime_with_builder();
+        runtime.survive_kill("illa_builder");
+        let step = Ui::default().progress().step();
+        let args = StopArgs {
+            timeout: Duration::from_secs(1),
+            signal: None,
+        };
+
+        let result = stop_gracefully(&runtime, &step, "illa_builder", "stop", &args).await;
+
+        assert!(
+            matches!(result, Err(ref e) if e.to_string().contains("was killed but still runs")),
+            "{result:?}"
+        );
+        assert!(runtime.is_running("illa_builder"));
+    }
+
+    #[test]
+    fn signals_are_normalized() {
+        assert_eq!(parse_signal("term").unwrap(), "SIGTERM");
+        assert_eq!(parse_signal("SIGQUIT").unwrap(), "SIGQUIT");
+        assert_eq!(parse_signal("15").unwrap(), "15");
+        assert!(parse_signal("term; rm").is_err());
+    }
 }
@@ -1,9 +1,38 @@
 use console::Emoji;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-pub static LOOKING_GLASS: Emoji<'_, '_> = Emoji("🔍 ", "");
-pub static SUCCESS: Emoji<'_, '_> = Emoji("✅ ", "");
-pub static FAIL: Emoji<'_, '_> = Emoji("❌ ", "");
-pub static SPARKLE: Emoji<'_, '_> = Emoji("✨ ", "");
-pub static WARN: Emoji<'_, '_> = Emoji("❗️ ", "");
-pub static BUILD: Emoji<'_, '_> = Emoji("🔨 ", "");
-pub static DIAMOND: Emoji<'_, '_> = Emoji("💎 ", "");
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Turn emoji off process-wide, so every icon renders its
+/// `console::Emoji` fallback instead
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// An emoji that honours [`set_enabled`]
+pub struct Icon(Emoji<'static, 'static>);
+
+impl fmt::Display for Icon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if enabled() {
+            self.0.fmt(f)
+        } else {
+            f.write_str(self.0 .1)
+        }
+    }
+}
+
+pub static LOOKING_GLASS: Icon = Icon(Emoji("🔍 ", ""));
+pub static SUCCESS: Icon = Icon(Emoji("✅ ", ""));
+pub static FAIL: Icon = Icon(Emoji("❌ ", ""));
+pub static SPARKLE: Icon = Icon(Emoji("✨ ", ""));
+pub static WARN: Icon = Icon(Emoji("❗️ ", ""));
+pub static BUILD: Icon = Icon(Emoji("🔨 ", ""));
+pub static DIAMOND: Icon = Icon(Emoji("💎 ", ""));
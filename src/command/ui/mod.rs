@@ -1 +1,8 @@
 pub mod emoji;
+pub mod output;
+pub mod progress;
+pub mod pull;
+
+pub use output::{CommandReport, OutputFormat, ProgressMode, Ui};
+pub use progress::{Progress, Step};
+pub use pull::{PullProgress, PullSummary};
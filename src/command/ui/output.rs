@@ -0,0 +1,209 @@
+use crate::result::{IllaError, Result};
+use clap::ValueEnum;
+use console::Term;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    fmt::Display,
+    io::{self, IsTerminal},
+};
+
+use super::{emoji, Progress};
+
+/// How command results are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable tables and progress
+    #[default]
+    Table,
+    /// A single JSON document
+    Json,
+    /// A single YAML document
+    Yaml,
+}
+
+/// How progress and prose are written while a command runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Animated spinners and emoji, for a terminal
+    #[default]
+    Interactive,
+    /// One line per progress event and no emoji, for CI logs
+    Plain,
+    /// Only results and errors
+    Quiet,
+}
+
+/// Writes command output in the selected format.
+///
+/// In the structured formats no spinners, emoji or prose reach stdout;
+/// only the final report does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ui {
+    pub format: OutputFormat,
+    pub mode: ProgressMode,
+}
+
+impl Ui {
+    pub fn new(format: OutputFormat, mode: ProgressMode) -> Self {
+        Ui { format, mode }
+    }
+
+    /// Pick the progress mode from the `--plain`/`--quiet` flags, falling
+    /// back to plain output when stdout is not a terminal
+    pub fn detect(format: OutputFormat, plain: bool, quiet: bool) -> Self {
+        let mode = if quiet {
+            ProgressMode::Quiet
+        } else if plain || !Term::stdout().is_term() {
+            ProgressMode::Plain
+        } else {
+            ProgressMode::Interactive
+        };
+        if mode != ProgressMode::Interactive {
+            emoji::set_enabled(false);
+        }
+        Ui::new(format, mode)
+    }
+
+    pub fn is_structured(&self) -> bool {
+        self.format != OutputFormat::Table
+    }
+
+    /// The effective progress mode; structured formats draw no progress
+    pub fn progress_mode(&self) -> ProgressMode {
+        if self.is_structured() {
+            ProgressMode::Quiet
+        } else {
+            self.mode
+        }
+    }
+
+    /// Print a line of prose, only when writing for humans
+    pub fn println(&self, msg: impl Display) {
+        if self.progress_mode() != ProgressMode::Quiet {
+            println!("{}", text(msg));
+        }
+    }
+
+    /// Start drawing the progress of a command
+    pub fn progress(&self) -> Progress {
+        Progress::new(self)
+    }
+
+    /// Ask on the terminal whether to go on with `question`, failing when
+    /// the answer is no. Without a terminal to ask on, scripts have to
+    /// confirm up front with `--yes`.
+    pub fn confirm(&self, question: &str) -> Result {
+        let term = Term::stderr();
+        if !term.is_term() || !io::stdin().is_terminal() {
+            return Err(IllaError::Aborted(String::from(
+                "no terminal to confirm on, pass --yes to go on without asking",
+            )));
+        }
+        term.write_str(&format!("{question} [y/N] "))?;
+        match term.read_line()?.trim().to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => Err(IllaError::Aborted(String::from("nothing was changed"))),
+        }
+    }
+
+    /// Write `value` as a JSON or YAML document; a no-op for tables
+    pub fn emit<T: Serialize>(&self, value: &T) -> Result {
+        match self.format {
+            OutputFormat::Table => {}
+            OutputFormat::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(value).map_err(anyhow::Error::from)?
+            ),
+            OutputFormat::Yaml => print!(
+                "{}",
+                serde_yaml::to_string(value).map_err(anyhow::Error::from)?
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Render `msg` for the terminal. Without emoji the `"{icon} message"`
+/// lines would start with a blank, so the indentation is dropped.
+pub fn text(msg: impl Display) -> String {
+    let msg = msg.to_string();
+    if emoji::enabled() {
+        return msg;
+    }
+    msg.lines()
+        .map(str::trim_start)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub kind: &'static str,
+    pub exit_code: i32,
+    pub message: String,
+}
+
+/// The structured result of a lifecycle command
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandReport {
+    pub command: String,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorReport>,
+    #[serde(flatten)]
+    pub details: Map<String, Value>,
+}
+
+impl CommandReport {
+    pub fn success(command: &str) -> Self {
+        CommandReport {
+            command: command.to_string(),
+            status: Status::Success,
+            instance: None,
+            message: None,
+            error: None,
+            details: Map::new(),
+        }
+    }
+
+    pub fn error(command: &str, e: &IllaError) -> Self {
+        CommandReport {
+            status: Status::Error,
+            error: Some(ErrorReport {
+                kind: e.kind(),
+                exit_code: e.exit_code(),
+                message: e.to_string(),
+            }),
+            ..CommandReport::success(command)
+        }
+    }
+
+    pub fn instance(mut self, name: &str) -> Self {
+        self.instance = Some(name.to_string());
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn detail(mut self, key: &str, value: impl Serialize) -> Self {
+        if let Ok(value) = serde_json::to_value(value) {
+            self.details.insert(key.to_string(), value);
+        }
+        self
+    }
+}
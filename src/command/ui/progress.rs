@@ -0,0 +1,105 @@
+use super::{
+    output::{text, ProgressMode},
+    Ui,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{fmt::Display, sync::Mutex};
+
+/// The steps of one command, drawn according to the [`ProgressMode`]
+pub struct Progress {
+    mode: ProgressMode,
+    multi: MultiProgress,
+}
+
+impl Progress {
+    pub(crate) fn new(ui: &Ui) -> Self {
+        Progress {
+            mode: ui.progress_mode(),
+            multi: MultiProgress::new(),
+        }
+    }
+
+    /// A bar with the given style below the previous ones, when drawing
+    /// to a terminal
+    pub fn bar(&self, style: ProgressStyle) -> Option<ProgressBar> {
+        match self.mode {
+            ProgressMode::Interactive => {
+                let bar = self.multi.add(ProgressBar::new(0));
+                bar.set_style(style);
+                Some(bar)
+            }
+            _ => None,
+        }
+    }
+
+    /// Print a progress event as a line, in plain mode only
+    pub fn log(&self, msg: impl Display) {
+        if self.mode == ProgressMode::Plain {
+            println!("{}", text(msg));
+        }
+    }
+
+    /// Start a new step below the previous ones
+    pub fn step(&self) -> Step {
+        Step {
+            mode: self.mode,
+            bar: self.bar(spinner_style()),
+            last: Mutex::new(String::new()),
+        }
+    }
+}
+
+/// A single spinner line, or a series of log lines in plain mode
+pub struct Step {
+    mode: ProgressMode,
+    bar: Option<ProgressBar>,
+    last: Mutex<String>,
+}
+
+impl Step {
+    /// Report what the step is doing. Plain mode prints a line only when
+    /// the message changes, so polling loops do not flood the log.
+    pub fn set_message(&self, msg: impl Display) {
+        let msg = msg.to_string();
+        match &self.bar {
+            Some(bar) => {
+                bar.set_message(msg);
+                bar.inc(1);
+            }
+            None if self.mode == ProgressMode::Plain => {
+                let msg = text(msg);
+                let mut last = self.last.lock().unwrap();
+                if *last != msg {
+                    println!("{msg}");
+                    *last = msg;
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Replace the spinner with a final message
+    pub fn finish(&self, msg: impl Display) {
+        match &self.bar {
+            Some(bar) => {
+                bar.set_style(ProgressStyle::with_template("{wide_msg}").unwrap());
+                bar.finish_with_message(msg.to_string());
+            }
+            None if self.mode == ProgressMode::Plain => println!("{}", text(msg)),
+            None => {}
+        }
+    }
+
+    /// Remove the spinner without a final message, e.g. before an error
+    pub fn clear(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+fn spinner_style() -> ProgressStyle {
+    ProgressStyle::with_template("{spinner} {wide_msg}")
+        .unwrap()
+        .tick_strings(&["🔸 ", "🔶 ", "🟠 ", "🟠 ", "🔶 "])
+}
@@ -0,0 +1,205 @@
+use super::progress::Progress;
+use bollard::models::CreateImageInfo;
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+
+/// What an image pull ended with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PullSummary {
+    pub digest: Option<String>,
+    pub layers: usize,
+    pub bytes: u64,
+}
+
+/// Draws the progress of an image pull from the runtime's status stream:
+/// one bar per layer plus a total with bytes and ETA in a terminal, or a
+/// line per layer status change in plain mode.
+pub struct PullProgress<'a> {
+    progress: &'a Progress,
+    total: Option<ProgressBar>,
+    layers: HashMap<String, Layer>,
+    digest: Option<String>,
+}
+
+#[derive(Default)]
+struct Layer {
+    status: String,
+    current: u64,
+    total: u64,
+    bar: Option<ProgressBar>,
+}
+
+impl<'a> PullProgress<'a> {
+    pub fn new(progress: &'a Progress, image: &str) -> Self {
+        let total = progress.bar(
+            ProgressStyle::with_template(
+                "{spinner} Downloading {msg} {bytes}/{total_bytes} (eta {eta})",
+            )
+            .unwrap()
+            .tick_strings(&["🔸 ", "🔶 ", "🟠 ", "🟠 ", "🔶 "]),
+        );
+        if let Some(total) = &total {
+            total.set_message(image.to_string());
+        }
+        progress.log(format!("Downloading {image}..."));
+        PullProgress {
+            progress,
+            total,
+            layers: HashMap::new(),
+            digest: None,
+        }
+    }
+
+    /// Apply one event of the pull stream
+    pub fn update(&mut self, info: &CreateImageInfo) {
+        let status = info.status.clone().unwrap_or_default();
+        if let Some(digest) = status.strip_prefix("Digest: ") {
+            self.digest = Some(digest.trim().to_string());
+            return;
+        }
+        let id = match &info.id {
+            // Events without a layer id describe the whole image
+            Some(id) if !status.starts_with("Pulling from") => id.clone(),
+            _ => return,
+        };
+
+        let progress = self.progress;
+        let layer = self.layers.entry(id.clone()).or_insert_with(|| Layer {
+            bar: progress.bar(
+                ProgressStyle::with_template("  {prefix} {msg:<18} {bar:30} {bytes}/{total_bytes}")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        });
+        if let Some(bar) = &layer.bar {
+            bar.set_prefix(id.clone());
+        }
+
+        match status.as_str() {
+            "Downloading" => {
+                if let Some(detail) = &info.progress_detail {
+                    layer.current = detail.current.unwrap_or_default().max(0) as u64;
+                    layer.total = detail.total.unwrap_or_default().max(0) as u64;
+                }
+            }
+            // Everything past the download has all of the layer's bytes
+            "Verifying Checksum" | "Download complete" | "Extracting" | "Pull complete" => {
+                layer.current = layer.total;
+            }
+            _ => {}
+        }
+
+        if let Some(bar) = &layer.bar {
+            match status.as_str() {
+                "Pull complete" | "Already exists" => {
+                    bar.set_style(ProgressStyle::with_template("  {prefix} {msg}").unwrap());
+                    bar.finish_with_message(status.clone());
+                }
+                "Extracting" => {
+                    if let Some(detail) = &info.progress_detail {
+                        bar.set_length(detail.total.unwrap_or_default().max(0) as u64);
+                        bar.set_position(detail.current.unwrap_or_default().max(0) as u64);
+                    }
+                    bar.set_message(status.clone());
+                }
+                _ => {
+                    bar.set_length(layer.total);
+                    bar.set_position(layer.current);
+                    bar.set_message(status.clone());
+                }
+            }
+        } else if layer.status != status {
+            self.progress.log(format!("{id}: {status}"));
+        }
+        layer.status = status;
+
+        if let Some(total) = &self.total {
+            total.set_length(self.layers.values().map(|l| l.total).sum());
+            total.set_position(self.layers.values().map(|l| l.current).sum());
+        }
+    }
+
+    /// Remove the bars, e.g. before reporting an error
+    pub fn clear(&self) {
+        for bar in self.layers.values().filter_map(|l| l.bar.as_ref()) {
+            bar.finish_and_clear();
+        }
+        if let Some(total) = &self.total {
+            total.finish_and_clear();
+        }
+    }
+
+    /// Remove the bars and return what was pulled
+    pub fn finish(self) -> PullSummary {
+        self.clear();
+        PullSummary {
+            layers: self.layers.len(),
+            bytes: self.layers.values().map(|l| l.total).sum(),
+            digest: self.digest,
+        }
+    }
+}
+
+impl PullSummary {
+    /// A one-line description for the finished download step
+    pub fn describe(&self) -> String {
+        let mut summary = format!("{} layers, {}", self.layers, HumanBytes(self.bytes));
+        if let Some(digest) = &self.digest {
+            summary.push_str(&format!(", {digest}"));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::ui::{OutputFormat, ProgressMode, Ui};
+    use bollard::models::ProgressDetail;
+
+    fn event(id: &str, status: &str, current: i64, total: i64) -> CreateImageInfo {
+        CreateImageInfo {
+            id: Some(id.to_string()),
+            status: Some(status.to_string()),
+            progress_detail: Some(ProgressDetail {
+                current: Some(current),
+                total: Some(total),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sums_layers_and_keeps_digest() {
+        let progress = Ui::new(OutputFormat::Table, ProgressMode::Quiet).progress();
+        let mut pull = PullProgress::new(&progress, "illasoft/illa-builder:latest");
+
+        pull.update(&event("latest", "Pulling from illasoft/illa-builder", 0, 0));
+        pull.update(&event("aaa", "Downloading", 10, 100));
+        pull.update(&event("bbb", "Already exists", 0, 0));
+        pull.update(&event("aaa", "Downloading", 100, 100));
+        pull.update(&event("aaa", "Extracting", 5, 100));
+        pull.update(&event("aaa", "Pull complete", 0, 0));
+        pull.update(&CreateImageInfo {
+            status: Some(String::from("Digest: sha256:abc")),
+            ..Default::default()
+        });
+
+        let summary = pull.finish();
+        assert_eq!(summary.layers, 2);
+        assert_eq!(summary.bytes, 100);
+        assert_eq!(summary.digest.as_deref(), Some("sha256:abc"));
+    }
+
+    #[test]
+    fn completed_layers_count_all_bytes() {
+        let progress = Ui::new(OutputFormat::Table, ProgressMode::Quiet).progress();
+        let mut pull = PullProgress::new(&progress, "illasoft/illa-builder:latest");
+
+        pull.update(&event("aaa", "Downloading", 40, 100));
+        pull.update(&event("aaa", "Download complete", 0, 0));
+
+        assert_eq!(pull.layers["aaa"].current, 100);
+        assert_eq!(pull.layers["aaa"].status, "Download complete");
+    }
+}
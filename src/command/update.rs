@@ -1,23 +1,18 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
-use bollard::{
-    container::{
-        Config, CreateContainerOptions, InspectContainerOptions, ListContainersOptions,
-        RemoveContainerOptions, StartContainerOptions, StatsOptions,
+use crate::{
+    command::{
+        plan::{Action, Outcome, Plan},
+        ui::{CommandReport, Ui},
+        *,
     },
-    image::CreateImageOptions,
-    models::{HostConfig, Mount, MountTypeEnum},
-    Docker,
+    registry::{self, ImageRef, Release},
+    result::{ExitStatus, IllaError, Result},
+    runtime::{self, ContainerRuntime, DIGEST_LABEL, IMAGE_LABEL, MIRROR_LABEL},
+    spec::InstanceSpec,
 };
-use clap::{builder, ArgAction::SetTrue, ArgGroup, Args};
+use bollard::container::Config;
+use clap::{ArgAction::SetTrue, ArgGroup, Args};
 use console::style;
-use futures_util::{StreamExt, TryStreamExt};
-use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
-use std::{
-    collections::HashMap,
-    process, thread,
-    time::{Duration, Instant},
-};
+use std::collections::HashMap;
 
 // Executes the `illa update` command to
 // update the ILLA Builder with the latest docker image
@@ -36,238 +31,781 @@ pub struct Cmd {
     /// Update ILLA Builder on ILLA Cloud
     #[clap(short = 'C', long = "cloud", action = SetTrue)]
     cloud: bool,
+
+    /// Switch to another image or mirror; by default the ones the
+    /// Builder was deployed with are kept
+    #[clap(flatten)]
+    source: image::ImageSource,
+
+    /// Move to this version of ILLA Builder; by default a Builder on a
+    /// release moves to the newest release, one on `latest` stays on it
+    #[clap(short = 'V', long = "builder-version", value_name = "X.Y.Z")]
+    builder_version: Option<String>,
+
+    #[clap(flatten)]
+    check: image::ImageCheck,
+
+    /// Only report whether a newer Builder is available; exits with 100
+    /// when one is
+    #[clap(long = "check", action = SetTrue, conflicts_with = "builder_version")]
+    check_only: bool,
+
+    /// Remove the superseded Builder images after updating, keeping the
+    /// newest unused one for rolling back
+    #[clap(long = "prune-old", action = SetTrue, conflicts_with = "check_only")]
+    prune_old: bool,
+
+    /// Print what would be done without doing it
+    #[clap(long = "dry-run", action = SetTrue, conflicts_with = "check_only")]
+    dry_run: bool,
+
+    /// Wait for another illa run changing the ILLA Builder to finish,
+    /// instead of failing
+    #[clap(long = "wait", action = SetTrue)]
+    wait: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self) -> Result {
-        let spinner_style = ProgressStyle::with_template("{spinner} {wide_msg}")
-            .unwrap()
-            .tick_strings(&["🔸 ", "🔶 ", "🟠 ", "🟠 ", "🔶 "]);
-
+    pub async fn run(&self, ui: &Ui) -> Result<ExitStatus> {
         let (self_host, cloud) = (self.self_host, self.cloud);
         match (self_host, cloud) {
-            (true, _) => update_local(spinner_style.clone()).await?,
-            (_, true) => println!("{} Looking forward to onboarding you!", ui::emoji::DIAMOND),
+            (true, _) if self.check_only => {
+                let runtime = runtime::connect().await?;
+                let (report, available) = check_update(&runtime, ui, &self.source).await?;
+                ui.emit(&report)?;
+                if available {
+                    return Ok(ExitStatus::UpdateAvailable);
+                }
+            }
+            (true, _) => {
+                let runtime = runtime::connect().await?;
+                if self.dry_run {
+                    let (plan, _) = update_plan(
+                        &runtime,
+                        ui,
+                        &self.source,
+                        self.builder_version.as_deref(),
+                        &self.check,
+                        self.prune_old,
+                    )
+                    .await?;
+                    plan.show(ui, "update")?;
+                    return Ok(ExitStatus::Success);
+                }
+                let report = lock::hold(&runtime, ui, "illa_builder", "update", self.wait, async {
+                    let report = update_local(
+                        &runtime,
+                        ui,
+                        &self.source,
+                        self.builder_version.as_deref(),
+                        &self.check,
+                        self.prune_old,
+                    )
+                    .await?;
+                    state::record(&runtime, ui, "illa_builder", "update").await;
+                    Ok(report)
+                })
+                .await?;
+                ui.emit(&report)?
+            }
+            (_, true) => utils::cloud_onboarding(ui, "update")?,
             _ => unreachable!(),
         };
-        Ok(())
+        Ok(ExitStatus::Success)
+    }
+}
+
+// The flags given, falling back to the image and mirror the Builder was
+// deployed with. A Builder deployed before the image was labelled is taken
+// to be deployed from the image it runs.
+fn deployed_source(
+    source: &image::ImageSource,
+    labels: &HashMap<String, String>,
+    configured: Option<&str>,
+) -> image::ImageSource {
+    let configured = configured.map(|image| image.split('@').next().unwrap_or(image).to_string());
+    image::ImageSource {
+        image: source
+            .image
+            .clone()
+            .or_else(|| labels.get(IMAGE_LABEL).cloned())
+            .or(configured),
+        registry_mirror: source
+            .registry_mirror
+            .clone()
+            .or_else(|| labels.get(MIRROR_LABEL).cloned()),
+        registry_url: source.registry_url.clone(),
+    }
+}
+
+/// The image a Builder deployed from `source` updates to. Only the
+/// repository is taken from the deployment: a Builder on a release moves
+/// to the newest published release, one on `latest` or another moving
+/// tag pulls that tag again, and `version` picks the release instead.
+async fn update_target(
+    ui: &Ui,
+    source: &image::ImageSource,
+    version: Option<&str>,
+) -> Result<ImageRef> {
+    let deployed = source.requested(None)?;
+    let repository = ImageRef {
+        tag: None,
+        digest: None,
+        ..deployed.clone()
+    };
+    if let Some(version) = version {
+        versions::validate_version(ui, source, &repository, version).await?;
+        return repository.with_version(Some(version));
+    }
+    let current = match deployed.tag.as_deref().and_then(Release::parse) {
+        Some(current) => current,
+        None => {
+            let tag = deployed.tag.unwrap_or_else(|| String::from("latest"));
+            return repository.with_version(Some(&tag));
+        }
+    };
+    let tags =
+        source.registry(&repository)?.tags().await.map_err(|e| {
+            IllaError::Registry(format!("{e}; pick a release with --builder-version"))
+        })?;
+    let newest = registry::newer_releases(&tags, &current)
+        .pop()
+        .unwrap_or(current);
+    repository.with_version(Some(&newest.tag))
+}
+
+/// Compare the running Builder with the registry. A Builder on a release
+/// tag is behind when newer releases exist; one on `latest` or another
+/// moving tag is behind when the tag now points at another digest.
+async fn check_update(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    source: &image::ImageSource,
+) -> Result<(CommandReport, bool)> {
+    let progress = ui.progress();
+    let pb_check = progress.step();
+    pb_check.set_message("Checking for updates...");
+
+    let checked = async {
+        let builder_info = utils::inspect_builder(runtime, "illa_builder").await?;
+        let labels = builder_info
+            .config
+            .as_ref()
+            .and_then(|c| c.labels.clone())
+            .unwrap_or_default();
+        let running = labels
+            .get(IMAGE_LABEL)
+            .cloned()
+            .or_else(|| builder_info.config.as_ref().and_then(|c| c.image.clone()))
+            .unwrap_or_default();
+        let running = ImageRef::parse(&running)?;
+        let configured = builder_info
+            .config
+            .as_ref()
+            .and_then(|c| c.image.as_deref());
+        let requested = deployed_source(source, &labels, configured).requested(None)?;
+        let client = source.registry(&requested)?;
+
+        let tag = running
+            .tag
+            .clone()
+            .unwrap_or_else(|| String::from("latest"));
+        match Release::parse(&tag) {
+            Some(current) => {
+                let newer = registry::newer_releases(&client.tags().await?, &current);
+                let newer: Vec<_> = newer.into_iter().map(|release| release.tag).collect();
+                Ok::<_, IllaError>((requested, tag, newer.is_empty(), newer))
+            }
+            None => {
+                let deployed = labels.get(DIGEST_LABEL).or(running.digest.as_ref());
+                let published = client.manifest_digest(&tag).await?;
+                Ok((requested, tag, deployed == Some(&published), vec![]))
+            }
+        }
+    }
+    .await;
+    let (requested, tag, up_to_date, newer) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            pb_check.clear();
+            return Err(e);
+        }
+    };
+
+    let message = match (up_to_date, newer.is_empty()) {
+        (true, _) => format!("ILLA Builder {tag} is up to date"),
+        (false, false) => format!(
+            "ILLA Builder {tag} is behind, newer versions: {}",
+            newer.join(", ")
+        ),
+        (false, true) => format!("A newer ILLA Builder {tag} image is published"),
+    };
+    let icon = if up_to_date {
+        &ui::emoji::SUCCESS
+    } else {
+        &ui::emoji::WARN
+    };
+    pb_check.finish(format!("{icon} {message}"));
+
+    let report = CommandReport::success("update")
+        .instance("illa_builder")
+        .message(message)
+        .detail("image", requested.name())
+        .detail("version", &tag)
+        .detail("update_available", !up_to_date)
+        .detail("newer_versions", &newer);
+    Ok((report, !up_to_date))
+}
+
+async fn update_local(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    source: &image::ImageSource,
+    version: Option<&str>,
+    check: &image::ImageCheck,
+    prune_old: bool,
+) -> Result<CommandReport> {
+    ui.println(format!("{} Updating the ILLA Builder...", ui::emoji::BUILD));
+
+    let progress = ui.progress();
+
+    let pb_setup = progress.step();
+    pb_setup.set_message("Initializing...");
+    let (plan, spec) = match update_plan(runtime, ui, source, version, check, prune_old).await {
+        Ok(planned) => planned,
+        Err(e) => {
+            pb_setup.clear();
+            return Err(e);
+        }
+    };
+    pb_setup.finish(format!("{} Setup complete", ui::emoji::SUCCESS));
+
+    // The new image is fetched and checked while the old Builder keeps running
+    let original = utils::inspect_builder(runtime, "illa_builder").await?;
+    let mut outcome = Outcome::default();
+    if let Err(e) = plan.execute(runtime, &progress, &mut outcome).await {
+        // The old Builder is put back if it was removed and the new one
+        // did not come up, whether that failed or was interrupted
+        if outcome.removed.iter().any(|name| name == "illa_builder") {
+            let running = original
+                .state
+                .as_ref()
+                .and_then(|state| state.running)
+                .unwrap_or(false);
+            utils::restore_container(runtime, "illa_builder", &original, running).await;
+        }
+        return Err(e);
+    }
+
+    let url = spec.url();
+    progress.step().finish(format!(
+        "{} {} {}",
+        ui::emoji::SPARKLE,
+        String::from("ILLA Builder started, please visit"),
+        style(&url).blue(),
+    ));
+
+    let mut report = CommandReport::success("update")
+        .instance("illa_builder")
+        .detail("image", outcome.image)
+        .detail("digest", outcome.pinned.map(|pinned| pinned.digest))
+        .detail("url", url);
+    if let Some(pruned) = outcome.pruned {
+        report = report
+            .detail("pruned", &pruned.removed)
+            .detail("reclaimed_bytes", pruned.reclaimed)
+    }
+    Ok(report)
+}
+
+// The steps of an update, recreating the Builder from its current spec
+// with the new image
+async fn update_plan(
+    runtime: &dyn ContainerRuntime,
+    ui: &Ui,
+    source: &image::ImageSource,
+    version: Option<&str>,
+    check: &image::ImageCheck,
+    prune_old: bool,
+) -> Result<(Plan, InstanceSpec)> {
+    let builder_info = utils::inspect_builder(runtime, "illa_builder").await?;
+    let spec = InstanceSpec::from_inspect(&builder_info)?;
+    let explicit = source.image.is_some();
+    let configured = builder_info
+        .config
+        .as_ref()
+        .and_then(|c| c.image.as_deref());
+    let source = deployed_source(source, &spec.labels, configured);
+    let requested = match explicit {
+        true => source.requested(version)?.or_latest(),
+        false => update_target(ui, &source, version).await?,
+    };
+    let builder_labels = utils::builder_labels(&requested, source.registry_mirror.as_deref());
+    let builder_image = source.pull_reference(runtime, &requested);
+
+    let mut plan = Plan::default();
+    plan.push(Action::PullImage {
+        image: builder_image.clone(),
+    });
+    plan.push(Action::VerifyImage {
+        image: builder_image.clone(),
+        requested,
+        check: check.clone(),
+    });
+    plan.push(Action::RemoveContainer {
+        name: String::from("illa_builder"),
+        force: true,
+    });
+    let builder_config = Config {
+        image: Some(builder_image),
+        env: Some(spec.env.clone()),
+        labels: Some(builder_labels),
+        host_config: Some(spec.host_config(runtime.info())),
+        ..Default::default()
+    };
+    plan.push(Action::create_container("illa_builder", builder_config));
+    plan.push(Action::StartContainer {
+        name: String::from("illa_builder"),
+    });
+    if prune_old {
+        plan.push(Action::PruneImages {
+            keep: prune::DEFAULT_KEEP,
+            images: vec![],
+        });
     }
+    Ok((plan, spec))
 }
 
-async fn update_local(progress_style: ProgressStyle) -> Result {
-    println!(
-        "{} Updating the ILLA Builder with the latest docker image...",
-        ui::emoji::BUILD
-    );
-
-    let _docker = Docker::connect_with_local_defaults().unwrap();
-    if (_docker.ping().await).is_err() {
-        println!(
-            "{} {}\n{} {}\n\n{}\n\n{}\n\n{}\n",
-            ui::emoji::FAIL,
-            String::from("No running docker found."),
-            ui::emoji::WARN,
-            style("Please check the status of docker with command: docker info").red(),
-            String::from("If you do not have Docker installed, please refer to the following content for instructions on how to install it: "),
-            style("https://docs.docker.com/engine/install/").blue(),
-            String::from("Once Docker is installed, please try running the command again."),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        registry::stub_registry,
+        runtime::fake::{builder_config, FakeRuntime, FAKE_DIGEST},
+    };
+    use serde_json::json;
+
+    fn runtime_with_builder() -> FakeRuntime {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            true,
         );
-        process::exit(1);
+        runtime
     }
 
-    let m = MultiProgress::new();
-    let finish_spinner_style = ProgressStyle::with_template("{wide_msg}").unwrap();
+    // The Builder carries no image label, as deployed by older versions, so
+    // the release it runs is taken from its image
+    #[tokio::test]
+    async fn recreates_builder_with_same_config() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+
+        update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
 
-    let pb_setup = m.add(ProgressBar::new(0));
-    pb_setup.set_style(progress_style.clone());
-    for _ in 0..10 {
-        pb_setup.set_message("Initializing...");
-        pb_setup.inc(1);
-        thread::sleep(Duration::from_millis(200));
+        assert!(runtime.is_running("illa_builder"));
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("illasoft/illa-builder:1.10.0@{FAKE_DIGEST}").as_str())
+        );
+        assert_eq!(
+            info.config.as_ref().unwrap().env.as_ref().unwrap()[2],
+            "POSTGRES_PASSWORD=secret"
+        );
+        assert_eq!(
+            utils::builder_data_dir(&info).as_deref(),
+            Some("/data/illa")
+        );
+        let bindings = info.host_config.unwrap().port_bindings.unwrap();
+        assert_eq!(
+            bindings["2022/tcp"].as_ref().unwrap()[0]
+                .host_port
+                .as_deref(),
+            Some("8080")
+        );
     }
-    let inspect_options = Some(InspectContainerOptions { size: false });
-    let builder_detail = &_docker
-        .inspect_container("illa_builder", inspect_options)
+
+    #[tokio::test]
+    async fn missing_builder_fails_before_removing() {
+        let runtime = FakeRuntime::new();
+
+        let result = update_local(
+            &runtime,
+            &Ui::default(),
+            &Default::default(),
+            None,
+            &Default::default(),
+            false,
+        )
         .await;
-    if builder_detail.is_err() {
-        println!(
-            "{} {}\n",
-            ui::emoji::FAIL,
-            String::from("No ILLA Builder found."),
+
+        assert!(matches!(result, Err(IllaError::ContainerNotFound(_))));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("remove")));
+    }
+
+    #[tokio::test]
+    async fn remove_failure_keeps_old_builder() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        runtime.fail("remove", "device or resource busy");
+
+        let result = update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::ContainerRemove(_))));
+        assert!(runtime.is_running("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("create")));
+    }
+
+    #[tokio::test]
+    async fn pull_failure_skips_create() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        runtime.fail("pull", "toomanyrequests");
+
+        let result = update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::ImagePull(_))));
+        assert!(runtime.is_running("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("create")));
+    }
+
+    #[tokio::test]
+    async fn start_failure_restores_the_old_builder() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        runtime.add_image("illasoft/illa-builder:1.2.0");
+        runtime.fail("start", "port is already allocated");
+
+        let result = update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await;
+
+        // The old Builder is back; starting it fails too, on the same port
+        assert!(matches!(result, Err(IllaError::ContainerStart(_))));
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(info.image.as_deref(), Some("illasoft/illa-builder:1.2.0"));
+        assert_eq!(
+            utils::builder_data_dir(&info).as_deref(),
+            Some("/data/illa")
         );
-        process::exit(1);
     }
-    let builder_info = builder_detail.as_ref().unwrap();
-    let builder_env_cp = &builder_info.config.as_ref().unwrap().env.clone().unwrap();
-    let builder_env = vec![
-        builder_env_cp[0].as_str(),
-        builder_env_cp[1].as_str(),
-        builder_env_cp[2].as_str(),
-    ];
-    let builder_mount_cp = &builder_info
-        .host_config
-        .as_ref()
-        .unwrap()
-        .mounts
-        .clone()
+
+    #[tokio::test]
+    async fn keeps_deployed_image_and_mirror() {
+        let runtime = FakeRuntime::new();
+        let image = ImageRef::parse("illasoft/illa-builder").unwrap();
+        let mut config = builder_config(
+            "mirror.corp/illasoft/illa-builder:latest",
+            8080,
+            "/data/illa",
+        );
+        config.labels = Some(utils::builder_labels(
+            &image.or_latest(),
+            Some("mirror.corp"),
+        ));
+        runtime.add_container("illa_builder", config, true);
+
+        update_local(
+            &runtime,
+            &Ui::default(),
+            &Default::default(),
+            None,
+            &Default::default(),
+            false,
+        )
+        .await
         .unwrap();
-    let mounts = vec![Mount {
-        target: Some("/opt/illa/database".to_string()),
-        source: Some(builder_mount_cp[0].source.clone().unwrap()),
-        typ: Some(MountTypeEnum::BIND),
-        read_only: Some(false),
-        ..Default::default()
-    }];
-    let mut builder_labels = HashMap::new();
-    builder_labels.insert("maintainer", "opensource@illasoft.com");
-    builder_labels.insert("license", "Apache-2.0");
-    let builder_port_bindings = builder_info
-        .host_config
-        .as_ref()
-        .unwrap()
-        .port_bindings
-        .clone();
-    let port = builder_port_bindings
-        .clone()
-        .unwrap()
-        .get("2022/tcp")
-        .unwrap()
-        .clone();
-    pb_setup.set_style(finish_spinner_style.clone());
-    pb_setup.finish_with_message(format!("{} Setup complete", ui::emoji::SUCCESS));
-
-    let pb_rm = m.add(ProgressBar::new(0));
-    pb_rm.set_style(progress_style.clone());
-    for _ in 0..10 {
-        pb_rm.set_message("Removing ILLA Builder...");
-        pb_rm.inc(1);
-        thread::sleep(Duration::from_millis(200));
+
+        assert!(runtime.has_image("mirror.corp/illasoft/illa-builder:latest"));
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        let labels = info.config.unwrap().labels.unwrap();
+        assert_eq!(labels[MIRROR_LABEL], "mirror.corp");
     }
-    let rm_options = Some(RemoveContainerOptions {
-        force: true,
-        ..Default::default()
-    });
-    let stop_builder = _docker.remove_container("illa_builder", rm_options).await;
-    if stop_builder.is_err() {
-        println!(
-            "{} {} {}",
-            ui::emoji::FAIL,
-            String::from("Try to remove ILLA Builder error:"),
-            style(stop_builder.err().unwrap()).red(),
+
+    // A Builder deployed from the 1.2.0 release, and a registry that
+    // publishes 1.3.0 and 1.10.0 after it
+    async fn pinned_builder() -> (FakeRuntime, image::ImageSource) {
+        let runtime = FakeRuntime::new();
+        let image = ImageRef::parse("illasoft/illa-builder:1.2.0").unwrap();
+        let mut config = builder_config(&image.to_string(), 8080, "/data/illa");
+        config.labels = Some(utils::builder_labels(&image, None));
+        runtime.add_container("illa_builder", config, true);
+        (runtime, releases().await)
+    }
+
+    // A registry that publishes 1.3.0 and 1.10.0 after 1.2.0
+    async fn releases() -> image::ImageSource {
+        let endpoint = stub_registry::serve(|_| {
+            vec![(
+                "/v2/illasoft/illa-builder/tags/list".to_string(),
+                stub_registry::response(
+                    "200 OK",
+                    "",
+                    r#"{"tags":["latest","1.10.0","1.1.0","1.2.0","1.3.0","main"]}"#,
+                ),
+            )]
+        })
+        .await;
+        image::ImageSource {
+            registry_url: Some(endpoint),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn pinned_release_moves_to_the_newest_release() {
+        let (runtime, source) = pinned_builder().await;
+
+        update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.config.unwrap().labels.unwrap()[IMAGE_LABEL],
+            "illasoft/illa-builder:1.10.0"
         );
-        process::exit(1);
     }
-    pb_rm.set_style(finish_spinner_style.clone());
-    pb_rm.finish_with_message(format!(
-        "{} {}",
-        ui::emoji::SUCCESS,
-        style("Successfully remove the old ILLA Builder."),
-    ));
 
-    let pb_download = m.add(ProgressBar::new(0));
-    pb_download.set_style(progress_style.clone());
-    let builder_image = "illasoft/illa-builder:latest";
-    let download_started = Instant::now();
-    let stream_list = &mut _docker.create_image(
-        Some(CreateImageOptions {
-            from_image: builder_image,
+    #[tokio::test]
+    async fn builder_version_picks_the_release() {
+        let (runtime, source) = pinned_builder().await;
+
+        update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            Some("1.3.0"),
+            &Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.config.unwrap().labels.unwrap()[IMAGE_LABEL],
+            "illasoft/illa-builder:1.3.0"
+        );
+    }
+
+    #[tokio::test]
+    async fn image_flag_overrides_deployed_image() {
+        let runtime = runtime_with_builder();
+        let source = image::ImageSource {
+            image: Some(String::from("registry.corp/illa/builder:2.0.0")),
             ..Default::default()
-        }),
-        None,
-        None,
-    );
-    while let Some(value) = stream_list.next().await {
-        pb_download.set_message(format!("Downloading {builder_image}..."));
-        pb_download.inc(1);
-        thread::sleep(Duration::from_millis(100));
-        if value.is_err() {
-            pb_download.set_style(finish_spinner_style.clone());
-            pb_download.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Download image error:"),
-                style(value.err().unwrap()).red(),
-            ));
-            process::exit(1);
         };
+
+        update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.image.as_deref(),
+            Some(format!("registry.corp/illa/builder:2.0.0@{FAKE_DIGEST}").as_str())
+        );
+        assert_eq!(
+            info.config.unwrap().labels.unwrap()[IMAGE_LABEL],
+            "registry.corp/illa/builder:2.0.0"
+        );
     }
-    pb_download.set_style(finish_spinner_style.clone());
-    pb_download.finish_with_message(format!(
-        "{} Downloaded in {}",
-        ui::emoji::SUCCESS,
-        HumanDuration(download_started.elapsed())
-    ));
 
-    let pb_deploy = m.add(ProgressBar::new(0));
-    pb_deploy.set_style(progress_style.clone());
-    let builder_config = Config {
-        image: Some(builder_image),
-        env: Some(builder_env),
-        labels: Some(builder_labels),
-        host_config: Some(HostConfig {
-            port_bindings: builder_port_bindings.clone(),
-            mounts: Some(mounts),
+    #[tokio::test]
+    async fn digest_mismatch_keeps_old_builder() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        let check = image::ImageCheck {
+            expect_digest: Some(format!("sha256:{}", "0".repeat(64))),
             ..Default::default()
-        }),
-        ..Default::default()
-    };
+        };
 
-    let create_builder = &_docker
-        .create_container(
-            Some(CreateContainerOptions {
-                name: "illa_builder",
-            }),
-            builder_config,
-        )
+        let result = update_local(&runtime, &Ui::default(), &source, None, &check, false).await;
+
+        assert!(matches!(result, Err(IllaError::ImageVerify(_))));
+        assert!(runtime.is_running("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("remove")));
+    }
+
+    #[tokio::test]
+    async fn records_pinned_digest() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        let check = image::ImageCheck {
+            expect_digest: Some(FAKE_DIGEST.to_string()),
+            ..Default::default()
+        };
+
+        update_local(&runtime, &Ui::default(), &source, None, &check, false)
+            .await
+            .unwrap();
+
+        let info = runtime.inspect("illa_builder", false).await.unwrap();
+        assert_eq!(
+            info.config.unwrap().labels.unwrap()[DIGEST_LABEL],
+            FAKE_DIGEST
+        );
+    }
+
+    #[tokio::test]
+    async fn check_lists_newer_releases() {
+        let runtime = runtime_with_builder();
+        let endpoint = stub_registry::serve(|_| {
+            vec![(
+                "/v2/illasoft/illa-builder/tags/list".to_string(),
+                stub_registry::response(
+                    "200 OK",
+                    "",
+                    r#"{"tags":["latest","1.10.0","1.1.0","1.2.0","1.3.0","main"]}"#,
+                ),
+            )]
+        })
         .await;
+        let source = image::ImageSource {
+            registry_url: Some(endpoint),
+            ..Default::default()
+        };
+
+        let (report, available) = check_update(&runtime, &Ui::default(), &source)
+            .await
+            .unwrap();
+
+        assert!(available);
+        assert_eq!(report.details["newer_versions"], json!(["1.3.0", "1.10.0"]));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("pull")));
+        assert!(runtime.is_running("illa_builder"));
+    }
 
-    let start_builder = &_docker
-        .start_container("illa_builder", None::<StartContainerOptions<String>>)
+    #[tokio::test]
+    async fn check_compares_latest_digest() {
+        let runtime = FakeRuntime::new();
+        let image = ImageRef::parse("illasoft/illa-builder")
+            .unwrap()
+            .or_latest();
+        let mut config = builder_config(&image.to_string(), 8080, "/data/illa");
+        let mut labels = utils::builder_labels(&image, None);
+        labels.insert(DIGEST_LABEL.to_string(), FAKE_DIGEST.to_string());
+        config.labels = Some(labels);
+        runtime.add_container("illa_builder", config, true);
+        let endpoint = stub_registry::serve(|_| {
+            vec![(
+                "/v2/illasoft/illa-builder/manifests/latest".to_string(),
+                stub_registry::response(
+                    "200 OK",
+                    &format!("Docker-Content-Digest: {FAKE_DIGEST}\r\n"),
+                    "",
+                ),
+            )]
+        })
         .await;
+        let source = image::ImageSource {
+            registry_url: Some(endpoint),
+            ..Default::default()
+        };
 
-    match (create_builder.is_err(), start_builder.is_err()) {
-        (true, _) => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Create ILLA Builder error:"),
-                style(create_builder.as_ref().err().unwrap()).red(),
-            ));
-            process::exit(1);
-        }
-        (false, true) => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::FAIL,
-                String::from("Start ILLA Builder error:"),
-                style(start_builder.as_ref().err().unwrap()).red(),
-            ));
-            process::exit(1);
-        }
-        _ => {
-            pb_deploy.set_style(finish_spinner_style.clone());
-            pb_deploy.finish_with_message(format!(
-                "{} {} {}",
-                ui::emoji::SPARKLE,
-                String::from("ILLA Builder started, please visit"),
-                style(format!(
-                    "{}:{}",
-                    "http://localhost",
-                    port.clone()
-                        .unwrap()
-                        .get(0)
-                        .unwrap()
-                        .host_port
-                        .as_ref()
-                        .unwrap()
-                ))
-                .blue(),
-            ));
-            process::exit(0);
+        let (report, available) = check_update(&runtime, &Ui::default(), &source)
+            .await
+            .unwrap();
+
+        assert!(!available);
+        assert_eq!(report.details["update_available"], json!(false));
+    }
+
+    #[tokio::test]
+    async fn prune_old_removes_superseded_images() {
+        let runtime = runtime_with_builder();
+        let source = releases().await;
+        for tag in ["1.0.0", "1.1.0", "1.2.0"] {
+            runtime.add_image(&format!("illasoft/illa-builder:{tag}"));
         }
-    };
 
-    Ok(())
+        let report = update_local(
+            &runtime,
+            &Ui::default(),
+            &source,
+            None,
+            &Default::default(),
+            true,
+        )
+        .await
+        .unwrap();
+
+        // 1.2.0 is the rollback image; the Builder now runs 1.10.0
+        assert_eq!(
+            report.details["pruned"],
+            json!(["illasoft/illa-builder:1.1.0", "illasoft/illa-builder:1.0.0"])
+        );
+        assert!(runtime.has_image("illasoft/illa-builder:1.2.0"));
+        assert!(runtime.has_image("illasoft/illa-builder:1.10.0"));
+    }
+
+    #[tokio::test]
+    async fn hand_made_builder_fails_before_pulling() {
+        let runtime = FakeRuntime::new();
+        let mut config = builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa");
+        config.host_config.as_mut().unwrap().mounts = None;
+        runtime.add_container("illa_builder", config, true);
+
+        let result = update_local(
+            &runtime,
+            &Ui::default(),
+            &Default::default(),
+            None,
+            &Default::default(),
+            false,
+        )
+        .await;
+
+        assert!(matches!(result, Err(IllaError::InstanceConfig(_))));
+        assert!(runtime.is_running("illa_builder"));
+        assert!(!runtime.calls().iter().any(|c| c.starts_with("pull")));
+    }
 }
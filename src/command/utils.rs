@@ -1,7 +1,27 @@
-use crate::{command::*, result::Result};
-use anyhow::Ok;
+use crate::{
+    command::*,
+    registry::{self, ImageRef},
+    result::{IllaError, Result},
+    runtime::{
+        ContainerRuntime, RuntimeError, BUILDER_DATA_DIR, IMAGE_LABEL, LEASE_LABEL, MIRROR_LABEL,
+    },
+};
+use bollard::{
+    container::{Config, NetworkingConfig},
+    models::{
+        ContainerInspectResponse, ContainerSummary, EndpointSettings, Mount, MountPointTypeEnum,
+        MountTypeEnum,
+    },
+};
+use console::style;
 use dirs;
-use std::{env, fs};
+use futures_util::StreamExt;
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 #[cfg(target_os = "macos")]
 pub fn local_bind_init(path: &String) -> String {
@@ -30,14 +50,303 @@ pub fn local_bind_init(path: &String) -> String {
 }
 
 pub fn local_bind_delete(path: String) -> Result {
-    fs::remove_dir_all(path);
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        deleted => Ok(deleted?),
+    }
+}
+
+/// Refuse to delete the data directory `path` unless it lies inside one of
+/// the `managed` directories illa made. The root and the home directory,
+/// or any directory holding it, are never deleted.
+pub fn check_deletable(path: &str, managed: &[String]) -> Result {
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let refuse = |reason: &str| {
+        Err(IllaError::InvalidArgument(format!(
+            "refusing to delete {path}: {reason}"
+        )))
+    };
+    if !Path::new(path).is_absolute() {
+        return refuse("it is not an absolute path");
+    }
 
+    let target = resolve(Path::new(path));
+    let home = dirs::home_dir().map(|home| resolve(&home));
+    if target.parent().is_none() || home.is_some_and(|home| home.starts_with(&target)) {
+        return refuse("it is the root or holds the home directory");
+    }
+    if !managed
+        .iter()
+        .any(|dir| target.starts_with(resolve(Path::new(dir))))
+    {
+        return refuse("illa did not create it, delete it by hand if you are sure");
+    }
     Ok(())
 }
 
+/// Where `illa remove --backup` writes its archives by default
+pub fn default_backup_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("illa")
+        .join("backups")
+}
+
+/// The archive to back up the data of the instance `name` to, named after
+/// the current time
+pub fn backup_archive(dir: &Path, name: &str) -> PathBuf {
+    let at = humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .replace([':', '-'], "");
+    dir.join(format!("{name}-{at}.tar"))
+}
+
+/// Write the directory `path` to the tarball `archive`, returning its size
+pub fn backup_dir(path: &str, archive: &Path) -> Result<u64> {
+    if let Some(parent) = archive.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tarball = tar::Builder::new(fs::File::create(archive)?);
+    tarball.follow_symlinks(false);
+    tarball.append_dir_all(".", path)?;
+    tarball.into_inner()?.sync_all()?;
+    Ok(fs::metadata(archive)?.len())
+}
+
 pub fn get_default_mount() -> String {
     let tmp_dir = dirs::home_dir().unwrap();
     let temp_dir = tmp_dir.join(".illa-builder");
 
     temp_dir.display().to_string()
 }
+
+// ILLA Cloud is not open yet, every cloud flag ends here
+pub fn cloud_onboarding(ui: &ui::Ui, command: &str) -> Result {
+    let message = "Looking forward to onboarding you!";
+    ui.println(format!("{} {}", ui::emoji::DIAMOND, message));
+    ui.emit(&ui::CommandReport::success(command).message(message))
+}
+
+pub fn no_runtime_found() -> String {
+    format!(
+        "{} {}\n{} {}\n\n{}\n\n{}\n{}\n\n{}\n",
+        ui::emoji::FAIL,
+        String::from("No running Docker or Podman found."),
+        ui::emoji::WARN,
+        style("Please check the status of the container runtime with command: docker info (or podman info)").red(),
+        String::from("If you do not have Docker or Podman installed, please refer to the following content for instructions on how to install it: "),
+        style("https://docs.docker.com/engine/install/").blue(),
+        style("https://podman.io/docs/installation").blue(),
+        String::from("Once Docker or Podman is installed, please try running the command again."),
+    )
+}
+
+// Inspect a Builder container, reporting a missing one as such
+pub async fn inspect_builder(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+) -> Result<ContainerInspectResponse> {
+    runtime
+        .inspect(name, false)
+        .await
+        .map_err(|e| IllaError::container(name, e, IllaError::Runtime))
+}
+
+/// Whether the listed container is the lease of an instance rather than
+/// an instance itself
+pub fn is_lease(container: &ContainerSummary) -> bool {
+    container
+        .labels
+        .as_ref()
+        .is_some_and(|labels| labels.contains_key(LEASE_LABEL))
+}
+
+// The host directory bound to the Builder data directory, whether it was
+// created as a mount or as an SELinux-labelled bind
+pub fn builder_data_dir(builder_info: &ContainerInspectResponse) -> Option<String> {
+    builder_info
+        .mounts
+        .as_ref()?
+        .iter()
+        .find(|m| m.destination.as_deref() == Some(BUILDER_DATA_DIR))
+        .and_then(|m| m.source.clone())
+}
+
+// Pull an image with the user's registry credentials, drawing per-layer
+// progress. Errors reported inside the status stream fail the pull just
+// like transport errors do.
+pub async fn pull_image(
+    runtime: &dyn ContainerRuntime,
+    progress: &ui::Progress,
+    image: &str,
+) -> Result<ui::PullSummary> {
+    let credentials = registry::auth::credentials(ImageRef::parse(image)?.registry())?;
+    let mut pull = ui::PullProgress::new(progress, image);
+    let mut stream = runtime.pull(image, credentials);
+    while let Some(value) = stream.next().await {
+        let info = value.and_then(|info| match &info.error {
+            Some(message) => Err(RuntimeError::DockerResponseServerError {
+                status_code: 500,
+                message: message.clone(),
+            }),
+            None => Ok(info),
+        });
+        match info {
+            Ok(info) => pull.update(&info),
+            Err(e) => {
+                pull.clear();
+                return Err(IllaError::ImagePull(e));
+            }
+        }
+    }
+    Ok(pull.finish())
+}
+
+// Labels of a Builder container deployed from `image`
+pub fn builder_labels(image: &ImageRef, mirror: Option<&str>) -> HashMap<String, String> {
+    let mut labels = HashMap::new();
+    labels.insert(
+        "maintainer".to_string(),
+        "opensource@illasoft.com".to_string(),
+    );
+    labels.insert("license".to_string(), "Apache-2.0".to_string());
+    labels.insert(IMAGE_LABEL.to_string(), image.to_string());
+    if let Some(mirror) = mirror {
+        labels.insert(MIRROR_LABEL.to_string(), mirror.to_string());
+    }
+    labels
+}
+
+/// The config to create `original` again with: its settings, host config
+/// and primary network. Its anonymous volumes are mounted by name, so their
+/// data is kept; other networks and files written into the container
+/// itself are not carried over.
+pub fn recreate_config(original: &ContainerInspectResponse) -> Config<String> {
+    let config = original.config.clone().unwrap_or_default();
+    let mut host_config = original.host_config.clone().unwrap_or_default();
+
+    let covered: Vec<String> = host_config
+        .binds
+        .iter()
+        .flatten()
+        .filter_map(|bind| bind.split(':').nth(1).map(String::from))
+        .chain(
+            host_config
+                .mounts
+                .iter()
+                .flatten()
+                .filter_map(|mount| mount.target.clone()),
+        )
+        .collect();
+    for mount in original.mounts.iter().flatten() {
+        let (Some(MountPointTypeEnum::VOLUME), Some(volume), Some(target)) =
+            (mount.typ, &mount.name, &mount.destination)
+        else {
+            continue;
+        };
+        if covered.contains(target) {
+            continue;
+        }
+        host_config.mounts.get_or_insert_with(Vec::new).push(Mount {
+            target: Some(target.clone()),
+            source: Some(volume.clone()),
+            typ: Some(MountTypeEnum::VOLUME),
+            read_only: mount.rw.map(|rw| !rw),
+            ..Default::default()
+        });
+    }
+
+    // The daemon names a container's host after its ID unless told otherwise
+    let id = original.id.as_deref().unwrap_or_default();
+    let hostname = config
+        .hostname
+        .filter(|hostname| !hostname.is_empty() && !id.starts_with(hostname.as_str()));
+    let networking_config = primary_network(original).map(|(network, endpoint)| NetworkingConfig {
+        endpoints_config: HashMap::from([(
+            network,
+            EndpointSettings {
+                ipam_config: endpoint.ipam_config,
+                links: endpoint.links,
+                aliases: endpoint.aliases,
+                ..Default::default()
+            },
+        )]),
+    });
+
+    Config {
+        hostname,
+        domainname: config.domainname,
+        user: config.user,
+        exposed_ports: config.exposed_ports,
+        tty: config.tty,
+        open_stdin: config.open_stdin,
+        stdin_once: config.stdin_once,
+        env: config.env,
+        cmd: config.cmd,
+        healthcheck: config.healthcheck,
+        image: config.image,
+        volumes: config.volumes,
+        working_dir: config.working_dir,
+        entrypoint: config.entrypoint,
+        labels: config.labels,
+        stop_signal: config.stop_signal,
+        stop_timeout: config.stop_timeout,
+        shell: config.shell,
+        host_config: Some(host_config),
+        networking_config,
+        ..Default::default()
+    }
+}
+
+// The network `info` was created on, with its settings there
+fn primary_network(info: &ContainerInspectResponse) -> Option<(String, EndpointSettings)> {
+    let mode = info.host_config.as_ref()?.network_mode.clone()?;
+    let mode = match mode.as_str() {
+        "default" => String::from("bridge"),
+        _ => mode,
+    };
+    let endpoint = info
+        .network_settings
+        .as_ref()?
+        .networks
+        .as_ref()?
+        .get(&mode)?
+        .clone();
+    Some((mode, endpoint))
+}
+
+/// The networks `info` is connected to besides the one it was created on,
+/// which recreating it does not reconnect
+pub fn extra_networks(info: &ContainerInspectResponse) -> Vec<String> {
+    let primary = primary_network(info).map(|(network, _)| network);
+    let mut networks: Vec<String> = info
+        .network_settings
+        .as_ref()
+        .and_then(|settings| settings.networks.as_ref())
+        .map(|networks| networks.keys().cloned().collect())
+        .unwrap_or_default();
+    networks.retain(|network| Some(network) != primary.as_ref());
+    networks.sort();
+    networks
+}
+
+// Put the `original` container back after its `replacement` failed to
+// come up. The error that got us here is what the user needs to see, so
+// failures here are not reported.
+pub async fn restore_container(
+    runtime: &dyn ContainerRuntime,
+    replacement: &str,
+    original: &ContainerInspectResponse,
+    running: bool,
+) {
+    let name = original
+        .name
+        .as_deref()
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    runtime.remove(replacement, true).await.ok();
+    let restored = recreate_config(original);
+    if runtime.create(name, restored).await.is_ok() && running {
+        runtime.start(name).await.ok();
+    }
+}
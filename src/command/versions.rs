@@ -0,0 +1,188 @@
+use crate::{
+    command::{list, ui::Ui, *},
+    registry::{self, ImageRef},
+    result::{IllaError, Result},
+    runtime::{self, ContainerRuntime},
+};
+use clap::Args;
+use prettytable::{color, Attr};
+use prettytable::{Cell, Row, Table};
+use serde::Serialize;
+
+// Executes the `illa versions` command to
+// list the ILLA Builder versions published in the registry
+#[derive(Debug, Args)]
+/// List the available ILLA Builder versions
+pub struct Cmd {
+    #[clap(flatten)]
+    source: image::ImageSource,
+}
+
+impl Cmd {
+    pub async fn run(&self, ui: &Ui) -> Result {
+        // Without a runtime the versions are still listed, just unmarked
+        let runtime = runtime::connect().await.ok();
+        let records = version_records(
+            runtime.as_ref().map(|r| r as &dyn ContainerRuntime),
+            ui,
+            &self.source,
+        )
+        .await?;
+        if ui.is_structured() {
+            return ui.emit(&records);
+        }
+
+        let mut table = Table::new();
+        table.add_row(Row::new(vec![
+            Cell::new("Version").with_style(Attr::ForegroundColor(color::GREEN)),
+            Cell::new("Installed").with_style(Attr::ForegroundColor(color::GREEN)),
+        ]));
+        for record in records {
+            table.add_row(Row::new(vec![
+                Cell::new(&record.version).with_style(Attr::ForegroundColor(color::BLUE)),
+                Cell::new(&record.installed.join(", ")),
+            ]));
+        }
+        table.printstd();
+        Ok(())
+    }
+}
+
+/// A published Builder version as reported by `illa versions --output json|yaml`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VersionRecord {
+    pub version: String,
+    /// The instances running this version
+    pub installed: Vec<String>,
+}
+
+// The release tags of the Builder image, newest first, each marked with
+// the instances that run it
+async fn version_records(
+    runtime: Option<&dyn ContainerRuntime>,
+    ui: &Ui,
+    source: &image::ImageSource,
+) -> Result<Vec<VersionRecord>> {
+    let image = source.requested(None)?;
+    let pb_tags = ui.progress().step();
+    pb_tags.set_message(format!("Fetching the tags of {}...", image.name()));
+    let tags = match source.registry(&image)?.tags().await {
+        Ok(tags) => tags,
+        Err(e) => {
+            pb_tags.clear();
+            return Err(e);
+        }
+    };
+    pb_tags.clear();
+
+    let instances = match runtime {
+        Some(runtime) => list::instance_records(runtime).await?,
+        None => vec![],
+    };
+    Ok(registry::releases(&tags)
+        .into_iter()
+        .rev()
+        .map(|release| VersionRecord {
+            installed: instances
+                .iter()
+                .filter(|instance| instance.version.as_deref() == Some(release.tag.as_str()))
+                .map(|instance| instance.name.clone())
+                .collect(),
+            version: release.tag,
+        })
+        .collect())
+}
+
+/// Make sure `version` of `image` is published before anything is pulled,
+/// suggesting the closest release on a typo. When the registry cannot be
+/// asked, e.g. behind a mirror, the pull has the final word.
+pub async fn validate_version(
+    ui: &Ui,
+    source: &image::ImageSource,
+    image: &ImageRef,
+    version: &str,
+) -> Result {
+    let tags = match source.registry(image)?.tags().await {
+        Ok(tags) => tags,
+        Err(e) => {
+            ui.println(format!(
+                "{} Could not check the version against the registry: {e}",
+                ui::emoji::WARN
+            ));
+            return Ok(());
+        }
+    };
+    if tags.iter().any(|tag| tag == version) {
+        return Ok(());
+    }
+    let hint = match registry::suggest(&tags, version) {
+        Some(release) => format!("; did you mean {}?", release.tag),
+        None => String::from("; run `illa versions` to list the published versions"),
+    };
+    Err(IllaError::InvalidArgument(format!(
+        "ILLA Builder version {version} is not published for {}{hint}",
+        image.name()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        registry::stub_registry,
+        runtime::fake::{builder_config, FakeRuntime},
+    };
+
+    async fn registry_with_tags(tags: &str) -> image::ImageSource {
+        let body = format!(r#"{{"name":"illasoft/illa-builder","tags":{tags}}}"#);
+        let endpoint = stub_registry::serve(|_| {
+            vec![(
+                "/v2/illasoft/illa-builder/tags/list".to_string(),
+                stub_registry::response("200 OK", "", &body),
+            )]
+        })
+        .await;
+        image::ImageSource {
+            registry_url: Some(endpoint),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn marks_installed_versions() {
+        let runtime = FakeRuntime::new();
+        runtime.add_container(
+            "illa_builder",
+            builder_config("illasoft/illa-builder:1.2.0", 8080, "/data/illa"),
+            true,
+        );
+        let source = registry_with_tags(r#"["latest","1.2.0","1.10.0","1.3.0"]"#).await;
+
+        let records = version_records(Some(&runtime), &Ui::default(), &source)
+            .await
+            .unwrap();
+
+        let versions: Vec<_> = records.iter().map(|r| r.version.as_str()).collect();
+        assert_eq!(versions, ["1.10.0", "1.3.0", "1.2.0"]);
+        assert_eq!(records[2].installed, ["illa_builder"]);
+        assert!(records[0].installed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_version_suggests_closest() {
+        let source = registry_with_tags(r#"["latest","1.2.0","1.2.14"]"#).await;
+        let image = ImageRef::default();
+
+        let result = validate_version(&Ui::default(), &source, &image, "1.2.41").await;
+
+        match result {
+            Err(IllaError::InvalidArgument(message)) => {
+                assert!(message.contains("did you mean 1.2.14?"), "{message}")
+            }
+            other => panic!("expected an invalid argument, got {other:?}"),
+        }
+        assert!(validate_version(&Ui::default(), &source, &image, "1.2.0")
+            .await
+            .is_ok());
+    }
+}
@@ -1,2 +1,6 @@
 pub mod command;
+pub mod registry;
 pub mod result;
+pub mod runtime;
+pub mod spec;
+pub mod state;
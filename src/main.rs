@@ -1,8 +1,11 @@
-#![allow(unused)]
 use clap::{Parser, Subcommand};
+use console::style;
 use illa::{
-    command::{deploy, doctor, list, remove, restart, stop, update},
-    result::Result,
+    command::{
+        adopt, apply, deploy, df, diff, doctor, image, inspect, interrupt, list, prune, remove,
+        restart, start, state, stop, ui, update, utils, versions,
+    },
+    result::{ExitStatus, IllaError, Result},
 };
 use std::process;
 
@@ -13,36 +16,111 @@ use std::process;
 struct Cli {
     #[clap(subcommand)]
     cmd: Cmds,
+
+    /// Output format of the command result
+    #[clap(long = "output", value_enum, global = true, default_value = "table")]
+    output: ui::OutputFormat,
+
+    /// Print progress as plain lines without spinners or emoji
+    #[clap(long = "plain", global = true, action = clap::ArgAction::SetTrue)]
+    plain: bool,
+
+    /// Print only results and errors
+    #[clap(short = 'q', long = "quiet", global = true, action = clap::ArgAction::SetTrue)]
+    quiet: bool,
 }
 
 #[derive(Debug, Subcommand)]
 enum Cmds {
     List(list::Cmd),
+    Start(start::Cmd),
     Stop(stop::Cmd),
     Doctor(doctor::Cmd),
     Deploy(deploy::Cmd),
     Remove(remove::Cmd),
     Update(update::Cmd),
     Restart(restart::Cmd),
+    Image(image::Cmd),
+    Versions(versions::Cmd),
+    Prune(prune::Cmd),
+    Df(df::Cmd),
+    Inspect(inspect::Cmd),
+    Adopt(adopt::Cmd),
+    State(state::Cmd),
+    Diff(diff::Cmd),
+    Apply(apply::Cmd),
+}
+
+impl Cmds {
+    fn name(&self) -> &'static str {
+        match self {
+            Cmds::List(_) => "list",
+            Cmds::Start(_) => "start",
+            Cmds::Stop(_) => "stop",
+            Cmds::Doctor(_) => "doctor",
+            Cmds::Deploy(_) => "deploy",
+            Cmds::Remove(_) => "remove",
+            Cmds::Update(_) => "update",
+            Cmds::Restart(_) => "restart",
+            Cmds::Image(_) => "image",
+            Cmds::Versions(_) => "versions",
+            Cmds::Prune(_) => "prune",
+            Cmds::Df(_) => "df",
+            Cmds::Inspect(_) => "inspect",
+            Cmds::Adopt(_) => "adopt",
+            Cmds::State(_) => "state",
+            Cmds::Diff(_) => "diff",
+            Cmds::Apply(_) => "apply",
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
-    if let Err(e) = run(cli).await {
-        eprintln!("error: {e:?}");
-        process::exit(1);
+    let ui = ui::Ui::detect(cli.output, cli.plain, cli.quiet);
+    let name = cli.cmd.name();
+    interrupt::install();
+    let e = match run(cli, &ui).await {
+        Ok(ExitStatus::Success) => return,
+        Ok(status) => process::exit(status.code()),
+        Err(e) => e,
+    };
+    if ui.is_structured() {
+        ui.emit(&ui::CommandReport::error(name, &e)).ok();
+    } else {
+        match e {
+            IllaError::RuntimeUnavailable(_) => {
+                eprintln!("{}", ui::output::text(utils::no_runtime_found()))
+            }
+            _ => eprintln!(
+                "{}",
+                ui::output::text(format!("{} {}", ui::emoji::FAIL, style(&e).red()))
+            ),
+        }
     }
+    process::exit(e.exit_code());
 }
 
-async fn run(cli: Cli) -> Result {
-    match cli.cmd {
-        Cmds::List(cmd) => cmd.run().await,
-        Cmds::Stop(cmd) => cmd.run().await,
-        Cmds::Doctor(cmd) => cmd.run().await,
-        Cmds::Deploy(cmd) => cmd.run().await,
-        Cmds::Remove(cmd) => cmd.run().await,
-        Cmds::Update(cmd) => cmd.run().await,
-        Cmds::Restart(cmd) => cmd.run().await,
-    }
+async fn run(cli: Cli, ui: &ui::Ui) -> Result<ExitStatus> {
+    let result = match cli.cmd {
+        Cmds::List(cmd) => cmd.run(ui).await,
+        Cmds::Start(cmd) => cmd.run(ui).await,
+        Cmds::Stop(cmd) => cmd.run(ui).await,
+        Cmds::Doctor(cmd) => cmd.run(ui).await,
+        Cmds::Deploy(cmd) => cmd.run(ui).await,
+        Cmds::Remove(cmd) => cmd.run(ui).await,
+        Cmds::Update(cmd) => return cmd.run(ui).await,
+        Cmds::Restart(cmd) => cmd.run(ui).await,
+        Cmds::Image(cmd) => cmd.run(ui).await,
+        Cmds::Versions(cmd) => cmd.run(ui).await,
+        Cmds::Prune(cmd) => cmd.run(ui).await,
+        Cmds::Df(cmd) => cmd.run(ui).await,
+        Cmds::Inspect(cmd) => cmd.run(ui).await,
+        Cmds::Adopt(cmd) => cmd.run(ui).await,
+        Cmds::State(cmd) => cmd.run(ui).await,
+        Cmds::Diff(cmd) => return cmd.run(ui).await,
+        Cmds::Apply(cmd) => cmd.run(ui).await,
+    };
+    result.map(|()| ExitStatus::Success)
 }
@@ -0,0 +1,228 @@
+use super::is_docker_hub;
+use crate::result::{IllaError, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bollard::auth::DockerCredentials;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+// The key Docker stores Docker Hub credentials under
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
+
+// Credential helpers report identity tokens with this user name
+const TOKEN_USERNAME: &str = "<token>";
+
+/// The parts of `~/.docker/config.json` that hold registry credentials
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+    #[serde(default)]
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AuthEntry {
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    identitytoken: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+impl DockerConfig {
+    /// Read `$DOCKER_CONFIG/config.json`, or `~/.docker/config.json`.
+    /// A missing file means no credentials.
+    pub fn load() -> Result<DockerConfig> {
+        let path = match env::var_os("DOCKER_CONFIG") {
+            Some(dir) => PathBuf::from(dir).join("config.json"),
+            None => match dirs::home_dir() {
+                Some(home) => home.join(".docker").join("config.json"),
+                None => return Ok(DockerConfig::default()),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => DockerConfig::parse(&content)
+                .map_err(|e| IllaError::InvalidArgument(format!("{}: {e}", path.display()))),
+            Err(_) => Ok(DockerConfig::default()),
+        }
+    }
+
+    pub fn parse(content: &str) -> std::result::Result<DockerConfig, serde_json::Error> {
+        serde_json::from_str(content)
+    }
+
+    /// The credentials for `registry`, asking the configured credential
+    /// helper first and falling back to the inline `auths` entries
+    pub fn credentials(&self, registry: &str) -> Option<DockerCredentials> {
+        let server = server_address(registry);
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(host, _)| same_registry(host, registry))
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+        if let Some(credentials) = helper.and_then(|helper| run_helper(helper, &server)) {
+            return Some(credentials);
+        }
+
+        let entry = self
+            .auths
+            .iter()
+            .find(|(host, _)| same_registry(host, registry))
+            .map(|(_, entry)| entry)?;
+        if let Some(token) = &entry.identitytoken {
+            return Some(DockerCredentials {
+                identitytoken: Some(token.clone()),
+                serveraddress: Some(server),
+                ..Default::default()
+            });
+        }
+        let decoded = STANDARD.decode(entry.auth.as_ref()?).ok()?;
+        let (username, password) = String::from_utf8(decoded)
+            .ok()?
+            .split_once(':')
+            .map(|(username, password)| (username.to_string(), password.to_string()))?;
+        Some(DockerCredentials {
+            username: Some(username),
+            password: Some(password),
+            serveraddress: Some(server),
+            ..Default::default()
+        })
+    }
+}
+
+/// The credentials for `registry` from the user's Docker configuration
+pub fn credentials(registry: &str) -> Result<Option<DockerCredentials>> {
+    Ok(DockerConfig::load()?.credentials(registry))
+}
+
+// `docker-credential-<helper> get` reads the server on stdin and prints
+// the credentials as JSON, or fails when it has none
+fn run_helper(helper: &str, server: &str) -> Option<DockerCredentials> {
+    let mut child = Command::new(format!("docker-credential-{helper}"))
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(server.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let found: HelperCredentials = serde_json::from_slice(&output.stdout).ok()?;
+    Some(helper_credentials(found, server))
+}
+
+fn helper_credentials(found: HelperCredentials, server: &str) -> DockerCredentials {
+    if found.username == TOKEN_USERNAME {
+        DockerCredentials {
+            identitytoken: Some(found.secret),
+            serveraddress: Some(server.to_string()),
+            ..Default::default()
+        }
+    } else {
+        DockerCredentials {
+            username: Some(found.username),
+            password: Some(found.secret),
+            serveraddress: Some(server.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+fn server_address(registry: &str) -> String {
+    if is_docker_hub(registry) {
+        DOCKER_HUB_SERVER.to_string()
+    } else {
+        registry.to_string()
+    }
+}
+
+// Config keys may carry a scheme and a path, e.g. `https://host/v1/`
+fn same_registry(key: &str, registry: &str) -> bool {
+    let host = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    host == registry || (is_docker_hub(host) && is_docker_hub(registry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_inline_auths() {
+        let config = DockerConfig::parse(
+            r#"{
+                "auths": {
+                    "https://index.docker.io/v1/": { "auth": "aWxsYTpodWI=" },
+                    "registry.corp:5000": { "auth": "Ym90OnMzY3IzdDpwYXJ0" },
+                    "ghcr.io": { "identitytoken": "opaque" }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let hub = config.credentials("docker.io").unwrap();
+        assert_eq!(hub.username.as_deref(), Some("illa"));
+        assert_eq!(hub.password.as_deref(), Some("hub"));
+        assert_eq!(hub.serveraddress.as_deref(), Some(DOCKER_HUB_SERVER));
+
+        // Only the first colon separates the user from the password
+        let private = config.credentials("registry.corp:5000").unwrap();
+        assert_eq!(private.username.as_deref(), Some("bot"));
+        assert_eq!(private.password.as_deref(), Some("s3cr3t:part"));
+
+        let token = config.credentials("ghcr.io").unwrap();
+        assert_eq!(token.identitytoken.as_deref(), Some("opaque"));
+
+        assert!(config.credentials("quay.io").is_none());
+    }
+
+    #[test]
+    fn missing_helper_falls_back_to_auths() {
+        let config = DockerConfig::parse(
+            r#"{
+                "credsStore": "illa-test-missing-helper",
+                "auths": { "registry.corp": { "auth": "Ym90OnB3" } }
+            }"#,
+        )
+        .unwrap();
+
+        let found = config.credentials("registry.corp").unwrap();
+        assert_eq!(found.username.as_deref(), Some("bot"));
+    }
+
+    #[test]
+    fn helper_tokens_become_identity_tokens() {
+        let found = helper_credentials(
+            HelperCredentials {
+                username: TOKEN_USERNAME.to_string(),
+                secret: String::from("refresh"),
+            },
+            "registry.corp",
+        );
+        assert_eq!(found.identitytoken.as_deref(), Some("refresh"));
+        assert!(found.username.is_none());
+    }
+}
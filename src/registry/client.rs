@@ -0,0 +1,299 @@
+use super::{auth, ImageRef};
+use crate::result::{IllaError, Result};
+use bollard::auth::DockerCredentials;
+use reqwest::{header, Client, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Mutex};
+
+// Docker Hub serves the registry API from a different host than its name
+const DOCKER_HUB_ENDPOINT: &str = "https://registry-1.docker.io";
+
+// Manifest types whose digest `docker pull` reports, multi-arch first
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+    application/vnd.docker.distribution.manifest.list.v2+json, \
+    application/vnd.oci.image.manifest.v1+json, \
+    application/vnd.docker.distribution.manifest.v2+json";
+
+#[derive(Debug, Deserialize)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+/// Reads tags and manifests over the Docker Registry HTTP API v2
+pub struct RegistryClient {
+    http: Client,
+    endpoint: String,
+    repository: String,
+    credentials: Option<DockerCredentials>,
+    token: Mutex<Option<String>>,
+}
+
+impl RegistryClient {
+    /// A client for the repository of `image`. `endpoint` replaces the
+    /// registry's own address, e.g. `http://localhost:5000` for a local
+    /// registry standing in for it.
+    pub fn new(image: &ImageRef, endpoint: Option<&str>) -> Result<RegistryClient> {
+        let endpoint = match endpoint {
+            Some(endpoint) if endpoint.contains("://") => endpoint.to_string(),
+            Some(endpoint) => format!("https://{endpoint}"),
+            None if image.is_docker_hub() => DOCKER_HUB_ENDPOINT.to_string(),
+            None => format!("https://{}", image.registry()),
+        };
+        // Official Docker Hub images live under `library/`
+        let repository = if image.is_docker_hub() && !image.repository.contains('/') {
+            format!("library/{}", image.repository)
+        } else {
+            image.repository.clone()
+        };
+        Ok(RegistryClient {
+            http: Client::builder()
+                .user_agent(concat!("illa/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .map_err(registry_error)?,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            repository,
+            credentials: auth::credentials(image.registry())?,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Every tag of the repository, following the API's pagination
+    pub async fn tags(&self) -> Result<Vec<String>> {
+        let mut tags = vec![];
+        let mut next = Some(format!("/v2/{}/tags/list", self.repository));
+        while let Some(path) = next {
+            let response = self.send(Method::GET, &path, None).await?;
+            next = next_page(&response);
+            let page: TagList = response.json().await.map_err(registry_error)?;
+            tags.extend(page.tags.unwrap_or_default());
+        }
+        Ok(tags)
+    }
+
+    /// The digest `reference` (a tag) currently points at
+    pub async fn manifest_digest(&self, reference: &str) -> Result<String> {
+        let path = format!("/v2/{}/manifests/{reference}", self.repository);
+        let response = self.send(Method::HEAD, &path, Some(MANIFEST_TYPES)).await?;
+        response
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|digest| digest.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| {
+                IllaError::Registry(format!(
+                    "{} sent no digest for {}:{reference}",
+                    self.endpoint, self.repository
+                ))
+            })
+    }
+
+    async fn send(&self, method: Method, path: &str, accept: Option<&str>) -> Result<Response> {
+        let url = format!("{}{path}", self.endpoint);
+        let request = |token: Option<String>| {
+            let mut request = self.http.request(method.clone(), &url);
+            if let Some(accept) = accept {
+                request = request.header(header::ACCEPT, accept);
+            }
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        };
+
+        let cached = self.token.lock().unwrap().clone();
+        let mut response = request(cached).send().await.map_err(registry_error)?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|challenge| challenge.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            response = if let Some(params) = challenge.strip_prefix("Bearer ") {
+                let token = self.fetch_token(&parse_challenge(params)).await?;
+                *self.token.lock().unwrap() = Some(token.clone());
+                request(Some(token)).send().await
+            } else {
+                self.basic_auth(request(None)).send().await
+            }
+            .map_err(registry_error)?;
+        }
+
+        if !response.status().is_success() {
+            return Err(IllaError::Registry(format!(
+                "{url} answered {}",
+                response.status()
+            )));
+        }
+        Ok(response)
+    }
+
+    // Trade the user's credentials, or none, for a token scoped by the
+    // registry's challenge
+    async fn fetch_token(&self, challenge: &HashMap<String, String>) -> Result<String> {
+        let realm = challenge
+            .get("realm")
+            .ok_or_else(|| IllaError::Registry(format!("{} sent no token realm", self.endpoint)))?;
+        let query: Vec<_> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| challenge.get(key).map(|value| (key, value)))
+            .collect();
+        let response = self
+            .basic_auth(self.http.get(realm).query(&query))
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .map_err(registry_error)?;
+        let token: Token = response.json().await.map_err(registry_error)?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| IllaError::Registry(format!("{realm} sent no token")))
+    }
+
+    fn basic_auth(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.credentials {
+            Some(DockerCredentials {
+                username: Some(username),
+                password,
+                ..
+            }) => request.basic_auth(username, password.as_ref()),
+            _ => request,
+        }
+    }
+}
+
+fn registry_error(e: reqwest::Error) -> IllaError {
+    IllaError::Registry(e.to_string())
+}
+
+// `realm="https://auth.docker.io/token",scope="repository:a/b:pull,push"`
+fn parse_challenge(params: &str) -> HashMap<String, String> {
+    let mut found = HashMap::new();
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_lowercase();
+        let (value, tail) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        found.insert(key, value.to_string());
+        rest = tail;
+    }
+    found
+}
+
+// `Link: </v2/name/tags/list?last=b&n=100>; rel="next"`
+fn next_page(response: &Response) -> Option<String> {
+    let link = response.headers().get(header::LINK)?.to_str().ok()?;
+    if !link.contains("rel=\"next\"") {
+        return None;
+    }
+    let (_, target) = link.split_once('<')?;
+    let (target, _) = target.split_once('>')?;
+    Some(target.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serve canned `(path, response)` pairs on localhost and return the
+    /// endpoint. A `"<path> authorized"` route answers requests carrying a
+    /// bearer token; any other path is a 404.
+    pub(crate) async fn serve(routes: impl FnOnce(&str) -> Vec<(String, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let routes = routes(&endpoint);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or_default();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let route = match request.to_lowercase().contains("authorization: bearer") {
+                    true => format!("{path} authorized"),
+                    false => path.to_string(),
+                };
+                let response = routes
+                    .iter()
+                    .find(|(found, _)| *found == route)
+                    .or_else(|| routes.iter().find(|(found, _)| found == path))
+                    .map(|(_, response)| response.clone())
+                    .unwrap_or_else(|| response("404 Not Found", "", "{}"));
+                stream.write_all(response.as_bytes()).await.ok();
+            }
+        });
+        endpoint
+    }
+
+    pub(crate) fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n{headers}\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn parses_bearer_challenge() {
+        let challenge = parse_challenge(
+            r#"realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:illasoft/illa-builder:pull,push""#,
+        );
+        assert_eq!(challenge["realm"], "https://auth.docker.io/token");
+        assert_eq!(challenge["service"], "registry.docker.io");
+        assert_eq!(
+            challenge["scope"],
+            "repository:illasoft/illa-builder:pull,push"
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_tags_across_pages_with_token() {
+        let endpoint = serve(|endpoint| {
+            let challenge = format!(
+                "WWW-Authenticate: Bearer realm=\"{endpoint}/token\",service=\"local\"\r\n"
+            );
+            vec![
+                (
+                    "/token?service=local".to_string(),
+                    response("200 OK", "", r#"{"token":"t0ken"}"#),
+                ),
+                (
+                    "/v2/illa/builder/tags/list".to_string(),
+                    response("401 Unauthorized", &challenge, "{}"),
+                ),
+                (
+                    "/v2/illa/builder/tags/list authorized".to_string(),
+                    response(
+                        "200 OK",
+                        "Link: </v2/illa/builder/tags/list?last=1.0.0&n=2>; rel=\"next\"\r\n",
+                        r#"{"name":"illa/builder","tags":["latest","1.0.0"]}"#,
+                    ),
+                ),
+                (
+                    "/v2/illa/builder/tags/list?last=1.0.0&n=2 authorized".to_string(),
+                    response("200 OK", "", r#"{"name":"illa/builder","tags":["1.1.0"]}"#),
+                ),
+            ]
+        })
+        .await;
+        let image = ImageRef::parse("registry.corp/illa/builder").unwrap();
+        let client = RegistryClient::new(&image, Some(&endpoint)).unwrap();
+
+        assert_eq!(client.tags().await.unwrap(), ["latest", "1.0.0", "1.1.0"]);
+    }
+}
@@ -0,0 +1,24 @@
+//! Image references and access to container registries
+
+pub mod auth;
+mod client;
+mod reference;
+pub mod signature;
+mod version;
+
+#[cfg(test)]
+pub(crate) use client::tests as stub_registry;
+pub use client::RegistryClient;
+pub use reference::{ImageRef, DEFAULT_IMAGE};
+pub use version::{newer_releases, releases, suggest, Release};
+
+// Where images without an explicit registry are pulled from
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// Whether `host` is one of the names Docker Hub goes by
+pub fn is_docker_hub(host: &str) -> bool {
+    matches!(
+        host,
+        DOCKER_HUB | "index.docker.io" | "registry-1.docker.io"
+    )
+}
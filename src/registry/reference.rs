@@ -0,0 +1,242 @@
+use super::{is_docker_hub, DOCKER_HUB};
+use crate::result::{IllaError, Result};
+use std::fmt;
+
+/// The image ILLA Builder is deployed from unless `--image` says otherwise
+pub const DEFAULT_IMAGE: &str = "illasoft/illa-builder";
+
+/// A parsed `[registry/]repository[:tag][@digest]` image reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    pub registry: Option<String>,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    pub fn parse(reference: &str) -> Result<ImageRef> {
+        let invalid = |why: &str| {
+            IllaError::InvalidArgument(format!("Invalid image reference {reference:?}: {why}"))
+        };
+
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) => {
+                let (algorithm, hex) = digest
+                    .split_once(':')
+                    .ok_or_else(|| invalid("a digest looks like sha256:<hex>"))?;
+                if algorithm.is_empty()
+                    || hex.is_empty()
+                    || !hex.chars().all(|c| c.is_ascii_hexdigit())
+                {
+                    return Err(invalid("a digest looks like sha256:<hex>"));
+                }
+                (name, Some(digest.to_string()))
+            }
+            None => (reference, None),
+        };
+
+        // A colon after the last slash separates the tag; one before it
+        // belongs to a registry port
+        let last = name.rsplit('/').next().unwrap_or_default();
+        let (name, tag) = match last.rsplit_once(':') {
+            Some((_, tag)) => (&name[..name.len() - tag.len() - 1], Some(tag.to_string())),
+            None => (name, None),
+        };
+        if tag.as_deref() == Some("") {
+            return Err(invalid("the tag is empty"));
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                (Some(first.to_string()), rest.to_string())
+            }
+            _ => (None, name.to_string()),
+        };
+        if repository.is_empty()
+            || repository.split('/').any(str::is_empty)
+            || repository
+                .chars()
+                .any(|c| c.is_ascii_uppercase() || c.is_whitespace())
+        {
+            return Err(invalid(
+                "the repository must be a lowercase path like org/name",
+            ));
+        }
+
+        Ok(ImageRef {
+            registry,
+            repository,
+            tag,
+            digest,
+        })
+    }
+
+    /// Apply `--builder-version` as the tag. A reference that already
+    /// names a tag or digest cannot take a second version.
+    pub fn with_version(mut self, version: Option<&str>) -> Result<ImageRef> {
+        match version {
+            Some(version) if self.tag.is_some() || self.digest.is_some() => {
+                Err(IllaError::InvalidArgument(format!(
+                    "{self} already names a version; drop --builder-version {version}"
+                )))
+            }
+            Some(version) => {
+                self.tag = Some(version.to_string());
+                Ok(self)
+            }
+            None => Ok(self),
+        }
+    }
+
+    /// Pull Docker Hub images through `mirror` instead. Images from any
+    /// other registry are left alone.
+    pub fn mirrored(&self, mirror: Option<&str>) -> ImageRef {
+        match mirror {
+            Some(mirror) if self.is_docker_hub() => ImageRef {
+                registry: Some(mirror.trim_end_matches('/').to_string()),
+                ..self.clone()
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// The reference without its tag and digest
+    pub fn name(&self) -> String {
+        match &self.registry {
+            Some(registry) => format!("{registry}/{}", self.repository),
+            None => self.repository.clone(),
+        }
+    }
+
+    /// The registry host the image is pulled from
+    pub fn registry(&self) -> &str {
+        self.registry.as_deref().unwrap_or(DOCKER_HUB)
+    }
+
+    pub fn is_docker_hub(&self) -> bool {
+        is_docker_hub(self.registry())
+    }
+
+    /// The reference with `latest` filled in when neither a tag nor a
+    /// digest is given
+    pub fn or_latest(mut self) -> ImageRef {
+        if self.tag.is_none() && self.digest.is_none() {
+            self.tag = Some(String::from("latest"));
+        }
+        self
+    }
+}
+
+impl Default for ImageRef {
+    fn default() -> Self {
+        ImageRef::parse(DEFAULT_IMAGE).unwrap()
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(registry) = &self.registry {
+            write!(f, "{registry}/")?;
+        }
+        write!(f, "{}", self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:5f1e3a9c0b7d2e4f6a8c0e2b4d6f8a1c3e5a7c9e1b3d5f7a9c1e3b5d7f9a1c3e";
+
+    #[test]
+    fn parses_references() {
+        let cases = [
+            (
+                "illasoft/illa-builder",
+                None,
+                "illasoft/illa-builder",
+                None,
+                None,
+            ),
+            (
+                "illasoft/illa-builder:1.2.0",
+                None,
+                "illasoft/illa-builder",
+                Some("1.2.0"),
+                None,
+            ),
+            (
+                "registry.corp:5000/illa/builder",
+                Some("registry.corp:5000"),
+                "illa/builder",
+                None,
+                None,
+            ),
+            (
+                "localhost/builder:dev",
+                Some("localhost"),
+                "builder",
+                Some("dev"),
+                None,
+            ),
+        ];
+        for (input, registry, repository, tag, digest) in cases {
+            let image = ImageRef::parse(input).unwrap();
+            assert_eq!(image.registry.as_deref(), registry, "{input}");
+            assert_eq!(image.repository, repository, "{input}");
+            assert_eq!(image.tag.as_deref(), tag, "{input}");
+            assert_eq!(image.digest.as_deref(), digest, "{input}");
+            assert_eq!(image.to_string(), input);
+        }
+
+        let pinned = ImageRef::parse(&format!("ghcr.io/illa/builder:1.2.0@{DIGEST}")).unwrap();
+        assert_eq!(pinned.tag.as_deref(), Some("1.2.0"));
+        assert_eq!(pinned.digest.as_deref(), Some(DIGEST));
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        for input in [
+            "",
+            "Illasoft/builder",
+            "illa//builder",
+            "illa/builder:",
+            "illa/builder@sha256",
+            "illa/builder@sha256:xyz",
+        ] {
+            assert!(
+                matches!(ImageRef::parse(input), Err(IllaError::InvalidArgument(_))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn version_fills_the_tag_once() {
+        let image = ImageRef::default().with_version(Some("1.2.0")).unwrap();
+        assert_eq!(image.to_string(), "illasoft/illa-builder:1.2.0");
+        assert!(image.with_version(Some("1.3.0")).is_err());
+    }
+
+    #[test]
+    fn mirror_only_applies_to_docker_hub() {
+        let hub = ImageRef::default().or_latest();
+        assert_eq!(
+            hub.mirrored(Some("mirror.corp/")).to_string(),
+            "mirror.corp/illasoft/illa-builder:latest"
+        );
+
+        let private = ImageRef::parse("registry.corp/illa/builder:1.0").unwrap();
+        assert_eq!(private.mirrored(Some("mirror.corp")), private);
+    }
+}
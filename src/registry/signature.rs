@@ -0,0 +1,166 @@
+use super::ImageRef;
+use crate::result::{IllaError, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use p256::{
+    ecdsa::{signature::Verifier, Signature, VerifyingKey},
+    pkcs8::DecodePublicKey,
+};
+use serde::Deserialize;
+
+/// The cosign "simple signing" payload an image signature covers
+#[derive(Debug, Deserialize)]
+struct SimpleSigning {
+    critical: Critical,
+}
+
+#[derive(Debug, Deserialize)]
+struct Critical {
+    identity: Identity,
+    image: SignedImage,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identity {
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedImage {
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+/// Verify a cosign signature offline, as written by
+/// `cosign sign --output-signature SIG --output-payload PAYLOAD`.
+///
+/// `public_key` is the PEM encoded ECDSA P-256 key, `signature` the base64
+/// signature over `payload`. The payload has to name `image` and `digest`.
+pub fn verify(
+    public_key: &str,
+    payload: &[u8],
+    signature: &str,
+    image: &ImageRef,
+    digest: &str,
+) -> Result {
+    let fail = |why: String| IllaError::ImageVerify(why);
+
+    let key = VerifyingKey::from_public_key_pem(public_key.trim())
+        .map_err(|e| fail(format!("unreadable public key: {e}")))?;
+    let signature = STANDARD
+        .decode(signature.trim())
+        .map_err(|e| fail(format!("the signature is not base64: {e}")))?;
+    let signature = Signature::from_der(&signature)
+        .or_else(|_| Signature::from_slice(&signature))
+        .map_err(|e| fail(format!("unreadable signature: {e}")))?;
+    key.verify(payload, &signature)
+        .map_err(|_| fail(String::from("the signature does not match the public key")))?;
+
+    let signed: SimpleSigning = serde_json::from_slice(payload)
+        .map_err(|e| fail(format!("unreadable signature payload: {e}")))?;
+    if signed.critical.image.docker_manifest_digest != digest {
+        return Err(fail(format!(
+            "the signature covers {}, not {digest}",
+            signed.critical.image.docker_manifest_digest
+        )));
+    }
+    let signed_name = ImageRef::parse(&signed.critical.identity.docker_reference)?;
+    if !same_repository(&signed_name, image) {
+        return Err(fail(format!(
+            "the signature is for {}, not {}",
+            signed.critical.identity.docker_reference,
+            image.name()
+        )));
+    }
+    Ok(())
+}
+
+fn same_repository(a: &ImageRef, b: &ImageRef) -> bool {
+    let normalize = |image: &ImageRef| {
+        let repository = image
+            .repository
+            .strip_prefix("library/")
+            .unwrap_or(&image.repository)
+            .to_string();
+        (
+            image.is_docker_hub(),
+            image.registry().to_string(),
+            repository,
+        )
+    };
+    let (a_hub, a_registry, a_repository) = normalize(a);
+    let (b_hub, b_registry, b_repository) = normalize(b);
+    a_repository == b_repository && (a_registry == b_registry || (a_hub && b_hub))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::{
+        ecdsa::{signature::Signer, SigningKey},
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    const DIGEST: &str = "sha256:5f1e3a9c0b7d2e4f6a8c0e2b4d6f8a1c3e5a7c9e1b3d5f7a9c1e3b5d7f9a1c3e";
+
+    fn payload(reference: &str, digest: &str) -> Vec<u8> {
+        serde_json::json!({
+            "critical": {
+                "identity": { "docker-reference": reference },
+                "image": { "docker-manifest-digest": digest },
+                "type": "cosign container image signature"
+            },
+            "optional": null
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn sign(key: &SigningKey, payload: &[u8]) -> String {
+        let signature: Signature = key.sign(payload);
+        STANDARD.encode(signature.to_der())
+    }
+
+    fn key() -> (SigningKey, String) {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let pem = key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        (key, pem)
+    }
+
+    #[test]
+    fn accepts_a_matching_signature() {
+        let (key, pem) = key();
+        let payload = payload("index.docker.io/illasoft/illa-builder", DIGEST);
+        let image = ImageRef::parse("illasoft/illa-builder:1.2.0").unwrap();
+
+        verify(&pem, &payload, &sign(&key, &payload), &image, DIGEST).unwrap();
+    }
+
+    #[test]
+    fn rejects_another_key() {
+        let (_, pem) = key();
+        let other = SigningKey::from_slice(&[9; 32]).unwrap();
+        let payload = payload("illasoft/illa-builder", DIGEST);
+        let image = ImageRef::parse("illasoft/illa-builder").unwrap();
+
+        let result = verify(&pem, &payload, &sign(&other, &payload), &image, DIGEST);
+        assert!(matches!(result, Err(IllaError::ImageVerify(_))));
+    }
+
+    #[test]
+    fn rejects_another_digest_or_image() {
+        let (key, pem) = key();
+        let payload = payload("illasoft/illa-builder", DIGEST);
+        let signature = sign(&key, &payload);
+
+        let image = ImageRef::parse("illasoft/illa-builder").unwrap();
+        let other_digest = format!("sha256:{}", "0".repeat(64));
+        assert!(verify(&pem, &payload, &signature, &image, &other_digest).is_err());
+
+        let other_image = ImageRef::parse("registry.corp/illasoft/illa-builder").unwrap();
+        assert!(verify(&pem, &payload, &signature, &other_image, DIGEST).is_err());
+    }
+}
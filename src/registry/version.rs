@@ -0,0 +1,105 @@
+use semver::Version;
+
+/// A tag that names a release, e.g. `1.2.0` or `v1.2.0`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Release {
+    pub version: Version,
+    pub tag: String,
+}
+
+impl Release {
+    pub fn parse(tag: &str) -> Option<Release> {
+        let version = Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()?;
+        Some(Release {
+            version,
+            tag: tag.to_string(),
+        })
+    }
+}
+
+/// The release tags among `tags`, oldest first. `latest`, branch names
+/// and other tags that are not versions are left out.
+pub fn releases(tags: &[String]) -> Vec<Release> {
+    let mut releases: Vec<_> = tags.iter().filter_map(|tag| Release::parse(tag)).collect();
+    releases.sort();
+    releases
+}
+
+/// The releases in `tags` that are newer than `current`, oldest first
+pub fn newer_releases(tags: &[String], current: &Release) -> Vec<Release> {
+    releases(tags)
+        .into_iter()
+        .filter(|release| release.version > current.version)
+        .collect()
+}
+
+/// The release in `tags` closest to the mistyped `wanted`, if any is
+/// within a couple of edits of it
+pub fn suggest(tags: &[String], wanted: &str) -> Option<Release> {
+    releases(tags)
+        .into_iter()
+        .rev()
+        .map(|release| (edit_distance(&release.tag, wanted), release))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, release)| release)
+}
+
+// Levenshtein distance over characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn sorts_releases_by_version() {
+        let found = releases(&tags(&[
+            "latest",
+            "1.10.0",
+            "v1.9.2",
+            "1.2.0",
+            "main",
+            "1.10.0-rc.1",
+        ]));
+
+        let found: Vec<_> = found.iter().map(|release| release.tag.as_str()).collect();
+        assert_eq!(found, ["1.2.0", "v1.9.2", "1.10.0-rc.1", "1.10.0"]);
+    }
+
+    #[test]
+    fn lists_only_newer_releases() {
+        let current = Release::parse("1.9.2").unwrap();
+
+        let newer = newer_releases(&tags(&["1.2.0", "1.9.2", "1.10.0", "2.0.0"]), &current);
+
+        let newer: Vec<_> = newer.iter().map(|release| release.tag.as_str()).collect();
+        assert_eq!(newer, ["1.10.0", "2.0.0"]);
+    }
+
+    #[test]
+    fn suggests_the_closest_release() {
+        let published = tags(&["latest", "1.2.0", "1.2.14", "1.3.0"]);
+
+        assert_eq!(suggest(&published, "1.2.4").unwrap().tag, "1.2.14");
+        assert_eq!(suggest(&published, "v1.3.0").unwrap().tag, "1.3.0");
+        assert!(suggest(&published, "nightly").is_none());
+    }
+}
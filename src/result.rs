@@ -1,3 +1,272 @@
-pub type Result<T = ()> = anyhow::Result<T>;
-pub type Error = anyhow::Error;
-pub use anyhow::{anyhow, bail};
+use crate::runtime::{self, RuntimeError};
+use thiserror::Error;
+
+pub type Result<T = ()> = std::result::Result<T, IllaError>;
+pub type Error = IllaError;
+pub use anyhow::anyhow;
+
+/// The exit code of `illa update --check` when a newer Builder exists,
+/// kept clear of the error codes below
+pub const UPDATE_AVAILABLE: i32 = 100;
+
+/// The exit code of `illa diff` when the Builder differs from its spec
+pub const DRIFT_DETECTED: i32 = 101;
+
+/// How a command that did not fail ends the process. Commands return it
+/// to `main` rather than exiting themselves, so locks are released and
+/// output is flushed first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExitStatus {
+    #[default]
+    Success,
+    /// `illa update --check` found a newer Builder
+    UpdateAvailable,
+    /// `illa diff` found the Builder differs from its spec
+    DriftDetected,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> i32 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::UpdateAvailable => UPDATE_AVAILABLE,
+            ExitStatus::DriftDetected => DRIFT_DETECTED,
+        }
+    }
+}
+
+/// Every way an `illa` command can fail.
+///
+/// Each variant maps to a stable process exit code so scripts can tell the
+/// failure classes apart:
+///
+/// | Code | Variant              |
+/// |------|----------------------|
+/// | 1    | `Other`              |
+/// | 2    | `InvalidArgument`    |
+/// | 3    | `RuntimeUnavailable` |
+/// | 4    | `ImagePull`          |
+/// | 5    | `ContainerNotFound`  |
+/// | 6    | `ContainerCreate`    |
+/// | 7    | `ContainerStart`     |
+/// | 8    | `ContainerStop`      |
+/// | 9    | `ContainerRestart`   |
+/// | 10   | `ContainerRemove`    |
+/// | 11   | `Io`                 |
+/// | 12   | `Runtime`            |
+/// | 13   | `ImageLoad`          |
+/// | 14   | `ImageVerify`        |
+/// | 15   | `Registry`           |
+/// | 16   | `InstanceConfig`     |
+/// | 17   | `State`              |
+/// | 18   | `Aborted`            |
+/// | 19   | `Locked`             |
+/// | 20   | `PortInUse`          |
+/// | 21   | `NotReady`           |
+/// | 130  | `Interrupted`        |
+///
+/// `illa update --check` exits with [`UPDATE_AVAILABLE`] when the Builder
+/// is behind, and `illa diff` with [`DRIFT_DETECTED`] when the Builder was
+/// changed, which are not failures.
+#[derive(Debug, Error)]
+pub enum IllaError {
+    #[error("{0}")]
+    InvalidArgument(String),
+
+    #[error("No running Docker or Podman found: {0}")]
+    RuntimeUnavailable(String),
+
+    #[error("Download image error: {0}")]
+    ImagePull(RuntimeError),
+
+    #[error("No ILLA Builder found: {0}")]
+    ContainerNotFound(String),
+
+    #[error("Create ILLA Builder error: {0}")]
+    ContainerCreate(RuntimeError),
+
+    #[error("Start ILLA Builder error: {0}")]
+    ContainerStart(RuntimeError),
+
+    #[error("Try to stop ILLA Builder error: {0}")]
+    ContainerStop(RuntimeError),
+
+    #[error("Try to restart ILLA Builder error: {0}")]
+    ContainerRestart(RuntimeError),
+
+    #[error("Try to remove ILLA Builder error: {0}")]
+    ContainerRemove(RuntimeError),
+
+    #[error("File system error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Container runtime error: {0}")]
+    Runtime(#[from] RuntimeError),
+
+    #[error("Load image archive error: {0}")]
+    ImageLoad(RuntimeError),
+
+    #[error("Image verification failed: {0}")]
+    ImageVerify(String),
+
+    #[error("Registry error: {0}")]
+    Registry(String),
+
+    #[error("Unsupported ILLA Builder configuration: {0}")]
+    InstanceConfig(String),
+
+    #[error("State file error: {0}")]
+    State(String),
+
+    #[error("Aborted: {0}")]
+    Aborted(String),
+
+    #[error("ILLA Builder is busy: {0}")]
+    Locked(String),
+
+    #[error("Port in use: {0}")]
+    PortInUse(String),
+
+    #[error("ILLA Builder is not ready: {0}")]
+    NotReady(String),
+
+    #[error("Interrupted: {0}")]
+    Interrupted(String),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl IllaError {
+    /// The process exit code reported for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            IllaError::Other(_) => 1,
+            IllaError::InvalidArgument(_) => 2,
+            IllaError::RuntimeUnavailable(_) => 3,
+            IllaError::ImagePull(_) => 4,
+            IllaError::ContainerNotFound(_) => 5,
+            IllaError::ContainerCreate(_) => 6,
+            IllaError::ContainerStart(_) => 7,
+            IllaError::ContainerStop(_) => 8,
+            IllaError::ContainerRestart(_) => 9,
+            IllaError::ContainerRemove(_) => 10,
+            IllaError::Io(_) => 11,
+            IllaError::Runtime(_) => 12,
+            IllaError::ImageLoad(_) => 13,
+            IllaError::ImageVerify(_) => 14,
+            IllaError::Registry(_) => 15,
+            IllaError::InstanceConfig(_) => 16,
+            IllaError::State(_) => 17,
+            IllaError::Aborted(_) => 18,
+            IllaError::Locked(_) => 19,
+            IllaError::PortInUse(_) => 20,
+            IllaError::NotReady(_) => 21,
+            // As shells report a run ended by SIGINT
+            IllaError::Interrupted(_) => 130,
+        }
+    }
+
+    /// A stable machine-readable name for the failure class
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IllaError::Other(_) => "other",
+            IllaError::InvalidArgument(_) => "invalid_argument",
+            IllaError::RuntimeUnavailable(_) => "runtime_unavailable",
+            IllaError::ImagePull(_) => "image_pull",
+            IllaError::ContainerNotFound(_) => "container_not_found",
+            IllaError::ContainerCreate(_) => "container_create",
+            IllaError::ContainerStart(_) => "container_start",
+            IllaError::ContainerStop(_) => "container_stop",
+            IllaError::ContainerRestart(_) => "container_restart",
+            IllaError::ContainerRemove(_) => "container_remove",
+            IllaError::Io(_) => "io",
+            IllaError::Runtime(_) => "runtime",
+            IllaError::ImageLoad(_) => "image_load",
+            IllaError::ImageVerify(_) => "image_verify",
+            IllaError::Registry(_) => "registry",
+            IllaError::InstanceConfig(_) => "instance_config",
+            IllaError::State(_) => "state",
+            IllaError::Aborted(_) => "aborted",
+            IllaError::Locked(_) => "locked",
+            IllaError::PortInUse(_) => "port_in_use",
+            IllaError::NotReady(_) => "not_ready",
+            IllaError::Interrupted(_) => "interrupted",
+        }
+    }
+
+    /// Wrap a runtime error raised while operating on container `name`,
+    /// reporting a missing container as [`IllaError::ContainerNotFound`]
+    pub fn container(name: &str, e: RuntimeError, wrap: fn(RuntimeError) -> IllaError) -> Self {
+        if runtime::is_not_found(&e) {
+            IllaError::ContainerNotFound(name.to_string())
+        } else {
+            wrap(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, io};
+
+    fn server_error(status_code: u16) -> RuntimeError {
+        RuntimeError::DockerResponseServerError {
+            status_code,
+            message: String::from("boom"),
+        }
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = vec![
+            IllaError::Other(anyhow!("boom")),
+            IllaError::InvalidArgument(String::from("port")),
+            IllaError::RuntimeUnavailable(String::from("no socket")),
+            IllaError::ImagePull(server_error(500)),
+            IllaError::ContainerNotFound(String::from("illa_builder")),
+            IllaError::ContainerCreate(server_error(500)),
+            IllaError::ContainerStart(server_error(500)),
+            IllaError::ContainerStop(server_error(500)),
+            IllaError::ContainerRestart(server_error(500)),
+            IllaError::ContainerRemove(server_error(500)),
+            IllaError::Io(io::Error::from(io::ErrorKind::PermissionDenied)),
+            IllaError::Runtime(server_error(500)),
+            IllaError::ImageLoad(server_error(500)),
+            IllaError::ImageVerify(String::from("digest mismatch")),
+            IllaError::Registry(String::from("401 Unauthorized")),
+            IllaError::InstanceConfig(String::from("no data directory")),
+            IllaError::State(String::from("corrupt")),
+            IllaError::Aborted(String::from("nothing was changed")),
+            IllaError::Locked(String::from("illa_builder")),
+            IllaError::PortInUse(String::from("8080")),
+            IllaError::NotReady(String::from("illa_builder")),
+            IllaError::Interrupted(String::from("pulling")),
+        ];
+        let codes: HashSet<i32> = errors.iter().map(IllaError::exit_code).collect();
+
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0));
+        for status in [ExitStatus::UpdateAvailable, ExitStatus::DriftDetected] {
+            assert!(!codes.contains(&status.code()), "{status:?}");
+        }
+        assert_eq!(ExitStatus::default().code(), 0);
+    }
+
+    #[test]
+    fn missing_container_maps_to_not_found() {
+        let e = IllaError::container("illa_builder", server_error(404), IllaError::ContainerStop);
+
+        assert!(matches!(e, IllaError::ContainerNotFound(ref name) if name == "illa_builder"));
+        assert_eq!(e.exit_code(), 5);
+    }
+
+    #[test]
+    fn other_failures_keep_their_class() {
+        let e = IllaError::container("illa_builder", server_error(500), IllaError::ContainerStop);
+
+        assert!(matches!(e, IllaError::ContainerStop(_)));
+        assert_eq!(e.exit_code(), 8);
+    }
+}
@@ -0,0 +1,423 @@
+use super::{ContainerRuntime, ContainerStats, RuntimeError, RuntimeInfo, RuntimeKind};
+use crate::result::{self, IllaError};
+use async_trait::async_trait;
+use bollard::{
+    auth::DockerCredentials,
+    container::{
+        Config, CreateContainerOptions, InspectContainerOptions, KillContainerOptions,
+        ListContainersOptions, LogsOptions, RemoveContainerOptions, RestartContainerOptions,
+        StartContainerOptions, StatsOptions, StopContainerOptions,
+    },
+    image::{CreateImageOptions, ImportImageOptions, ListImagesOptions, RemoveImageOptions},
+    models::{
+        BuildInfo, ContainerInspectResponse, ContainerSummary, CreateImageInfo, ImageInspect,
+        ImageSummary,
+    },
+    Docker, API_DEFAULT_VERSION,
+};
+use futures_util::{stream, stream::BoxStream, StreamExt, TryStreamExt};
+use hyper::{body::Bytes, Body};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::Path,
+    time::Duration,
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+// Chunk size used when streaming an image archive to the daemon
+const ARCHIVE_CHUNK: usize = 64 * 1024;
+
+// Timeout (seconds) for probing a candidate socket, kept short as several
+// dead ones may be tried in turn
+const PROBE_TIMEOUT: u64 = 3;
+
+// Timeout (seconds) for the requests to the runtime once connected
+const REQUEST_TIMEOUT: u64 = 120;
+
+/// A Docker or Podman daemon reached through its Docker-compatible API
+#[derive(Debug, Clone)]
+pub struct BollardRuntime {
+    pub docker: Docker,
+    pub info: RuntimeInfo,
+}
+
+/// Connect to the first reachable container runtime.
+///
+/// `DOCKER_HOST` and `CONTAINER_HOST` take precedence, as a local socket or
+/// a `tcp://` address; otherwise the Docker socket and the rootful,
+/// rootless and `podman machine` Podman sockets are tried in turn.
+pub async fn connect() -> result::Result<BollardRuntime> {
+    let mut last_err = None;
+    for candidate in candidate_sockets() {
+        let docker = match is_remote(&candidate) {
+            true => Docker::connect_with_http(&candidate, PROBE_TIMEOUT, API_DEFAULT_VERSION),
+            false => Docker::connect_with_local(&candidate, PROBE_TIMEOUT, API_DEFAULT_VERSION),
+        };
+        let docker = match docker {
+            Ok(docker) => docker,
+            Err(e) => {
+                last_err = Some(format!("{candidate}: {e}"));
+                continue;
+            }
+        };
+        match docker.ping().await {
+            Ok(_) => {
+                return describe(docker.with_timeout(Duration::from_secs(REQUEST_TIMEOUT))).await
+            }
+            Err(e) => last_err = Some(format!("{candidate}: {e}")),
+        }
+    }
+    Err(IllaError::RuntimeUnavailable(last_err.unwrap_or_else(
+        || String::from("no container runtime socket found"),
+    )))
+}
+
+async fn describe(docker: Docker) -> result::Result<BollardRuntime> {
+    let version = docker
+        .version()
+        .await
+        .map_err(|e| IllaError::RuntimeUnavailable(e.to_string()))?;
+    let is_podman = version
+        .components
+        .as_ref()
+        .map(|components| {
+            components
+                .iter()
+                .any(|c| c.name.to_lowercase().contains("podman"))
+        })
+        .unwrap_or(false)
+        || version
+            .platform
+            .as_ref()
+            .map(|p| p.name.to_lowercase().contains("podman"))
+            .unwrap_or(false);
+    let kind = if is_podman {
+        RuntimeKind::Podman
+    } else {
+        RuntimeKind::Docker
+    };
+    let security_options = docker
+        .info()
+        .await
+        .ok()
+        .and_then(|info| info.security_options)
+        .unwrap_or_default();
+    let has_option = |name: &str| {
+        security_options
+            .iter()
+            .any(|opt| opt == name || opt.contains(&format!("name={name}")))
+    };
+
+    Ok(BollardRuntime {
+        info: RuntimeInfo {
+            kind,
+            version: version.version.unwrap_or_else(|| String::from("unknown")),
+            rootless: has_option("rootless"),
+            selinux: has_option("selinux"),
+        },
+        docker,
+    })
+}
+
+#[async_trait]
+impl ContainerRuntime for BollardRuntime {
+    fn info(&self) -> &RuntimeInfo {
+        &self.info
+    }
+
+    fn pull(
+        &self,
+        image: &str,
+        credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'_, Result<CreateImageInfo, RuntimeError>> {
+        self.docker
+            .create_image(
+                Some(CreateImageOptions {
+                    from_image: image.to_string(),
+                    ..Default::default()
+                }),
+                None,
+                credentials,
+            )
+            .boxed()
+    }
+
+    fn export_image(&self, image: &str) -> BoxStream<'_, Result<Bytes, RuntimeError>> {
+        self.docker.export_image(image).boxed()
+    }
+
+    fn load_image(&self, archive: &Path) -> BoxStream<'_, Result<BuildInfo, RuntimeError>> {
+        let archive = archive.to_path_buf();
+        stream::once(async move { File::open(&archive).await })
+            .map_ok(move |file| {
+                // Stream the tarball instead of reading it into memory
+                let chunks = stream::try_unfold(file, |mut file| async move {
+                    let mut buf = vec![0; ARCHIVE_CHUNK];
+                    let n = file.read(&mut buf).await?;
+                    buf.truncate(n);
+                    Ok::<_, std::io::Error>((n > 0).then_some((buf, file)))
+                });
+                self.docker.import_image(
+                    ImportImageOptions { quiet: true },
+                    Body::wrap_stream(chunks),
+                    None,
+                )
+            })
+            .map_err(RuntimeError::from)
+            .try_flatten()
+            .boxed()
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, RuntimeError> {
+        self.docker.inspect_image(image).await
+    }
+
+    async fn list_images(&self) -> Result<Vec<ImageSummary>, RuntimeError> {
+        self.docker
+            .list_images(Some(ListImagesOptions::<String> {
+                all: false,
+                digests: true,
+                ..Default::default()
+            }))
+            .await
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), RuntimeError> {
+        self.docker
+            .remove_image(
+                image,
+                Some(RemoveImageOptions {
+                    force: false,
+                    noprune: false,
+                }),
+                None,
+            )
+            .await
+            .map(|_| ())
+    }
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), RuntimeError> {
+        self.docker
+            .create_container(Some(CreateContainerOptions { name }), config)
+            .await
+            .map(|_| ())
+    }
+
+    async fn start(&self, name: &str) -> Result<(), RuntimeError> {
+        self.docker
+            .start_container(name, None::<StartContainerOptions<String>>)
+            .await
+    }
+
+    async fn stop(&self, name: &str, timeout: i64) -> Result<(), RuntimeError> {
+        self.docker
+            .stop_container(name, Some(StopContainerOptions { t: timeout }))
+            .await
+    }
+
+    async fn restart(&self, name: &str, timeout: isize) -> Result<(), RuntimeError> {
+        self.docker
+            .restart_container(name, Some(RestartContainerOptions { t: timeout }))
+            .await
+    }
+
+    async fn kill(&self, name: &str, signal: &str) -> Result<(), RuntimeError> {
+        self.docker
+            .kill_container(name, Some(KillContainerOptions { signal }))
+            .await
+    }
+
+    async fn inspect(
+        &self,
+        name: &str,
+        size: bool,
+    ) -> Result<ContainerInspectResponse, RuntimeError> {
+        self.docker
+            .inspect_container(name, Some(InspectContainerOptions { size }))
+            .await
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), RuntimeError> {
+        self.docker
+            .remove_container(
+                name,
+                Some(RemoveContainerOptions {
+                    force,
+                    ..Default::default()
+                }),
+            )
+            .await
+    }
+
+    fn logs(&self, name: &str, tail: usize) -> BoxStream<'_, Result<String, RuntimeError>> {
+        self.docker
+            .logs(
+                name,
+                Some(LogsOptions {
+                    stdout: true,
+                    stderr: true,
+                    tail: tail.to_string(),
+                    ..Default::default()
+                }),
+            )
+            .map_ok(|output| output.to_string())
+            .boxed()
+    }
+
+    async fn stats(&self, name: &str) -> Result<ContainerStats, RuntimeError> {
+        let stats = self
+            .docker
+            .stats(
+                name,
+                Some(StatsOptions {
+                    stream: false,
+                    one_shot: false,
+                }),
+            )
+            .try_next()
+            .await?
+            .ok_or(RuntimeError::DockerResponseServerError {
+                status_code: 404,
+                message: format!("no stats reported for {name}"),
+            })?;
+
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or_default()
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+        let cpus = stats.cpu_stats.online_cpus.unwrap_or(1);
+        let cpu_percent = if system_delta > 0 {
+            cpu_delta as f64 / system_delta as f64 * cpus as f64 * 100.0
+        } else {
+            0.0
+        };
+        Ok(ContainerStats {
+            cpu_percent,
+            memory_usage: stats.memory_stats.usage.unwrap_or_default(),
+            memory_limit: stats.memory_stats.limit.unwrap_or_default(),
+        })
+    }
+
+    async fn list(&self, name: &str) -> Result<Vec<ContainerSummary>, RuntimeError> {
+        let mut filters = HashMap::new();
+        filters.insert("name".to_string(), vec![name.to_string()]);
+        self.docker
+            .list_containers(Some(ListContainersOptions::<String> {
+                all: true,
+                filters,
+                ..Default::default()
+            }))
+            .await
+    }
+}
+
+#[cfg(unix)]
+fn candidate_sockets() -> Vec<String> {
+    let mut sockets = daemon_hosts(&["unix://", "tcp://"]);
+    sockets.push(String::from("/var/run/docker.sock"));
+    let runtime_dir = env::var("XDG_RUNTIME_DIR")
+        .ok()
+        .or_else(|| Some(format!("/run/user/{}", current_uid())));
+    if let Some(dir) = runtime_dir {
+        sockets.push(format!("{dir}/podman/podman.sock"));
+    }
+    sockets.push(String::from("/run/podman/podman.sock"));
+    #[cfg(target_os = "macos")]
+    sockets.extend(podman_machine_sockets());
+    dedup(sockets)
+        .into_iter()
+        .filter(|s| is_remote(s) || Path::new(s.trim_start_matches("unix://")).exists())
+        .collect()
+}
+
+#[cfg(windows)]
+fn candidate_sockets() -> Vec<String> {
+    let mut sockets = daemon_hosts(&["npipe://", "tcp://"]);
+    sockets.push(String::from("npipe:////./pipe/docker_engine"));
+    sockets.push(String::from("npipe:////./pipe/podman-machine-default"));
+    dedup(sockets)
+}
+
+// The sockets `podman machine` forwards on macOS, by Podman version
+#[cfg(target_os = "macos")]
+fn podman_machine_sockets() -> Vec<String> {
+    let mut sockets = vec![];
+    if let Some(home) = dirs::home_dir() {
+        let machine = home.join(".local/share/containers/podman/machine");
+        for dir in ["", "qemu", "applehv", "podman-machine-default"] {
+            sockets.push(machine.join(dir).join("podman.sock").display().to_string());
+        }
+    }
+    if let Ok(tmp) = env::var("TMPDIR") {
+        let tmp = Path::new(&tmp).join("podman");
+        sockets.push(
+            tmp.join("podman-machine-default-api.sock")
+                .display()
+                .to_string(),
+        );
+    }
+    sockets
+}
+
+// The daemons `DOCKER_HOST` and `CONTAINER_HOST` point at, if reached
+// through one of `schemes`
+fn daemon_hosts(schemes: &[&str]) -> Vec<String> {
+    ["DOCKER_HOST", "CONTAINER_HOST"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .filter(|host| schemes.iter().any(|scheme| host.starts_with(scheme)))
+        .collect()
+}
+
+// Drop later mentions of a socket, keeping the order they are tried in
+fn dedup(sockets: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    sockets
+        .into_iter()
+        .filter(|s| seen.insert(s.trim_start_matches("unix://").to_string()))
+        .collect()
+}
+
+fn is_remote(socket: &str) -> bool {
+    socket.starts_with("tcp://") || socket.starts_with("http://")
+}
+
+#[cfg(unix)]
+fn current_uid() -> u32 {
+    // SAFETY: getuid cannot fail and touches no memory
+    unsafe { libc::getuid() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sockets_are_tried_once_in_order() {
+        let sockets = dedup(vec![
+            String::from("tcp://10.0.0.5:2375"),
+            String::from("unix:///var/run/docker.sock"),
+            String::from("/run/podman/podman.sock"),
+            String::from("/var/run/docker.sock"),
+            String::from("tcp://10.0.0.5:2375"),
+        ]);
+
+        assert_eq!(
+            sockets,
+            [
+                "tcp://10.0.0.5:2375",
+                "unix:///var/run/docker.sock",
+                "/run/podman/podman.sock"
+            ]
+        );
+        assert!(is_remote(&sockets[0]));
+        assert!(!is_remote(&sockets[1]));
+    }
+}
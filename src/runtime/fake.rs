@@ -0,0 +1,816 @@
+use super::{ContainerRuntime, ContainerStats, RuntimeError, RuntimeInfo, RuntimeKind};
+use crate::command::image::{normalize_tag, read_archive};
+use async_trait::async_trait;
+use bollard::{
+    auth::DockerCredentials,
+    container::Config,
+    models::{
+        BuildInfo, ContainerConfig, ContainerInspectResponse, ContainerState,
+        ContainerStateStatusEnum, ContainerSummary, CreateImageInfo, HostConfig, ImageInspect,
+        ImageSummary, MountPoint, MountPointTypeEnum, NetworkSettings, Port, PortBinding,
+        PortTypeEnum, ProgressDetail,
+    },
+};
+use futures_util::{stream, stream::BoxStream, StreamExt};
+use hyper::body::Bytes;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    path::Path,
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+pub(crate) const FAKE_DIGEST: &str =
+    "sha256:5f1e3a9c0b7d2e4f6a8c0e2b4d6f8a1c3e5a7c9e1b3d5f7a9c1e3b5d7f9a1c3e";
+
+/// An in-memory container runtime for exercising the command flows.
+///
+/// Every operation is recorded in [`FakeRuntime::calls`], and any operation
+/// can be made to fail with [`FakeRuntime::fail`].
+pub(crate) struct FakeRuntime {
+    info: RuntimeInfo,
+    state: Mutex<FakeState>,
+}
+
+#[derive(Default)]
+struct FakeState {
+    images: HashMap<String, FakeImage>,
+    containers: HashMap<String, FakeContainer>,
+    failures: HashMap<String, String>,
+    hooks: HashMap<String, Box<dyn Fn() + Send>>,
+    calls: Vec<String>,
+    clock: i64,
+}
+
+#[derive(Clone)]
+struct FakeImage {
+    id: String,
+    // Only pulled images have a registry manifest digest
+    repo_digest: Option<String>,
+    // Stands in for the creation time; later images are newer
+    created: i64,
+}
+
+// Every fake image takes this many bytes, and every container this many
+// on top of it
+pub(crate) const FAKE_IMAGE_SIZE: i64 = 3072;
+pub(crate) const FAKE_WRITABLE_SIZE: i64 = 512;
+
+// The memory every running fake container uses
+pub(crate) const FAKE_MEMORY_USAGE: u64 = 256 << 20;
+
+#[derive(Clone)]
+struct FakeContainer {
+    id: String,
+    config: Config<String>,
+    running: bool,
+    // Only containers made by `create` start out never started
+    started: bool,
+    exit_code: i64,
+    // Only SIGKILL stops a container that ignores its stop signal
+    ignores_signals: bool,
+    // Not even SIGKILL stops a container stuck in the kernel
+    survives_kill: bool,
+    logs: Vec<String>,
+}
+
+impl FakeState {
+    // The name of the container `name`, which may also be its ID like the
+    // daemon accepts
+    fn container_name(&self, name: &str) -> String {
+        self.containers
+            .iter()
+            .find(|(_, c)| c.id == name)
+            .map(|(found, _)| found.clone())
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    // Look an image up by `name:tag`, or by `name[:tag]@digest` like the
+    // daemon does for pinned references
+    fn find_image(&self, reference: &str) -> Option<&FakeImage> {
+        if let Some(image) = self.images.get(reference) {
+            return Some(image);
+        }
+        let (name, digest) = reference.split_once('@')?;
+        if let Some(image) = self.images.get(name) {
+            if image.repo_digest.as_deref() == Some(digest) {
+                return Some(image);
+            }
+        }
+        let name = repository(name);
+        self.images
+            .iter()
+            .find(|(tag, image)| {
+                repository(tag) == name && image.repo_digest.as_deref() == Some(digest)
+            })
+            .map(|(_, image)| image)
+    }
+
+    // The ID of the image a container was created from
+    fn image_id_of(&self, container: &FakeContainer) -> Option<String> {
+        let image = container.config.image.as_deref()?;
+        self.find_image(image).map(|image| image.id.clone())
+    }
+
+    fn next_created(&mut self) -> i64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+// `name:tag` without the tag, keeping a registry port
+fn repository(reference: &str) -> &str {
+    match reference.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => name,
+        _ => reference,
+    }
+}
+
+impl FakeRuntime {
+    pub(crate) fn new() -> Self {
+        FakeRuntime {
+            info: RuntimeInfo {
+                kind: RuntimeKind::Docker,
+                version: String::from("24.0.0"),
+                rootless: false,
+                selinux: false,
+            },
+            state: Mutex::new(FakeState::default()),
+        }
+    }
+
+    pub(crate) fn with_info(mut self, info: RuntimeInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Make every later call of `op` fail with a daemon error. The
+    /// pseudo-op `pull_stream` fails a pull inside its status stream.
+    pub(crate) fn fail(&self, op: &str, message: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.insert(op.to_string(), message.to_string());
+    }
+
+    /// Run `hook` whenever `op` is called, e.g. to press Ctrl-C while it
+    /// is in flight
+    pub(crate) fn on(&self, op: &str, hook: impl Fn() + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        state.hooks.insert(op.to_string(), Box::new(hook));
+    }
+
+    /// Add an image as if it had been pulled, with [`FAKE_DIGEST`] as its
+    /// manifest digest
+    pub(crate) fn add_image(&self, image: &str) {
+        let mut state = self.state.lock().unwrap();
+        let fake = FakeImage {
+            id: image_id(&image_config(image)),
+            repo_digest: Some(FAKE_DIGEST.to_string()),
+            created: state.next_created(),
+        };
+        state.images.insert(image.to_string(), fake);
+    }
+
+    pub(crate) fn add_container(&self, name: &str, config: Config<String>, running: bool) {
+        let mut state = self.state.lock().unwrap();
+        let id = format!("{:0>64}", state.containers.len() + 1);
+        state.containers.insert(
+            name.to_string(),
+            FakeContainer {
+                id,
+                config,
+                running,
+                started: true,
+                exit_code: 0,
+                ignores_signals: false,
+                survives_kill: false,
+                logs: vec![],
+            },
+        );
+    }
+
+    /// Have the container `name` ignore every signal but SIGKILL, like a
+    /// Builder stuck shutting down
+    pub(crate) fn ignore_signals(&self, name: &str) {
+        if let Some(c) = self.state.lock().unwrap().containers.get_mut(name) {
+            c.ignores_signals = true;
+        }
+    }
+
+    /// Have the container `name` keep running even when killed, like a
+    /// process stuck in the kernel
+    pub(crate) fn survive_kill(&self, name: &str) {
+        if let Some(c) = self.state.lock().unwrap().containers.get_mut(name) {
+            c.ignores_signals = true;
+            c.survives_kill = true;
+        }
+    }
+
+    /// Have the container `name` write `lines` to its log
+    pub(crate) fn add_logs(&self, name: &str, lines: &[&str]) {
+        if let Some(c) = self.state.lock().unwrap().containers.get_mut(name) {
+            c.logs.extend(lines.iter().map(|line| format!("{line}\n")));
+        }
+    }
+
+    pub(crate) fn has_image(&self, image: &str) -> bool {
+        self.state.lock().unwrap().find_image(image).is_some()
+    }
+
+    pub(crate) fn has_container(&self, name: &str) -> bool {
+        self.state.lock().unwrap().containers.contains_key(name)
+    }
+
+    pub(crate) fn is_running(&self, name: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .containers
+            .get(name)
+            .map(|c| c.running)
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    // Record the call and return the injected failure, if any
+    fn enter(&self, op: &str, name: &str) -> Result<(), RuntimeError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("{op} {name}"));
+        if let Some(hook) = state.hooks.get(op) {
+            hook();
+        }
+        match state.failures.get(op) {
+            Some(message) => Err(server_error(500, message)),
+            None => Ok(()),
+        }
+    }
+
+    fn with_container<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut FakeContainer) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let mut state = self.state.lock().unwrap();
+        let name = state.container_name(name);
+        match state.containers.get_mut(&name) {
+            Some(container) => f(container),
+            None => Err(server_error(404, &format!("No such container: {name}"))),
+        }
+    }
+}
+
+/// A container config shaped like the one `illa deploy` creates
+pub(crate) fn builder_config(image: &str, port: u16, data_dir: &str) -> Config<String> {
+    let info = FakeRuntime::new().info;
+    let mut port_bindings = HashMap::new();
+    port_bindings.insert(
+        "2022/tcp".to_string(),
+        Some(vec![PortBinding {
+            host_port: Some(port.to_string()),
+            host_ip: Some("0.0.0.0".to_string()),
+        }]),
+    );
+    Config {
+        image: Some(image.to_string()),
+        env: Some(vec![
+            "ILLA_SERVER_MODE=release".to_string(),
+            "ILLA_DEPLOY_MODE=self-host".to_string(),
+            "POSTGRES_PASSWORD=secret".to_string(),
+        ]),
+        host_config: Some(info.builder_host_config(port_bindings, data_dir)),
+        ..Default::default()
+    }
+}
+
+/// A `docker save` tarball holding `tags`. The manifest names the config
+/// blob by `config_name`, which is normally `<hex>.json` of its digest.
+pub(crate) fn image_archive(path: &Path, tags: &[&str], config: &[u8], config_name: &str) {
+    let manifest = serde_json::json!([{
+        "Config": config_name,
+        "RepoTags": tags,
+        "Layers": ["layer.tar"],
+    }]);
+    let mut archive = tar::Builder::new(File::create(path).unwrap());
+    for (name, content) in [
+        (config_name, config.to_vec()),
+        ("layer.tar", vec![0; 512]),
+        ("manifest.json", manifest.to_string().into_bytes()),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive
+            .append_data(&mut header, name, content.as_slice())
+            .unwrap();
+    }
+    archive.finish().unwrap();
+}
+
+/// The config blob of a fake image; its digest is the image ID
+pub(crate) fn image_config(image: &str) -> Vec<u8> {
+    serde_json::json!({ "config": { "Image": image } })
+        .to_string()
+        .into_bytes()
+}
+
+pub(crate) fn image_id(config: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(config))
+}
+
+fn server_error(status_code: u16, message: &str) -> RuntimeError {
+    RuntimeError::DockerResponseServerError {
+        status_code,
+        message: message.to_string(),
+    }
+}
+
+fn mount_points(host_config: &Option<HostConfig>) -> Vec<MountPoint> {
+    let mut mounts: Vec<MountPoint> = host_config
+        .as_ref()
+        .and_then(|h| h.mounts.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|m| MountPoint {
+            typ: Some(MountPointTypeEnum::BIND),
+            source: m.source,
+            destination: m.target,
+            rw: Some(!m.read_only.unwrap_or(false)),
+            ..Default::default()
+        })
+        .collect();
+    for bind in host_config
+        .as_ref()
+        .and_then(|h| h.binds.clone())
+        .unwrap_or_default()
+    {
+        let mut parts = bind.split(':');
+        mounts.push(MountPoint {
+            typ: Some(MountPointTypeEnum::BIND),
+            source: parts.next().map(String::from),
+            destination: parts.next().map(String::from),
+            mode: parts.next().map(String::from),
+            rw: Some(true),
+            ..Default::default()
+        });
+    }
+    mounts
+}
+
+fn ports(host_config: &Option<HostConfig>) -> Vec<Port> {
+    let bindings = host_config
+        .as_ref()
+        .and_then(|h| h.port_bindings.clone())
+        .unwrap_or_default();
+    let mut ports = vec![];
+    for (container_port, bindings) in bindings {
+        let private_port = container_port
+            .split('/')
+            .next()
+            .and_then(|p| p.parse().ok())
+            .unwrap_or_default();
+        for binding in bindings.unwrap_or_default() {
+            ports.push(Port {
+                ip: binding.host_ip,
+                private_port,
+                public_port: binding.host_port.and_then(|p| p.parse().ok()),
+                typ: Some(PortTypeEnum::TCP),
+            });
+        }
+    }
+    ports
+}
+
+fn inspect_response(name: &str, container: &FakeContainer) -> ContainerInspectResponse {
+    let host_config = container.config.host_config.clone();
+    let mounts = mount_points(&host_config);
+
+    ContainerInspectResponse {
+        id: Some(container.id.clone()),
+        name: Some(format!("/{name}")),
+        image: container.config.image.clone(),
+        state: Some(ContainerState {
+            status: Some(match (container.running, container.started) {
+                (true, _) => ContainerStateStatusEnum::RUNNING,
+                (false, true) => ContainerStateStatusEnum::EXITED,
+                (false, false) => ContainerStateStatusEnum::CREATED,
+            }),
+            running: Some(container.running),
+            exit_code: Some(container.exit_code),
+            ..Default::default()
+        }),
+        config: Some(ContainerConfig {
+            image: container.config.image.clone(),
+            env: container.config.env.clone(),
+            labels: container.config.labels.clone(),
+            cmd: container.config.cmd.clone(),
+            entrypoint: container.config.entrypoint.clone(),
+            user: container.config.user.clone(),
+            working_dir: container.config.working_dir.clone(),
+            stop_signal: container.config.stop_signal.clone(),
+            ..Default::default()
+        }),
+        host_config,
+        mounts: Some(mounts),
+        network_settings: container
+            .config
+            .networking_config
+            .as_ref()
+            .map(|networking| NetworkSettings {
+                networks: Some(networking.endpoints_config.clone()),
+                ..Default::default()
+            }),
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    fn info(&self) -> &RuntimeInfo {
+        &self.info
+    }
+
+    fn pull(
+        &self,
+        image: &str,
+        _credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'_, Result<CreateImageInfo, RuntimeError>> {
+        let event = |id: Option<&str>, s: &str, current: i64, total: i64| {
+            Ok(CreateImageInfo {
+                id: id.map(String::from),
+                status: Some(s.to_string()),
+                progress_detail: (total > 0).then_some(ProgressDetail {
+                    current: Some(current),
+                    total: Some(total),
+                }),
+                ..Default::default()
+            })
+        };
+        let mut events = vec![event(
+            Some("latest"),
+            "Pulling from illasoft/illa-builder",
+            0,
+            0,
+        )];
+        if let Err(e) = self.enter("pull", image) {
+            events.push(Err(e));
+            return stream::iter(events).boxed();
+        }
+        // An error the daemon reports inside the status stream
+        if let Some(message) = self.state.lock().unwrap().failures.get("pull_stream") {
+            events.push(Ok(CreateImageInfo {
+                error: Some(message.clone()),
+                ..Default::default()
+            }));
+            return stream::iter(events).boxed();
+        }
+        for (layer, size) in [("a1b2c3d4e5f6", 2048), ("0f9e8d7c6b5a", 1024)] {
+            events.extend([
+                event(Some(layer), "Pulling fs layer", 0, 0),
+                event(Some(layer), "Waiting", 0, 0),
+                event(Some(layer), "Downloading", size / 2, size),
+                event(Some(layer), "Downloading", size, size),
+                event(Some(layer), "Download complete", 0, 0),
+                event(Some(layer), "Extracting", size, size),
+                event(Some(layer), "Pull complete", 0, 0),
+            ]);
+        }
+        events.push(event(None, &format!("Digest: {FAKE_DIGEST}"), 0, 0));
+        self.add_image(image);
+        stream::iter(events).boxed()
+    }
+
+    fn export_image(&self, image: &str) -> BoxStream<'_, Result<Bytes, RuntimeError>> {
+        let exported = self.enter("export_image", image).and_then(|_| {
+            if !self.has_image(image) {
+                return Err(server_error(404, &format!("No such image: {image}")));
+            }
+            let config = image_config(image);
+            let dir = env::temp_dir().join(format!("illa-export-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("image.tar");
+            let hex = image_id(&config).trim_start_matches("sha256:").to_string();
+            image_archive(&path, &[image], &config, &format!("{hex}.json"));
+            let content = fs::read(&path).unwrap();
+            fs::remove_dir_all(dir).ok();
+            Ok(content)
+        });
+        match exported {
+            // Split the tarball to exercise chunked writes
+            Ok(content) => stream::iter(
+                content
+                    .chunks(1024)
+                    .map(|c| Ok(Bytes::copy_from_slice(c)))
+                    .collect::<Vec<_>>(),
+            )
+            .boxed(),
+            Err(e) => stream::iter(vec![Err(e)]).boxed(),
+        }
+    }
+
+    fn load_image(&self, archive: &Path) -> BoxStream<'_, Result<BuildInfo, RuntimeError>> {
+        let name = archive.display().to_string();
+        if let Err(e) = self.enter("load_image", &name) {
+            return stream::iter(vec![Err(e)]).boxed();
+        }
+        // Like the daemon, name the image by the digest of the config it holds
+        let event = match read_archive(archive) {
+            Ok(manifest) => {
+                let id = manifest.config_digest.unwrap_or_default();
+                let mut state = self.state.lock().unwrap();
+                // Docker lists Docker Hub images by their short name
+                for tag in &manifest.repo_tags {
+                    let tag = match self.info.kind {
+                        RuntimeKind::Docker => normalize_tag(tag),
+                        RuntimeKind::Podman => tag,
+                    };
+                    let created = state.next_created();
+                    state.images.insert(
+                        tag.to_string(),
+                        FakeImage {
+                            id: id.clone(),
+                            repo_digest: None,
+                            created,
+                        },
+                    );
+                }
+                BuildInfo {
+                    stream: Some(format!("Loaded image ID: {id}")),
+                    ..Default::default()
+                }
+            }
+            Err(e) => BuildInfo {
+                error: Some(e.to_string()),
+                ..Default::default()
+            },
+        };
+        stream::iter(vec![Ok(event)]).boxed()
+    }
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, RuntimeError> {
+        self.enter("inspect_image", image)?;
+        let state = self.state.lock().unwrap();
+        match state.find_image(image) {
+            Some(found) => Ok(ImageInspect {
+                id: Some(found.id.clone()),
+                repo_tags: Some(vec![image.to_string()]),
+                size: Some(FAKE_IMAGE_SIZE),
+                repo_digests: Some(
+                    found
+                        .repo_digest
+                        .iter()
+                        .map(|d| format!("{}@{d}", repository(image.split('@').next().unwrap())))
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            None => Err(server_error(404, &format!("No such image: {image}"))),
+        }
+    }
+
+    async fn list_images(&self) -> Result<Vec<ImageSummary>, RuntimeError> {
+        self.enter("list_images", "")?;
+        let state = self.state.lock().unwrap();
+        let mut summaries: Vec<ImageSummary> = vec![];
+        for (tag, image) in &state.images {
+            let digests = image
+                .repo_digest
+                .iter()
+                .map(|d| format!("{}@{d}", repository(tag)));
+            match summaries.iter_mut().find(|s| s.id == image.id) {
+                Some(summary) => {
+                    summary.repo_tags.push(tag.clone());
+                    summary.repo_digests.extend(digests);
+                }
+                None => summaries.push(ImageSummary {
+                    id: image.id.clone(),
+                    repo_tags: vec![tag.clone()],
+                    repo_digests: digests.collect(),
+                    created: image.created,
+                    size: FAKE_IMAGE_SIZE,
+                    ..Default::default()
+                }),
+            }
+        }
+        summaries.sort_by_key(|s| s.created);
+        Ok(summaries)
+    }
+
+    async fn remove_image(&self, image: &str) -> Result<(), RuntimeError> {
+        self.enter("remove_image", image)?;
+        let mut state = self.state.lock().unwrap();
+        let tags: Vec<String> = match state.images.get(image) {
+            Some(_) => vec![image.to_string()],
+            None => state
+                .images
+                .iter()
+                .filter(|(_, found)| found.id == image)
+                .map(|(tag, _)| tag.clone())
+                .collect(),
+        };
+        let Some(id) = tags.first().map(|tag| state.images[tag].id.clone()) else {
+            return Err(server_error(404, &format!("No such image: {image}")));
+        };
+        if tags.len() > 1 {
+            return Err(server_error(
+                409,
+                &format!("unable to delete {image} (must be forced) - image is referenced in multiple repositories"),
+            ));
+        }
+        // The last tag of an image in use cannot go
+        let last_tag = state.images.values().filter(|i| i.id == id).count() == 1;
+        let in_use = state
+            .containers
+            .values()
+            .any(|c| state.image_id_of(c).as_deref() == Some(id.as_str()));
+        if last_tag && in_use {
+            return Err(server_error(
+                409,
+                &format!("conflict: unable to remove {image} - image is being used by a container"),
+            ));
+        }
+        state.images.remove(&tags[0]);
+        Ok(())
+    }
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), RuntimeError> {
+        self.enter("create", name)?;
+        if self.has_container(name) {
+            return Err(server_error(
+                409,
+                &format!("Conflict. The container name \"/{name}\" is already in use"),
+            ));
+        }
+        let image = config.image.clone().unwrap_or_default();
+        if !self.has_image(&image) {
+            return Err(server_error(404, &format!("No such image: {image}")));
+        }
+        self.add_container(name, config, false);
+        self.with_container(name, |c| {
+            c.started = false;
+            Ok(())
+        })
+    }
+
+    async fn start(&self, name: &str) -> Result<(), RuntimeError> {
+        self.enter("start", name)?;
+        self.with_container(name, |c| {
+            c.running = true;
+            c.started = true;
+            Ok(())
+        })
+    }
+
+    async fn stop(&self, name: &str, timeout: i64) -> Result<(), RuntimeError> {
+        self.enter("stop", name)?;
+        // A stopped container is left alone, which the daemon answers
+        // with 304 Not Modified
+        let stuck = self.with_container(name, |c| {
+            if !c.running {
+                return Ok(false);
+            }
+            if c.survives_kill {
+                return Ok(true);
+            }
+            c.exit_code = match c.ignores_signals || timeout <= 0 {
+                true => 137,
+                false => 0,
+            };
+            c.running = false;
+            Ok(false)
+        })?;
+        if stuck {
+            // The daemon waits for a container it cannot kill
+            std::future::pending::<()>().await;
+        }
+        Ok(())
+    }
+
+    async fn restart(&self, name: &str, timeout: isize) -> Result<(), RuntimeError> {
+        if self.is_running(name) {
+            self.stop(name, timeout as i64).await?;
+        }
+        self.enter("restart", name)?;
+        self.with_container(name, |c| {
+            c.running = true;
+            c.started = true;
+            Ok(())
+        })
+    }
+
+    async fn kill(&self, name: &str, signal: &str) -> Result<(), RuntimeError> {
+        self.enter("kill", name)?;
+        self.with_container(name, |c| {
+            if !c.running {
+                return Err(server_error(
+                    409,
+                    &format!("Container {name} is not running"),
+                ));
+            }
+            match signal {
+                _ if c.survives_kill => return Ok(()),
+                "SIGKILL" => c.exit_code = 137,
+                _ if c.ignores_signals => return Ok(()),
+                _ => c.exit_code = 0,
+            }
+            c.running = false;
+            Ok(())
+        })
+    }
+
+    async fn inspect(
+        &self,
+        name: &str,
+        size: bool,
+    ) -> Result<ContainerInspectResponse, RuntimeError> {
+        self.enter("inspect", name)?;
+        self.with_container(name, |c| {
+            let mut info = inspect_response(name, c);
+            // Like the daemon, only compute sizes when asked to
+            if size {
+                info.size_rw = Some(FAKE_WRITABLE_SIZE);
+                info.size_root_fs = Some(FAKE_IMAGE_SIZE + FAKE_WRITABLE_SIZE);
+            }
+            Ok(info)
+        })
+    }
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), RuntimeError> {
+        self.enter("remove", name)?;
+        self.with_container(name, |c| {
+            if c.running && !force {
+                return Err(server_error(
+                    409,
+                    "You cannot remove a running container. Stop the container before attempting removal or force remove",
+                ));
+            }
+            Ok(())
+        })?;
+        let mut state = self.state.lock().unwrap();
+        let name = state.container_name(name);
+        state.containers.remove(&name);
+        Ok(())
+    }
+
+    fn logs(&self, name: &str, tail: usize) -> BoxStream<'_, Result<String, RuntimeError>> {
+        let lines = self.enter("logs", name).and_then(|_| {
+            self.with_container(name, |c| {
+                Ok(c.logs[c.logs.len().saturating_sub(tail)..].to_vec())
+            })
+        });
+        match lines {
+            Ok(lines) => stream::iter(lines.into_iter().map(Ok)).boxed(),
+            Err(e) => stream::iter(vec![Err(e)]).boxed(),
+        }
+    }
+
+    async fn stats(&self, name: &str) -> Result<ContainerStats, RuntimeError> {
+        self.enter("stats", name)?;
+        self.with_container(name, |c| {
+            if !c.running {
+                return Err(server_error(
+                    409,
+                    &format!("Container {name} is not running"),
+                ));
+            }
+            let limit = c.config.host_config.as_ref().and_then(|h| h.memory);
+            Ok(ContainerStats {
+                cpu_percent: 0.0,
+                memory_usage: FAKE_MEMORY_USAGE,
+                memory_limit: limit.unwrap_or_default() as u64,
+            })
+        })
+    }
+
+    async fn list(&self, name: &str) -> Result<Vec<ContainerSummary>, RuntimeError> {
+        self.enter("list", name)?;
+        let state = self.state.lock().unwrap();
+        let mut summaries: Vec<ContainerSummary> = state
+            .containers
+            .iter()
+            .filter(|(n, _)| n.contains(name))
+            .map(|(n, c)| ContainerSummary {
+                id: Some(c.id.clone()),
+                names: Some(vec![format!("/{n}")]),
+                image: c.config.image.clone(),
+                image_id: state.image_id_of(c),
+                labels: c.config.labels.clone(),
+                state: Some(String::from(match (c.running, c.started) {
+                    (true, _) => "running",
+                    (false, true) => "exited",
+                    (false, false) => "created",
+                })),
+                ports: Some(ports(&c.config.host_config)),
+                mounts: Some(mount_points(&c.config.host_config)),
+                ..Default::default()
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.names.cmp(&b.names));
+        Ok(summaries)
+    }
+}
@@ -0,0 +1,223 @@
+use crate::result::{self, IllaError};
+use async_trait::async_trait;
+use bollard::{
+    auth::DockerCredentials,
+    container::Config,
+    models::{
+        BuildInfo, ContainerInspectResponse, ContainerSummary, CreateImageInfo, HostConfig,
+        ImageInspect, ImageSummary, Mount, MountTypeEnum, PortBinding,
+    },
+};
+use futures_util::stream::BoxStream;
+use hyper::body::Bytes;
+use serde::Serialize;
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+mod bollard_runtime;
+#[cfg(test)]
+pub(crate) mod fake;
+
+pub use bollard_runtime::{connect, BollardRuntime};
+
+/// Errors reported by the container runtime API
+pub type RuntimeError = bollard::errors::Error;
+
+// Data directory of ILLA Builder inside the container
+pub const BUILDER_DATA_DIR: &str = "/opt/illa/database";
+
+// Labels recording where the Builder image came from, so that
+// `illa update` pulls from the same place
+pub const IMAGE_LABEL: &str = "com.illasoft.illa.image";
+pub const MIRROR_LABEL: &str = "com.illasoft.illa.registry-mirror";
+// The content digest the Builder was created from
+pub const DIGEST_LABEL: &str = "com.illasoft.illa.digest";
+// Marks the never started container that leases an instance to one CLI
+// run; its value is the instance name
+pub const LEASE_LABEL: &str = "com.illasoft.illa.lease";
+
+/// The container engine behind the API socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeKind {
+    Docker,
+    Podman,
+}
+
+impl fmt::Display for RuntimeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeKind::Docker => write!(f, "Docker"),
+            RuntimeKind::Podman => write!(f, "Podman"),
+        }
+    }
+}
+
+/// The host facts that change how ILLA Builder has to be deployed
+#[derive(Debug, Clone)]
+pub struct RuntimeInfo {
+    pub kind: RuntimeKind,
+    pub version: String,
+    pub rootless: bool,
+    pub selinux: bool,
+}
+
+impl RuntimeInfo {
+    /// Qualify an image name for the runtime. Podman does not assume
+    /// Docker Hub for short names, so `illasoft/illa-builder` has to be
+    /// pulled as `docker.io/illasoft/illa-builder`.
+    pub fn image_name(&self, image: &str) -> String {
+        match self.kind {
+            RuntimeKind::Podman if !has_registry(image) => format!("docker.io/{image}"),
+            _ => image.to_owned(),
+        }
+    }
+
+    /// Fail early when a rootless runtime is asked to bind a privileged port
+    pub fn check_port(&self, port: u16) -> result::Result {
+        if !self.rootless {
+            return Ok(());
+        }
+        let start = unprivileged_port_start();
+        if port < start {
+            return Err(IllaError::InvalidArgument(format!(
+                "{} is running rootless and cannot bind port {port} (ports below {start} are privileged). \
+                 Choose a port >= {start} with --port, or lower net.ipv4.ip_unprivileged_port_start.",
+                self.kind
+            )));
+        }
+        Ok(())
+    }
+
+    /// Host configuration binding the Builder port and data directory.
+    /// On SELinux hosts the data directory is bound with a private `:Z`
+    /// label so the container is allowed to write to it.
+    pub fn builder_host_config(
+        &self,
+        port_bindings: HashMap<String, Option<Vec<PortBinding>>>,
+        data_dir: &str,
+    ) -> HostConfig {
+        let (mounts, binds) = if self.selinux {
+            (None, Some(vec![format!("{data_dir}:{BUILDER_DATA_DIR}:Z")]))
+        } else {
+            (
+                Some(vec![Mount {
+                    target: Some(BUILDER_DATA_DIR.to_string()),
+                    source: Some(data_dir.to_string()),
+                    typ: Some(MountTypeEnum::BIND),
+                    read_only: Some(false),
+                    ..Default::default()
+                }]),
+                None,
+            )
+        };
+        HostConfig {
+            port_bindings: Some(port_bindings),
+            mounts,
+            binds,
+            ..Default::default()
+        }
+    }
+}
+
+/// A point-in-time resource usage sample of a container
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ContainerStats {
+    pub cpu_percent: f64,
+    pub memory_usage: u64,
+    pub memory_limit: u64,
+}
+
+/// The container operations the subcommands rely on.
+///
+/// [`BollardRuntime`] talks to a Docker or Podman daemon; the in-memory
+/// fake used by the tests keeps containers and images in maps.
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    fn info(&self) -> &RuntimeInfo;
+
+    fn pull(
+        &self,
+        image: &str,
+        credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'_, Result<CreateImageInfo, RuntimeError>>;
+
+    /// The image as a `docker save` tarball
+    fn export_image(&self, image: &str) -> BoxStream<'_, Result<Bytes, RuntimeError>>;
+
+    /// Load the images of a `docker save` tarball
+    fn load_image(&self, archive: &Path) -> BoxStream<'_, Result<BuildInfo, RuntimeError>>;
+
+    async fn inspect_image(&self, image: &str) -> Result<ImageInspect, RuntimeError>;
+
+    /// Every image in the local store, tagged or not
+    async fn list_images(&self) -> Result<Vec<ImageSummary>, RuntimeError>;
+
+    /// Remove an image by tag or ID. Images used by a container are kept.
+    async fn remove_image(&self, image: &str) -> Result<(), RuntimeError>;
+
+    async fn create(&self, name: &str, config: Config<String>) -> Result<(), RuntimeError>;
+
+    async fn start(&self, name: &str) -> Result<(), RuntimeError>;
+
+    /// Send the container its stop signal, kill it after `timeout` seconds
+    /// and return once it exited
+    async fn stop(&self, name: &str, timeout: i64) -> Result<(), RuntimeError>;
+
+    /// Stop the container like [`ContainerRuntime::stop`] if it runs, then
+    /// start it
+    async fn restart(&self, name: &str, timeout: isize) -> Result<(), RuntimeError>;
+
+    /// Send `signal`, e.g. `SIGTERM`, to the main process of the container
+    async fn kill(&self, name: &str, signal: &str) -> Result<(), RuntimeError>;
+
+    async fn inspect(
+        &self,
+        name: &str,
+        size: bool,
+    ) -> Result<ContainerInspectResponse, RuntimeError>;
+
+    async fn remove(&self, name: &str, force: bool) -> Result<(), RuntimeError>;
+
+    /// The last `tail` lines the container wrote to stdout and stderr
+    fn logs(&self, name: &str, tail: usize) -> BoxStream<'_, Result<String, RuntimeError>>;
+
+    async fn stats(&self, name: &str) -> Result<ContainerStats, RuntimeError>;
+
+    async fn list(&self, name: &str) -> Result<Vec<ContainerSummary>, RuntimeError>;
+}
+
+/// Whether a runtime error means the container or image does not exist
+pub fn is_not_found(e: &RuntimeError) -> bool {
+    matches!(
+        e,
+        RuntimeError::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+/// Whether a runtime error means the name is already taken
+pub fn is_conflict(e: &RuntimeError) -> bool {
+    matches!(
+        e,
+        RuntimeError::DockerResponseServerError {
+            status_code: 409,
+            ..
+        }
+    )
+}
+
+fn unprivileged_port_start() -> u16 {
+    fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(1024)
+}
+
+// An image name carries a registry when its first component looks like a host
+fn has_registry(image: &str) -> bool {
+    match image.split_once('/') {
+        Some((first, _)) => first.contains('.') || first.contains(':') || first == "localhost",
+        None => false,
+    }
+}
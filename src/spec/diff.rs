@@ -0,0 +1,230 @@
+use super::{env_key, is_secret, InstanceSpec};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How an ILLA Builder should be, as recorded in the state file or
+/// declared in a spec file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DesiredSpec {
+    #[serde(flatten)]
+    pub spec: InstanceSpec,
+    /// The image digest to run; any digest of the image will do without one
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+/// A setting whose actual value differs from the desired one. A missing
+/// value is unset on that side; secret values are masked.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drift {
+    pub field: String,
+    pub desired: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// The settings of the `actual` container that differ from `desired`.
+///
+/// A secret the desired spec leaves out, as specs from the state file do,
+/// only has to be set on the container.
+pub fn diff(
+    desired: &DesiredSpec,
+    actual: &InstanceSpec,
+    actual_digest: Option<&str>,
+) -> Vec<Drift> {
+    let spec = &desired.spec;
+    let mut drift = vec![];
+
+    // A pinned image runs as `name:tag@digest`; the digest is compared on
+    // its own then
+    let image = |image: &str| match desired.digest {
+        Some(_) => image.split('@').next().unwrap_or(image).to_string(),
+        None => image.to_string(),
+    };
+    compare(
+        &mut drift,
+        String::from("image"),
+        Some(image(&spec.image)),
+        Some(image(&actual.image)),
+    );
+    if let Some(digest) = &desired.digest {
+        compare(
+            &mut drift,
+            String::from("digest"),
+            Some(digest.clone()),
+            actual_digest.map(String::from),
+        );
+    }
+
+    let desired_env = env_map(&spec.env);
+    let actual_env = env_map(&actual.env);
+    for key in desired_env
+        .keys()
+        .chain(actual_env.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        let (want, have) = (desired_env.get(key), actual_env.get(key));
+        if is_secret(key) {
+            // Masked values look the same, so the real ones are compared
+            let mask = |value: Option<&&str>| value.map(|_| String::from("********"));
+            if want.is_some() && want != have {
+                drift.push(Drift {
+                    field: format!("env.{key}"),
+                    desired: mask(want),
+                    actual: mask(have),
+                });
+            }
+            continue;
+        }
+        compare(
+            &mut drift,
+            format!("env.{key}"),
+            want.map(|v| v.to_string()),
+            have.map(|v| v.to_string()),
+        );
+    }
+
+    compare(
+        &mut drift,
+        String::from("port"),
+        Some(format!("{}:{}", spec.host_ip, spec.host_port)),
+        Some(format!("{}:{}", actual.host_ip, actual.host_port)),
+    );
+    compare(
+        &mut drift,
+        String::from("data_dir"),
+        Some(spec.data_dir.clone()),
+        Some(actual.data_dir.clone()),
+    );
+
+    let cpus = |nano: Option<i64>| nano.map(|nano| (nano as f64 / 1e9).to_string());
+    compare(
+        &mut drift,
+        String::from("limits.memory"),
+        spec.limits.memory.map(|m| m.to_string()),
+        actual.limits.memory.map(|m| m.to_string()),
+    );
+    compare(
+        &mut drift,
+        String::from("limits.cpus"),
+        cpus(spec.limits.nano_cpus),
+        cpus(actual.limits.nano_cpus),
+    );
+    compare(
+        &mut drift,
+        String::from("limits.pids"),
+        spec.limits.pids.map(|p| p.to_string()),
+        actual.limits.pids.map(|p| p.to_string()),
+    );
+
+    let labels: BTreeSet<_> = spec.labels.keys().chain(actual.labels.keys()).collect();
+    for key in labels {
+        compare(
+            &mut drift,
+            format!("labels.{key}"),
+            spec.labels.get(key).cloned(),
+            actual.labels.get(key).cloned(),
+        );
+    }
+    drift
+}
+
+fn compare(drift: &mut Vec<Drift>, field: String, desired: Option<String>, actual: Option<String>) {
+    if desired != actual {
+        drift.push(Drift {
+            field,
+            desired,
+            actual,
+        });
+    }
+}
+
+fn env_map(env: &[String]) -> BTreeMap<&str, &str> {
+    env.iter()
+        .map(|var| {
+            (
+                env_key(var),
+                var.split_once('=').map(|(_, v)| v).unwrap_or(""),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn spec() -> InstanceSpec {
+        InstanceSpec {
+            name: String::from("illa_builder"),
+            image: String::from("illasoft/illa-builder:1.2.0"),
+            env: vec![
+                String::from("ILLA_SERVER_MODE=release"),
+                String::from("POSTGRES_PASSWORD=secret"),
+            ],
+            host_ip: String::from("0.0.0.0"),
+            host_port: 8080,
+            data_dir: String::from("/data/illa"),
+            labels: HashMap::from([(String::from("maintainer"), String::from("illa"))]),
+            limits: Default::default(),
+        }
+    }
+
+    fn fields(drift: &[Drift]) -> Vec<&str> {
+        drift.iter().map(|d| d.field.as_str()).collect()
+    }
+
+    #[test]
+    fn recorded_spec_without_secrets_matches() {
+        let desired = DesiredSpec {
+            spec: spec().without_secrets(),
+            digest: Some(String::from("sha256:abc")),
+        };
+
+        assert!(diff(&desired, &spec(), Some("sha256:abc")).is_empty());
+    }
+
+    #[test]
+    fn pinned_image_is_compared_by_digest() {
+        let desired = DesiredSpec {
+            spec: spec(),
+            digest: Some(String::from("sha256:abc")),
+        };
+        let mut actual = spec();
+        actual.image = String::from("illasoft/illa-builder:1.2.0@sha256:abc");
+
+        assert!(diff(&desired, &actual, Some("sha256:abc")).is_empty());
+    }
+
+    #[test]
+    fn reports_hand_made_changes() {
+        let desired = DesiredSpec {
+            spec: spec(),
+            digest: Some(String::from("sha256:abc")),
+        };
+        let mut actual = spec();
+        actual.env[1] = String::from("POSTGRES_PASSWORD=changed");
+        actual.env.push(String::from("ILLA_DEBUG=true"));
+        actual.host_port = 9090;
+        actual.limits.memory = Some(1 << 30);
+        actual.labels.clear();
+
+        let drift = diff(&desired, &actual, Some("sha256:def"));
+
+        assert_eq!(
+            fields(&drift),
+            [
+                "digest",
+                "env.ILLA_DEBUG",
+                "env.POSTGRES_PASSWORD",
+                "port",
+                "limits.memory",
+                "labels.maintainer"
+            ]
+        );
+        let password = &drift[2];
+        assert_eq!(password.desired.as_deref(), Some("********"));
+        assert_eq!(password.actual.as_deref(), Some("********"));
+        assert_eq!(drift[3].actual.as_deref(), Some("0.0.0.0:9090"));
+    }
+}
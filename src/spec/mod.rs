@@ -0,0 +1,330 @@
+//! The typed configuration of a deployed ILLA Builder
+
+use crate::{
+    result::{IllaError, Result},
+    runtime::{RuntimeInfo, BUILDER_DATA_DIR},
+};
+use bollard::models::{ContainerInspectResponse, HostConfig, PortBinding};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+mod diff;
+pub use diff::{diff, DesiredSpec, Drift};
+
+// The port ILLA Builder listens on inside the container
+pub const BUILDER_PORT: &str = "2022/tcp";
+
+// Environment the Builder is configured by; anything else in an inspected
+// container's env comes from the image and must not be carried over
+const BUILDER_ENV_PREFIXES: [&str; 2] = ["ILLA_", "POSTGRES_"];
+
+// Set by every deploy; containers from older CLI versions may lack them
+const DEFAULT_ENV: [(&str, &str); 2] = [
+    ("ILLA_SERVER_MODE", "release"),
+    ("ILLA_DEPLOY_MODE", "self-host"),
+];
+
+// The database password is generated once and cannot be recovered
+pub const PASSWORD_ENV: &str = "POSTGRES_PASSWORD";
+
+// Environment variables with these in their name hold secrets
+const SECRET_MARKERS: [&str; 4] = ["PASSWORD", "SECRET", "TOKEN", "KEY"];
+
+/// What is needed to recreate an ILLA Builder container as it was
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceSpec {
+    pub name: String,
+    pub image: String,
+    /// `NAME=value` pairs, in the order they were set
+    pub env: Vec<String>,
+    pub host_ip: String,
+    pub host_port: u16,
+    /// The host directory bound to the Builder data directory
+    pub data_dir: String,
+    pub labels: HashMap<String, String>,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+/// Resource limits of the container; `None` means unlimited
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Bytes of memory
+    pub memory: Option<i64>,
+    /// Billionths of a CPU
+    pub nano_cpus: Option<i64>,
+    pub pids: Option<i64>,
+}
+
+impl InstanceSpec {
+    /// Read the spec of an inspected Builder container.
+    ///
+    /// Missing and extra fields are tolerated where a sensible value
+    /// exists; containers from older CLI versions are migrated to the
+    /// current layout. Anything that cannot be recovered is an
+    /// [`IllaError::InstanceConfig`] naming what is missing.
+    pub fn from_inspect(info: &ContainerInspectResponse) -> Result<InstanceSpec> {
+        let name = info
+            .name
+            .as_deref()
+            .unwrap_or("illa_builder")
+            .trim_start_matches('/')
+            .to_string();
+        let invalid = |why: String| IllaError::InstanceConfig(format!("{name}: {why}"));
+        let config = info.config.clone().unwrap_or_default();
+
+        let image = config
+            .image
+            .clone()
+            .filter(|image| !image.is_empty())
+            .ok_or_else(|| invalid(String::from("the container names no image")))?;
+
+        let mut env: Vec<String> = config
+            .env
+            .iter()
+            .flatten()
+            .filter(|var| {
+                BUILDER_ENV_PREFIXES
+                    .iter()
+                    .any(|prefix| var.starts_with(prefix))
+            })
+            .cloned()
+            .collect();
+        for (key, value) in DEFAULT_ENV.iter().rev() {
+            if !env.iter().any(|var| env_key(var) == *key) {
+                env.insert(0, format!("{key}={value}"));
+            }
+        }
+        if !env.iter().any(|var| env_key(var) == PASSWORD_ENV) {
+            return Err(invalid(format!(
+                "{PASSWORD_ENV} is not set, so the Builder database cannot be opened"
+            )));
+        }
+
+        let (host_ip, host_port) = port_binding(info).ok_or_else(|| {
+            invalid(format!(
+                "no host port is bound to the Builder port {BUILDER_PORT}"
+            ))
+        })?;
+        let data_dir = data_dir(info).ok_or_else(|| {
+            invalid(format!(
+                "no host directory is bound to the Builder data directory {BUILDER_DATA_DIR}"
+            ))
+        })?;
+
+        let host_config = info.host_config.clone().unwrap_or_default();
+        let limit = |value: Option<i64>| value.filter(|value| *value > 0);
+
+        Ok(InstanceSpec {
+            name,
+            image,
+            env,
+            host_ip,
+            host_port,
+            data_dir,
+            labels: config.labels.unwrap_or_default(),
+            limits: Limits {
+                memory: limit(host_config.memory),
+                nano_cpus: limit(host_config.nano_cpus),
+                pids: limit(host_config.pids_limit),
+            },
+        })
+    }
+
+    /// The value of an environment variable
+    pub fn env_value(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|var| env_key(var) == key)
+            .and_then(|var| var.split_once('='))
+            .map(|(_, value)| value)
+    }
+
+    /// The port bindings to create the container with
+    pub fn port_bindings(&self) -> HashMap<String, Option<Vec<PortBinding>>> {
+        HashMap::from([(
+            BUILDER_PORT.to_string(),
+            Some(vec![PortBinding {
+                host_ip: Some(self.host_ip.clone()),
+                host_port: Some(self.host_port.to_string()),
+            }]),
+        )])
+    }
+
+    /// The host configuration to create the container with
+    pub fn host_config(&self, runtime: &RuntimeInfo) -> HostConfig {
+        HostConfig {
+            memory: self.limits.memory,
+            nano_cpus: self.limits.nano_cpus,
+            pids_limit: self.limits.pids,
+            ..runtime.builder_host_config(self.port_bindings(), &self.data_dir)
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://localhost:{}", self.host_port)
+    }
+
+    /// The names of the environment variables that hold secrets
+    pub fn secret_keys(&self) -> Vec<String> {
+        self.env
+            .iter()
+            .map(|var| env_key(var))
+            .filter(|key| is_secret(key))
+            .map(String::from)
+            .collect()
+    }
+
+    /// The spec with the secret environment variables left out, to be
+    /// stored where the secrets themselves must not go
+    pub fn without_secrets(&self) -> InstanceSpec {
+        InstanceSpec {
+            env: self
+                .env
+                .iter()
+                .filter(|var| !is_secret(env_key(var)))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Whether the environment variable `key` holds a secret
+pub fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SECRET_MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+fn env_key(var: &str) -> &str {
+    var.split_once('=').map(|(key, _)| key).unwrap_or(var)
+}
+
+// The host side of the Builder port. Containers from before the port was
+// fixed may publish another one; its host port is kept and rebound to the
+// Builder port.
+fn port_binding(info: &ContainerInspectResponse) -> Option<(String, u16)> {
+    let bindings = info.host_config.as_ref()?.port_bindings.as_ref()?;
+    let binding = match bindings.get(BUILDER_PORT) {
+        Some(binding) => binding.as_ref()?.first()?,
+        None if bindings.len() == 1 => bindings.values().next()?.as_ref()?.first()?,
+        None => return None,
+    };
+    let host_port = binding.host_port.as_deref()?.parse().ok()?;
+    let host_ip = binding
+        .host_ip
+        .clone()
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or_else(|| String::from("0.0.0.0"));
+    Some((host_ip, host_port))
+}
+
+// The data directory from the mounts the daemon reports, falling back to
+// the binds and mounts the container was created with
+fn data_dir(info: &ContainerInspectResponse) -> Option<String> {
+    let mounted = info
+        .mounts
+        .iter()
+        .flatten()
+        .find(|m| m.destination.as_deref() == Some(BUILDER_DATA_DIR))
+        .and_then(|m| m.source.clone());
+    let host_config = info.host_config.as_ref();
+    let bound = || {
+        host_config?.binds.iter().flatten().find_map(|bind| {
+            let mut parts = bind.split(':');
+            let source = parts.next()?;
+            (parts.next()? == BUILDER_DATA_DIR).then(|| source.to_string())
+        })
+    };
+    let created = || {
+        host_config?
+            .mounts
+            .iter()
+            .flatten()
+            .find(|m| m.target.as_deref() == Some(BUILDER_DATA_DIR))
+            .and_then(|m| m.source.clone())
+    };
+    mounted
+        .or_else(bound)
+        .or_else(created)
+        .filter(|dir| !dir.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> ContainerInspectResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reads_current_layout() {
+        let spec =
+            InstanceSpec::from_inspect(&fixture(include_str!("fixtures/current.json"))).unwrap();
+
+        assert_eq!(spec.name, "illa_builder");
+        assert_eq!(spec.image, "illasoft/illa-builder:1.2.14");
+        // PATH and the other image env are not Builder settings
+        assert_eq!(
+            spec.env,
+            [
+                "ILLA_SERVER_MODE=release",
+                "ILLA_DEPLOY_MODE=self-host",
+                "POSTGRES_PASSWORD=6f1c0c2e-3b5f-4a8e-9d7c-1a2b3c4d5e6f",
+            ]
+        );
+        assert_eq!((spec.host_ip.as_str(), spec.host_port), ("0.0.0.0", 8080));
+        assert_eq!(spec.data_dir, "/home/illa/illa-builder");
+        assert_eq!(spec.url(), "http://localhost:8080");
+        assert_eq!(spec.labels["maintainer"], "opensource@illasoft.com");
+    }
+
+    #[test]
+    fn reads_selinux_binds_without_mounts() {
+        let spec =
+            InstanceSpec::from_inspect(&fixture(include_str!("fixtures/selinux.json"))).unwrap();
+
+        assert_eq!(spec.data_dir, "/var/lib/illa");
+        assert_eq!((spec.host_ip.as_str(), spec.host_port), ("127.0.0.1", 80));
+    }
+
+    #[test]
+    fn migrates_legacy_layout() {
+        let spec =
+            InstanceSpec::from_inspect(&fixture(include_str!("fixtures/legacy.json"))).unwrap();
+
+        // The missing modes are filled in, extra settings are kept
+        assert_eq!(spec.env_value("ILLA_SERVER_MODE"), Some("release"));
+        assert_eq!(spec.env_value("ILLA_DEPLOY_MODE"), Some("self-host"));
+        assert_eq!(spec.env_value("ILLA_SECRET_KEY"), Some("legacy"));
+        assert_eq!(spec.env_value("POSTGRES_PASSWORD"), Some("hunter2"));
+        // An old published port is rebound to the Builder port
+        assert_eq!(spec.host_port, 3000);
+        assert_eq!(
+            spec.port_bindings().keys().collect::<Vec<_>>(),
+            [BUILDER_PORT]
+        );
+        assert_eq!(spec.data_dir, "/opt/illa-data");
+    }
+
+    #[test]
+    fn reports_what_is_missing() {
+        let cases = [
+            (include_str!("fixtures/no_mount.json"), "data directory"),
+            (
+                include_str!("fixtures/no_password.json"),
+                "POSTGRES_PASSWORD",
+            ),
+            (include_str!("fixtures/no_port.json"), "host port"),
+        ];
+        for (json, missing) in cases {
+            match InstanceSpec::from_inspect(&fixture(json)) {
+                Err(IllaError::InstanceConfig(message)) => {
+                    assert!(message.contains(missing), "{message}")
+                }
+                other => panic!("expected a config error about {missing}, got {other:?}"),
+            }
+        }
+    }
+}
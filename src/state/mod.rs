@@ -0,0 +1,426 @@
+//! The local record of the ILLA Builder instances the CLI manages
+
+use crate::{
+    result::{IllaError, Result},
+    spec::InstanceSpec,
+};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The schema version written by this CLI
+pub const STATE_VERSION: u64 = 1;
+
+// Upgrades of older state files; `MIGRATIONS[n - 1]` turns a version `n`
+// file into a version `n + 1` one
+const MIGRATIONS: [fn(&mut Value); (STATE_VERSION - 1) as usize] = [];
+
+// Older deploys are dropped from an instance's history
+const MAX_HISTORY: usize = 20;
+
+/// Everything the CLI knows about its instances besides what the daemon
+/// reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    pub version: u64,
+    #[serde(default)]
+    pub instances: BTreeMap<String, InstanceState>,
+    /// Commands stopped with Ctrl-C, by instance name, until the instance
+    /// is changed again or repaired
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub interrupted: BTreeMap<String, Interruption>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            version: STATE_VERSION,
+            instances: BTreeMap::new(),
+            interrupted: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceState {
+    /// The spec the instance was last seen with, without secret values
+    pub spec: InstanceSpec,
+    /// Where the value of each secret environment variable is kept
+    #[serde(default)]
+    pub secrets: BTreeMap<String, String>,
+    /// The image digest the instance was last seen with
+    pub digest: Option<String>,
+    /// The deploys and updates of the instance, oldest first
+    #[serde(default)]
+    pub history: Vec<Deployment>,
+    /// How the instance was last stopped by `illa stop` or `illa restart`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_stop: Option<Shutdown>,
+    /// `illa deploy` created the data directory, so `illa remove --data`
+    /// may delete it
+    #[serde(default)]
+    pub data_dir_created: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deployment {
+    /// RFC 3339 time of the change
+    pub at: String,
+    /// The command that made the change
+    pub command: String,
+    pub image: String,
+    pub digest: Option<String>,
+}
+
+/// How a command stopped an instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shutdown {
+    pub command: String,
+    /// RFC 3339 time the instance was stopped
+    pub at: String,
+    /// The signal that asked the instance to exit
+    pub signal: String,
+    /// Whether the instance did not exit in time and was killed
+    pub killed: bool,
+    pub exit_code: Option<i64>,
+}
+
+/// A command on an instance that was stopped halfway
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interruption {
+    pub command: String,
+    /// RFC 3339 time of the interruption
+    pub at: String,
+    /// The step the command was stopped at
+    pub during: String,
+}
+
+impl Interruption {
+    pub fn new(command: &str, during: &str, at: SystemTime) -> Self {
+        Interruption {
+            command: command.to_string(),
+            at: humantime::format_rfc3339_seconds(at).to_string(),
+            during: during.to_string(),
+        }
+    }
+}
+
+impl InstanceState {
+    /// The entry of an instance with `spec`. Secret values stay in the
+    /// container; the entry only records that they live there.
+    pub fn new(spec: &InstanceSpec, digest: Option<String>) -> Self {
+        InstanceState {
+            spec: spec.without_secrets(),
+            secrets: spec
+                .secret_keys()
+                .into_iter()
+                .map(|key| (key, format!("container:{}", spec.name)))
+                .collect(),
+            digest,
+            history: vec![],
+            last_stop: None,
+            data_dir_created: false,
+        }
+    }
+}
+
+impl State {
+    /// Record that `command` left the instance with `spec` on `digest`
+    pub fn record(
+        &mut self,
+        spec: &InstanceSpec,
+        digest: Option<String>,
+        command: &str,
+        at: SystemTime,
+    ) {
+        let (history, last_stop, data_dir_created) = self
+            .instances
+            .remove(&spec.name)
+            .map(|instance| {
+                // A directory illa made stays its own only while it is used
+                let created = instance.data_dir_created && instance.spec.data_dir == spec.data_dir;
+                (instance.history, instance.last_stop, created)
+            })
+            .unwrap_or_default();
+        let mut instance = InstanceState::new(spec, digest.clone());
+        instance.history = history;
+        instance.last_stop = last_stop;
+        instance.data_dir_created = data_dir_created;
+        instance.history.push(Deployment {
+            at: humantime::format_rfc3339_seconds(at).to_string(),
+            command: command.to_string(),
+            image: spec.image.clone(),
+            digest,
+        });
+        let excess = instance.history.len().saturating_sub(MAX_HISTORY);
+        instance.history.drain(..excess);
+        self.instances.insert(spec.name.clone(), instance);
+    }
+}
+
+/// The state file, read and written under a lock so that concurrent CLI
+/// runs do not lose each other's changes
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    /// The state file under the user's config directory, e.g.
+    /// `~/.config/illa/state.json`
+    pub fn open() -> Result<Self> {
+        let dir = dirs::config_dir().ok_or_else(|| {
+            IllaError::State(String::from("no config directory for the current user"))
+        })?;
+        Ok(StateStore::at(dir.join("illa").join("state.json")))
+    }
+
+    pub fn at(path: impl Into<PathBuf>) -> Self {
+        StateStore { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the state; a missing file is an empty state
+    pub fn load(&self) -> Result<State> {
+        let lock = self.lock()?;
+        lock.lock_shared()?;
+        self.read()
+    }
+
+    /// Change the state with `f`, which sees the current file contents.
+    /// Nothing is written if `f` fails.
+    pub fn update<T>(&self, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        let lock = self.lock()?;
+        lock.lock_exclusive()?;
+        let mut state = self.read()?;
+        let result = f(&mut state)?;
+        self.write(&state)?;
+        Ok(result)
+    }
+
+    /// Like [`StateStore::update`], but a file that cannot be read is moved
+    /// aside to `state.json.corrupt` and `f` starts from an empty state
+    pub fn rebuild<T>(&self, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        let lock = self.lock()?;
+        lock.lock_exclusive()?;
+        let mut state = match self.parse()? {
+            Ok(state) => state,
+            Err(_) => {
+                fs::rename(&self.path, self.path.with_extension("json.corrupt"))?;
+                State::default()
+            }
+        };
+        let result = f(&mut state)?;
+        self.write(&state)?;
+        Ok(result)
+    }
+
+    // The lock is taken on a file of its own, as the state file is
+    // replaced on every write
+    fn lock(&self) -> Result<File> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.with_extension("lock"))?)
+    }
+
+    fn read(&self) -> Result<State> {
+        self.parse()?.map_err(|why| {
+            IllaError::State(format!(
+                "{} cannot be read ({why}); run `illa state repair` to rebuild it",
+                self.path.display()
+            ))
+        })
+    }
+
+    // The state, or why the file is corrupt
+    fn parse(&self) -> Result<std::result::Result<State, String>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Ok(State::default())),
+            Err(e) => return Err(e.into()),
+        };
+        let value: Value = match serde_json::from_str(&contents) {
+            Ok(value) => value,
+            Err(e) => return Ok(Err(e.to_string())),
+        };
+        let Some(value) = migrate(value)? else {
+            return Ok(Err(String::from("no schema version")));
+        };
+        Ok(serde_json::from_value(value).map_err(|e| e.to_string()))
+    }
+
+    // Write to a temporary file first so a crash never leaves half a file
+    fn write(&self, state: &State) -> Result {
+        let tmp = self.path.with_extension("json.tmp");
+        let contents = serde_json::to_string_pretty(state).map_err(anyhow::Error::from)?;
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+// Bring a state file of an older schema up to date; `None` without a
+// schema version. Files of a newer CLI are refused rather than rewritten
+// without the fields they added, even by a repair.
+fn migrate(mut value: Value) -> Result<Option<Value>> {
+    let Some(version) = value.get("version").and_then(Value::as_u64) else {
+        return Ok(None);
+    };
+    if version > STATE_VERSION {
+        return Err(IllaError::State(format!(
+            "the state file has schema version {version}, but this illa only knows up to \
+             {STATE_VERSION}; upgrade illa"
+        )));
+    }
+    for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        migration(&mut value);
+    }
+    value["version"] = Value::from(STATE_VERSION);
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, thread, time::Duration};
+    use uuid::Uuid;
+
+    fn temp_store() -> StateStore {
+        StateStore::at(
+            env::temp_dir()
+                .join(format!("illa-state-{}", Uuid::new_v4()))
+                .join("state.json"),
+        )
+    }
+
+    fn spec(image: &str) -> InstanceSpec {
+        InstanceSpec {
+            name: String::from("illa_builder"),
+            image: image.to_string(),
+            env: vec![
+                String::from("ILLA_SERVER_MODE=release"),
+                String::from("POSTGRES_PASSWORD=secret"),
+            ],
+            host_ip: String::from("0.0.0.0"),
+            host_port: 8080,
+            data_dir: String::from("/data/illa"),
+            labels: Default::default(),
+            limits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn records_history_without_secret_values() {
+        let store = temp_store();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        store
+            .update(|state| {
+                state.record(&spec("illasoft/illa-builder:1.0.0"), None, "deploy", at);
+                state.record(
+                    &spec("illasoft/illa-builder:1.1.0"),
+                    Some(String::from("sha256:abc")),
+                    "update",
+                    at,
+                );
+                Ok(())
+            })
+            .unwrap();
+
+        let contents = fs::read_to_string(store.path()).unwrap();
+        assert!(!contents.contains("secret\""), "{contents}");
+        let instance = &store.load().unwrap().instances["illa_builder"];
+        assert_eq!(instance.spec.env, ["ILLA_SERVER_MODE=release"]);
+        assert_eq!(
+            instance.secrets["POSTGRES_PASSWORD"],
+            "container:illa_builder"
+        );
+        assert_eq!(instance.digest.as_deref(), Some("sha256:abc"));
+        assert_eq!(instance.history.len(), 2);
+        assert_eq!(instance.history[0].at, "2023-11-14T22:13:20Z");
+        assert_eq!(instance.history[1].command, "update");
+    }
+
+    #[test]
+    fn created_data_dir_is_kept_only_while_in_use() {
+        let mut state = State::default();
+        let at = SystemTime::now();
+        let deployed = spec("illasoft/illa-builder:1.0.0");
+        state.record(&deployed, None, "deploy", at);
+        state
+            .instances
+            .get_mut("illa_builder")
+            .unwrap()
+            .data_dir_created = true;
+
+        state.record(&spec("illasoft/illa-builder:1.1.0"), None, "update", at);
+        assert!(state.instances["illa_builder"].data_dir_created);
+
+        let moved = InstanceSpec {
+            data_dir: String::from("/srv/illa"),
+            ..deployed
+        };
+        state.record(&moved, None, "deploy", at);
+        assert!(!state.instances["illa_builder"].data_dir_created);
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let store = temp_store();
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..5 {
+                        store
+                            .update(|state| {
+                                let spec = spec("illasoft/illa-builder:1.0.0");
+                                state.record(&spec, None, "deploy", SystemTime::now());
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let state = store.load().unwrap();
+        assert_eq!(state.instances["illa_builder"].history.len(), 20);
+    }
+
+    #[test]
+    fn refuses_newer_and_rebuilds_corrupt_files() {
+        let store = temp_store();
+        fs::create_dir_all(store.path().parent().unwrap()).unwrap();
+
+        fs::write(store.path(), r#"{"version": 99, "instances": {}}"#).unwrap();
+        assert!(matches!(store.load(), Err(IllaError::State(m)) if m.contains("upgrade illa")));
+        assert!(store.rebuild(|_| Ok(())).is_err());
+
+        fs::write(store.path(), "{ not json").unwrap();
+        assert!(
+            matches!(store.load(), Err(IllaError::State(m)) if m.contains("illa state repair"))
+        );
+        store.rebuild(|_| Ok(())).unwrap();
+        assert_eq!(store.load().unwrap(), State::default());
+        assert!(store.path().with_extension("json.corrupt").exists());
+    }
+}